@@ -1,3 +1,310 @@
-pub fn add() {
-    
-}
\ No newline at end of file
+//! # api_routes
+//! Route registration conventions shared by our services.
+//! ## Pieces
+//! - [`RouteModule`] : one group of routes with its tag, prefix and state
+//! - [`RouteRegistry`] : mounts modules and collects their OpenAPI tags
+//! - [`ApiVersion`] : a `/v1`-like prefix modules can be mounted under
+//! - [`RouterExt`] : small helpers on `ApiRouter`
+//! - [`ApiResponse`] : the JSON envelope every handler answers with
+
+use aide::{
+    axum::ApiRouter,
+    openapi::{OpenApi, Tag},
+    transform::TransformPathItem,
+};
+use axum::{
+    extract::{FromRef, OriginalUri},
+    http::{HeaderName, HeaderValue},
+    middleware::map_response,
+    response::{Redirect, Response},
+    routing::{MethodRouter, any},
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+pub trait RouterExt {
+    fn with_prefix(self, prefix: &'static str) -> Self;
+    fn with_tag(self, tag_name: &'static str) -> Self;
+}
+impl RouterExt for ApiRouter {
+    fn with_prefix(self, prefix: &'static str) -> Self {
+        ApiRouter::new().nest_api_service(prefix, self)
+    }
+    fn with_tag(self, tag_name: &'static str) -> Self {
+        self.with_path_items(|op| op.tag(tag_name))
+    }
+}
+
+/// # RouteModule
+/// A group of routes sharing one OpenAPI tag and URL prefix.
+/// `State` only needs what the handlers use,
+/// the registry derives it from the application state with [`FromRef`].
+/// ## How to use
+/// ```
+/// use aide::axum::{ApiRouter, routing::get};
+/// use api_routes::{RouteModule, RouteRegistry};
+///
+/// struct Hello;
+/// impl RouteModule for Hello {
+///     type State = ();
+///     const NAME: &'static str = "hello";
+///     const DESCRIPTION: &'static str = "Greetings";
+///     const PREFIX: Option<&'static str> = Some("/hello");
+///
+///     fn router() -> ApiRouter<Self::State> {
+///         ApiRouter::new().api_route("/", get(|| async { "hi" }))
+///     }
+/// }
+///
+/// let (app, api) = RouteRegistry::new(()).mount::<Hello>().finish();
+/// assert_eq!(api.tags[0].name, "hello");
+/// ```
+pub trait RouteModule {
+    /// State the handlers of this module extract.
+    type State: Clone + Send + Sync + 'static;
+    /// OpenAPI tag name, applied to every route of the module.
+    const NAME: &'static str;
+    /// OpenAPI tag description.
+    const DESCRIPTION: &'static str;
+    /// Path every route of the module is nested under.
+    const PREFIX: Option<&'static str> = None;
+
+    /// Routes of the module, relative to [`RouteModule::PREFIX`].
+    fn router() -> ApiRouter<Self::State>;
+
+    fn tag() -> Tag {
+        Tag {
+            name: Self::NAME.to_string(),
+            description: Some(Self::DESCRIPTION.to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// Router with state, tag and prefix applied, ready to be merged.
+    fn build(state: Self::State) -> ApiRouter {
+        Self::build_at(state, "")
+    }
+    /// [`RouteModule::build`], nested under `base` too, e.g. `/v1`.
+    fn build_at(state: Self::State, base: &str) -> ApiRouter {
+        let router = Self::router().with_state(state).with_tag(Self::NAME);
+        match format!("{}{}", base, Self::PREFIX.unwrap_or_default()).as_str() {
+            "" => router,
+            prefix => ApiRouter::new().nest_api_service(prefix, router),
+        }
+    }
+}
+
+/// # ApiVersion
+/// URL prefix of one version of the API, see [`RouteRegistry::version`].
+/// ## How to use
+/// ```
+/// use api_routes::ApiVersion;
+///
+/// const V1: ApiVersion = ApiVersion::new("/v1")
+///     .deprecated("@1792368000", "Mon, 19 Apr 2027 00:00:00 GMT")
+///     .legacy();
+/// assert_eq!(V1.name(), "v1");
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct ApiVersion {
+    pub prefix: &'static str,
+    /// `Deprecation` header (RFC 9745), e.g. `@1792368000`, `None` while supported
+    pub deprecation: Option<&'static str>,
+    /// `Sunset` header (RFC 8594), an HTTP-date
+    pub sunset: Option<&'static str>,
+    /// Unversioned paths of the modules of this version redirect to it
+    pub legacy: bool,
+}
+impl ApiVersion {
+    pub const fn new(prefix: &'static str) -> Self {
+        Self {
+            prefix,
+            deprecation: None,
+            sunset: None,
+            legacy: false,
+        }
+    }
+    pub const fn deprecated(mut self, deprecation: &'static str, sunset: &'static str) -> Self {
+        self.deprecation = Some(deprecation);
+        self.sunset = Some(sunset);
+        self
+    }
+    pub const fn legacy(mut self) -> Self {
+        self.legacy = true;
+        self
+    }
+    /// `v1` for `/v1`.
+    pub fn name(&self) -> &'static str {
+        self.prefix.trim_start_matches('/')
+    }
+
+    /// Adds the deprecation headers to every response of `router`,
+    /// and marks its operations deprecated in OpenAPI.
+    fn apply(&self, router: ApiRouter) -> ApiRouter {
+        let headers: Vec<(HeaderName, HeaderValue)> =
+            [("deprecation", self.deprecation), ("sunset", self.sunset)]
+                .into_iter()
+                .filter_map(|(name, value)| {
+                    Some((
+                        HeaderName::from_static(name),
+                        HeaderValue::from_static(value?),
+                    ))
+                })
+                .collect();
+        if headers.is_empty() {
+            return router;
+        }
+        router
+            .with_path_items(deprecate)
+            .layer(map_response(move |mut res: Response| {
+                let headers = headers.clone();
+                async move {
+                    res.headers_mut().extend(headers);
+                    res
+                }
+            }))
+    }
+}
+
+fn deprecate(mut item: TransformPathItem<'_>) -> TransformPathItem<'_> {
+    let path = item.inner_mut();
+    let operations = [
+        &mut path.get,
+        &mut path.put,
+        &mut path.post,
+        &mut path.delete,
+        &mut path.options,
+        &mut path.head,
+        &mut path.patch,
+        &mut path.trace,
+    ];
+    for operation in operations.into_iter().flatten() {
+        operation.deprecated = true;
+    }
+    item
+}
+
+/// Redirects every request to the same path and query under `prefix`, keeping the method.
+fn redirect_to(prefix: &'static str) -> MethodRouter {
+    any(move |OriginalUri(uri): OriginalUri| async move {
+        let path = uri.path_and_query().map_or(uri.path(), |pq| pq.as_str());
+        Redirect::permanent(&format!("{}{}", prefix, path))
+    })
+}
+
+/// # RouteRegistry
+/// Merges [`RouteModule`]s into one router,
+/// keeping the OpenAPI tag list in sync with what is mounted.
+/// `S` is the application state every module state is taken from.
+pub struct RouteRegistry<S = ()> {
+    state: S,
+    router: ApiRouter,
+    tags: Vec<Tag>,
+    enabled: Box<dyn Fn(&str) -> bool + Send + Sync>,
+    /// Version being mounted, with its modules so far
+    version: Option<(ApiVersion, ApiRouter)>,
+}
+impl<S> RouteRegistry<S> {
+    pub fn new(state: S) -> Self {
+        Self {
+            state,
+            router: ApiRouter::new(),
+            tags: Vec::new(),
+            enabled: Box::new(|_| true),
+            version: None,
+        }
+    }
+    /// Modules mounted from now on go under `version.prefix`, until the next version.
+    /// A module can be mounted in several versions.
+    pub fn version(mut self, version: ApiVersion) -> Self {
+        self.end_version();
+        self.version = Some((version, ApiRouter::new()));
+        self
+    }
+    fn end_version(&mut self) {
+        if let Some((version, routes)) = self.version.take() {
+            let router = std::mem::replace(&mut self.router, ApiRouter::new());
+            self.router = router.merge(version.apply(routes));
+        }
+    }
+    /// Modules for which `enabled` answers `false`, given their [`RouteModule::NAME`],
+    /// are skipped by [`RouteRegistry::mount`]. Every module is mounted by default.
+    pub fn filter(mut self, enabled: impl Fn(&str) -> bool + Send + Sync + 'static) -> Self {
+        self.enabled = Box::new(enabled);
+        self
+    }
+    pub fn mount<M>(mut self) -> Self
+    where
+        M: RouteModule,
+        M::State: FromRef<S>,
+    {
+        if !(self.enabled)(M::NAME) {
+            return self;
+        }
+        let state = M::State::from_ref(&self.state);
+        match self.version.take() {
+            Some((version, mut routes)) => {
+                if let (true, Some(prefix)) = (version.legacy, M::PREFIX) {
+                    routes = routes.nest_service(prefix, redirect_to(version.prefix));
+                }
+                let routes = routes.merge(M::build_at(state, version.prefix));
+                self.version = Some((version, routes));
+            }
+            None => self.router = self.router.merge(M::build(state)),
+        }
+        if !self.tags.iter().any(|tag| tag.name == M::NAME) {
+            self.tags.push(M::tag());
+        }
+        self
+    }
+    /// Router with every mounted module, and an OpenAPI document holding their tags.
+    pub fn finish(mut self) -> (ApiRouter, OpenApi) {
+        self.end_version();
+        (
+            self.router,
+            OpenApi {
+                tags: self.tags,
+                ..Default::default()
+            },
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Default, Clone)]
+pub struct ApiResponse<T>
+where
+    T: JsonSchema,
+{
+    pub code: isize,
+    pub resp: String,
+    pub data: T,
+}
+impl<T: JsonSchema> ApiResponse<T> {
+    pub fn code(mut self, code: isize) -> Self {
+        self.code = code;
+        self
+    }
+    pub fn resp(mut self, resp: String) -> Self {
+        self.resp = resp;
+        self
+    }
+    pub fn data(mut self, data: T) -> Self {
+        self.data = data;
+        self
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Default, Clone)]
+/// # Empty
+/// Describes `null` state for compiler to understand.
+/// ## How to use
+/// ```
+/// use api_routes::{ApiResponse, Empty};
+///
+/// let response = ApiResponse::<Empty> {
+///     code: 0,
+///     resp: "ok".to_string(),
+///     data: Empty,
+/// };
+/// ```
+pub struct Empty;
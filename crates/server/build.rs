@@ -0,0 +1,49 @@
+//! Compiles `proto/api.proto` for the gRPC service, see `src/grpc`.
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    #[cfg(feature = "grpc")]
+    {
+        use std::path::PathBuf;
+
+        let out_dir = PathBuf::from(std::env::var("OUT_DIR")?);
+        // SAFETY: build scripts are single-threaded
+        unsafe { std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?) };
+        // Checked like the REST bodies, see `src/validation.rs`
+        const USER_NAME: &str = "#[validate(length(min = 1, max = crate::validation::MAX_USER_NAME_LEN), \
+             regex(path = *crate::validation::USER_NAME))]";
+        const TITLE: &str = "#[validate(length(min = 1, max = crate::validation::MAX_TITLE_LEN))]";
+        const CONTENT: &str = "#[validate(length(max = crate::validation::MAX_CONTENT_LEN))]";
+        let validated = [
+            "api.v1.FiboRequest",
+            "api.v1.HanoiRequest",
+            "api.v1.CreateUserRequest",
+            "api.v1.RenameUserRequest",
+            "api.v1.CreatePostRequest",
+            "api.v1.EditPostRequest",
+        ];
+        let mut builder = tonic_prost_build::configure();
+        for message in validated {
+            builder = builder.message_attribute(message, "#[derive(validator::Validate)]");
+        }
+        builder
+            .field_attribute("api.v1.FiboRequest.n", "#[validate(range(max = 5_000))]")
+            .field_attribute(
+                "api.v1.HanoiRequest.n",
+                "#[validate(range(min = 1, max = crate::grpc::calc::MAX_HANOI_STREAMED))]",
+            )
+            .field_attribute("api.v1.CreateUserRequest.name", USER_NAME)
+            .field_attribute("api.v1.RenameUserRequest.name", USER_NAME)
+            .field_attribute("api.v1.CreatePostRequest.title", TITLE)
+            .field_attribute("api.v1.CreatePostRequest.content", CONTENT)
+            .field_attribute("api.v1.EditPostRequest.title", TITLE)
+            .field_attribute("api.v1.EditPostRequest.content", CONTENT)
+            .build_client(false)
+            .file_descriptor_set_path(out_dir.join("api_descriptor.bin"))
+            .compile_protos(
+                &[PathBuf::from("proto/api.proto")],
+                &[PathBuf::from("proto"), protoc_bin_vendored::include_path()?],
+            )?;
+    }
+    println!("cargo:rerun-if-changed=proto");
+    Ok(())
+}
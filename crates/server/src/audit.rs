@@ -0,0 +1,74 @@
+//! # audit
+//! Who is behind the current request, recorded with every repository write.
+//! The middleware [`scope`] sets an [`AuditContext`] for the whole request,
+//! repositories read it back with [`current`].
+
+use axum::{
+    extract::{Request, State},
+    http::HeaderValue,
+    middleware::Next,
+    response::Response,
+};
+
+use crate::{auth, config::Config, prelude::*};
+
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+#[derive(Clone, Debug)]
+pub struct AuditContext {
+    pub actor: String,
+    pub request_id: Option<String>,
+}
+impl AuditContext {
+    /// Context of work not started by a request, e.g. `system:purge`.
+    pub fn system(job: &str) -> Self {
+        Self {
+            actor: format!("system:{}", job),
+            request_id: None,
+        }
+    }
+}
+
+tokio::task_local! {
+    static AUDIT: AuditContext;
+}
+
+/// Context of the running task, `system` outside of any scope.
+pub fn current() -> AuditContext {
+    AUDIT
+        .try_with(Clone::clone)
+        .unwrap_or_else(|_| AuditContext {
+            actor: "system".to_string(),
+            request_id: None,
+        })
+}
+
+/// Runs `f` with `ctx` as the audit context.
+pub async fn with_context<F: Future>(ctx: AuditContext, f: F) -> F::Output {
+    AUDIT.scope(ctx, f).await
+}
+
+/// Middleware setting the audit context of a request.
+/// Reuses `X-Request-Id` when the client sent one, and echoes it in the response.
+pub async fn scope(State(config): State<Arc<Config>>, req: Request, next: Next) -> Response {
+    let request_id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let actor = if auth::is_admin(&config, req.headers()) {
+        "admin"
+    } else {
+        "anonymous"
+    };
+    let ctx = AuditContext {
+        actor: actor.to_string(),
+        request_id: Some(request_id.clone()),
+    };
+    let mut res = with_context(ctx, next.run(req)).await;
+    if let Ok(v) = HeaderValue::from_str(&request_id) {
+        res.headers_mut().insert(REQUEST_ID_HEADER, v);
+    }
+    res
+}
@@ -0,0 +1,63 @@
+//! # auth
+//! Extractors guarding protected routes.
+
+#![cfg_attr(not(feature = "db"), allow(dead_code))]
+
+use aide::{
+    OperationInput,
+    generate::GenContext,
+    openapi::{Operation, SecurityRequirement},
+};
+use axum::{
+    extract::{FromRef, FromRequestParts},
+    http::{HeaderMap, request::Parts},
+};
+
+use subtle::ConstantTimeEq;
+
+use crate::{config::Config, error::AppError, prelude::*};
+
+pub const AUTH_HEADER: &str = "X-Auth-Key";
+
+/// # AdminKey
+/// Rejects the request with 401 unless `X-Auth-Key` matches `ADMIN_API_KEY`.
+/// ## How to use
+/// ```
+/// pub async fn purge(_: AdminKey, ...) -> Result<..., AppError>
+/// ```
+pub struct AdminKey;
+
+impl<S> FromRequestParts<S> for AdminKey
+where
+    Arc<Config>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let config = Arc::<Config>::from_ref(state);
+        if is_admin(&config, &parts.headers) {
+            Ok(AdminKey)
+        } else {
+            Err(AppError::Unauthorized)
+        }
+    }
+}
+
+/// `true` when `X-Auth-Key` matches `ADMIN_API_KEY`, compared in constant time.
+pub fn is_admin(config: &Config, headers: &HeaderMap) -> bool {
+    let given = headers.get(AUTH_HEADER).and_then(|v| v.to_str().ok());
+    match (config.admin_key.as_deref(), given) {
+        (Some(expected), Some(given)) => expected.as_bytes().ct_eq(given.as_bytes()).into(),
+        _ => false,
+    }
+}
+
+impl OperationInput for AdminKey {
+    fn operation_input(_ctx: &mut GenContext, operation: &mut Operation) {
+        operation.security.push(SecurityRequirement::from_iter([(
+            "ApiKey".to_string(),
+            Vec::new(),
+        )]));
+    }
+}
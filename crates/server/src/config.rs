@@ -0,0 +1,340 @@
+//! # config
+//! Runtime settings loaded from environment variables (and `.env`).
+
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
+
+use anyhow::{Context, Result};
+
+const DEFAULT_LISTEN_ADDR: &str = "0.0.0.0:8080";
+const DEFAULT_UNIX_SOCKET_MODE: u32 = 0o660;
+const DEFAULT_SHUTDOWN_DRAIN_SECS: u64 = 5;
+const DEFAULT_SHUTDOWN_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_SOFT_DELETE_RETENTION_DAYS: u64 = 30;
+const DEFAULT_PURGE_INTERVAL_SECS: u64 = 60 * 60;
+const DEFAULT_PUBLISH_INTERVAL_SECS: u64 = 30;
+const DEFAULT_IDEMPOTENCY_TTL_HOURS: u64 = 24;
+const DEFAULT_OUTBOX_INTERVAL_SECS: u64 = 5;
+const DEFAULT_OUTBOX_RETENTION_DAYS: u64 = 7;
+/// A century, far below what dates can hold.
+const MAX_RETENTION_DAYS: u64 = 36_500;
+const DEFAULT_OUTBOX_SINKS: &str = "webhooks,events";
+const DEFAULT_WEBHOOK_INTERVAL_SECS: u64 = 5;
+const DEFAULT_WEBHOOK_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_WEBHOOK_MAX_ATTEMPTS: u64 = 8;
+const DEFAULT_WEBHOOK_RETRY_BASE_SECS: u64 = 30;
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub database_url: String,
+    pub listen: ListenConfig,
+    pub shutdown: ShutdownConfig,
+    pub routes: RoutesConfig,
+    pub soft_delete: SoftDeleteConfig,
+    pub publish: PublishConfig,
+    pub idempotency: IdempotencyConfig,
+    pub outbox: OutboxConfig,
+    pub webhooks: WebhookConfig,
+    /// Key expected in the `X-Auth-Key` header of admin routes (`ADMIN_API_KEY`).
+    /// Admin routes reject every request when unset.
+    pub admin_key: Option<String>,
+}
+impl Config {
+    /// Reads every setting from the environment.
+    /// Call after `dotenv::dotenv()` so `.env` values are visible.
+    pub fn from_env() -> Result<Self> {
+        Ok(Self {
+            database_url: std::env::var("DATABASE_URL")
+                .context("Fail to load DATABASE_URL from .env")?,
+            listen: ListenConfig::from_env()?,
+            shutdown: ShutdownConfig::from_env()?,
+            routes: RoutesConfig::from_env(),
+            soft_delete: SoftDeleteConfig::from_env()?,
+            publish: PublishConfig::from_env()?,
+            idempotency: IdempotencyConfig::from_env()?,
+            outbox: OutboxConfig::from_env()?,
+            webhooks: WebhookConfig::from_env()?,
+            admin_key: std::env::var("ADMIN_API_KEY")
+                .ok()
+                .filter(|key| !key.is_empty()),
+        })
+    }
+}
+
+/// # ListenConfig
+/// Where the server accepts connections.
+/// ## Environment variables
+/// - `LISTEN_ADDR` : TCP address, defaults to `0.0.0.0:8080`
+/// - `UNIX_SOCKET_PATH` : listen on a Unix domain socket instead of TCP
+/// - `UNIX_SOCKET_MODE` : octal permissions of the socket file, defaults to `660`
+///
+/// A socket passed by systemd (`LISTEN_FDS`) always takes precedence.
+#[derive(Clone, Debug)]
+pub struct ListenConfig {
+    pub tcp_addr: SocketAddr,
+    pub unix_socket: Option<UnixSocketConfig>,
+}
+#[derive(Clone, Debug)]
+pub struct UnixSocketConfig {
+    pub path: PathBuf,
+    pub mode: u32,
+}
+impl ListenConfig {
+    fn from_env() -> Result<Self> {
+        let tcp_addr = env_or("LISTEN_ADDR", DEFAULT_LISTEN_ADDR)
+            .parse()
+            .context("LISTEN_ADDR is not a valid socket address")?;
+        let unix_socket = match std::env::var("UNIX_SOCKET_PATH") {
+            Ok(path) if !path.is_empty() => Some(UnixSocketConfig {
+                path: PathBuf::from(path),
+                mode: match std::env::var("UNIX_SOCKET_MODE") {
+                    Ok(v) => u32::from_str_radix(v.trim_start_matches("0o"), 8)
+                        .context("UNIX_SOCKET_MODE must be an octal number (e.g. 660)")?,
+                    Err(..) => DEFAULT_UNIX_SOCKET_MODE,
+                },
+            }),
+            _ => None,
+        };
+        Ok(Self {
+            tcp_addr,
+            unix_socket,
+        })
+    }
+}
+
+/// # ShutdownConfig
+/// How long a graceful shutdown may take.
+/// ## Environment variables
+/// - `SHUTDOWN_DRAIN_SECS` : time between failing readiness and closing the listener, defaults to `5`
+/// - `SHUTDOWN_TIMEOUT_SECS` : time allowed for in-flight requests and jobs before a forced exit, defaults to `30`
+#[derive(Clone, Debug)]
+pub struct ShutdownConfig {
+    pub drain: Duration,
+    pub timeout: Duration,
+}
+impl ShutdownConfig {
+    fn from_env() -> Result<Self> {
+        Ok(Self {
+            drain: env_secs("SHUTDOWN_DRAIN_SECS", DEFAULT_SHUTDOWN_DRAIN_SECS)?,
+            timeout: env_secs("SHUTDOWN_TIMEOUT_SECS", DEFAULT_SHUTDOWN_TIMEOUT_SECS)?,
+        })
+    }
+}
+
+/// Module holding `/is_alive` and `/is_ready`, which orchestrators keep probing.
+const ALWAYS_ENABLED: &str = "Index";
+
+/// # RoutesConfig
+/// Route modules switched off at runtime.
+/// ## Environment variables
+/// - `ROUTES_DISABLED` : comma separated module tag names (e.g. `calc,database`),
+///   `docs` turns off the documentation pages and `grpc` the gRPC services.
+///   `Index` always stays on, it answers the liveness and readiness probes
+#[derive(Clone, Debug, Default)]
+pub struct RoutesConfig {
+    pub disabled: Vec<String>,
+}
+impl RoutesConfig {
+    fn from_env() -> Self {
+        Self {
+            disabled: env_or("ROUTES_DISABLED", "")
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(str::to_string)
+                .collect(),
+        }
+    }
+    pub fn is_enabled(&self, name: &str) -> bool {
+        name.eq_ignore_ascii_case(ALWAYS_ENABLED)
+            || !self.disabled.iter().any(|d| d.eq_ignore_ascii_case(name))
+    }
+}
+
+/// # SoftDeleteConfig
+/// How long soft-deleted rows are kept.
+/// ## Environment variables
+/// - `SOFT_DELETE_RETENTION_DAYS` : age after which deleted rows are purged, defaults to `30`
+/// - `PURGE_INTERVAL_SECS` : how often the purge runs, defaults to `3600`
+#[derive(Clone, Debug)]
+pub struct SoftDeleteConfig {
+    pub retention: Duration,
+    pub purge_interval: Duration,
+}
+impl SoftDeleteConfig {
+    fn from_env() -> Result<Self> {
+        Ok(Self {
+            retention: env_days(
+                "SOFT_DELETE_RETENTION_DAYS",
+                DEFAULT_SOFT_DELETE_RETENTION_DAYS,
+            )?,
+            purge_interval: env_interval("PURGE_INTERVAL_SECS", DEFAULT_PURGE_INTERVAL_SECS)?,
+        })
+    }
+}
+
+/// # PublishConfig
+/// How often scheduled posts are checked.
+/// ## Environment variables
+/// - `PUBLISH_INTERVAL_SECS` : time between two checks for due posts, defaults to `30`
+#[derive(Clone, Debug)]
+pub struct PublishConfig {
+    pub interval: Duration,
+}
+impl PublishConfig {
+    fn from_env() -> Result<Self> {
+        Ok(Self {
+            interval: env_interval("PUBLISH_INTERVAL_SECS", DEFAULT_PUBLISH_INTERVAL_SECS)?,
+        })
+    }
+}
+
+/// # IdempotencyConfig
+/// How long responses to `Idempotency-Key` requests are replayed.
+/// ## Environment variables
+/// - `IDEMPOTENCY_TTL_HOURS` : time a key answers with its first response, defaults to `24`
+#[derive(Clone, Debug)]
+pub struct IdempotencyConfig {
+    pub ttl: Duration,
+}
+impl IdempotencyConfig {
+    fn from_env() -> Result<Self> {
+        let hours = env_u64("IDEMPOTENCY_TTL_HOURS", DEFAULT_IDEMPOTENCY_TTL_HOURS)?;
+        Ok(Self {
+            ttl: scaled("IDEMPOTENCY_TTL_HOURS", hours, 60 * 60)?,
+        })
+    }
+}
+
+/// # OutboxConfig
+/// Where events of writes are relayed, see `tasks/outbox.rs`.
+/// ## Environment variables
+/// - `OUTBOX_SINKS` : comma separated sinks among `webhooks`, `events` (live events) and `log`,
+///   defaults to `webhooks,events`
+/// - `OUTBOX_INTERVAL_SECS` : time between two checks for events when no notification comes, defaults to `5`
+/// - `OUTBOX_RETENTION_DAYS` : age after which relayed events are removed, defaults to `7`
+#[derive(Clone, Debug)]
+pub struct OutboxConfig {
+    pub sinks: Vec<OutboxSink>,
+    pub interval: Duration,
+    pub retention: Duration,
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutboxSink {
+    /// Queues deliveries for subscribed webhooks
+    Webhooks,
+    /// Notifies `row_changes`, fed to the live events of every instance
+    Events,
+    /// Logs every event at info level
+    Log,
+}
+impl OutboxConfig {
+    fn from_env() -> Result<Self> {
+        let sinks = env_or("OUTBOX_SINKS", DEFAULT_OUTBOX_SINKS)
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| match name {
+                "webhooks" => Ok(OutboxSink::Webhooks),
+                "events" => Ok(OutboxSink::Events),
+                "log" => Ok(OutboxSink::Log),
+                _ => anyhow::bail!(
+                    "OUTBOX_SINKS : unknown sink {}, expected webhooks, events or log",
+                    name
+                ),
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            sinks,
+            interval: env_interval("OUTBOX_INTERVAL_SECS", DEFAULT_OUTBOX_INTERVAL_SECS)?,
+            retention: env_days("OUTBOX_RETENTION_DAYS", DEFAULT_OUTBOX_RETENTION_DAYS)?,
+        })
+    }
+}
+
+/// # WebhookConfig
+/// How webhook deliveries are sent and retried.
+/// ## Environment variables
+/// - `WEBHOOK_INTERVAL_SECS` : time between two checks for due deliveries, defaults to `5`
+/// - `WEBHOOK_TIMEOUT_SECS` : time a receiver has to answer, defaults to `10`
+/// - `WEBHOOK_MAX_ATTEMPTS` : attempts before a delivery is dead, defaults to `8`
+/// - `WEBHOOK_RETRY_BASE_SECS` : wait after the first failure, doubled after each next one, defaults to `30`
+#[derive(Clone, Debug)]
+#[cfg_attr(not(feature = "webhooks"), allow(dead_code))]
+pub struct WebhookConfig {
+    pub interval: Duration,
+    pub timeout: Duration,
+    pub max_attempts: u32,
+    pub retry_base: Duration,
+}
+impl WebhookConfig {
+    fn from_env() -> Result<Self> {
+        let max_attempts = env_u64("WEBHOOK_MAX_ATTEMPTS", DEFAULT_WEBHOOK_MAX_ATTEMPTS)?;
+        Ok(Self {
+            interval: env_interval("WEBHOOK_INTERVAL_SECS", DEFAULT_WEBHOOK_INTERVAL_SECS)?,
+            timeout: env_secs("WEBHOOK_TIMEOUT_SECS", DEFAULT_WEBHOOK_TIMEOUT_SECS)?,
+            max_attempts: max_attempts
+                .clamp(1, 100)
+                .try_into()
+                .context("WEBHOOK_MAX_ATTEMPTS is out of range")?,
+            retry_base: env_secs("WEBHOOK_RETRY_BASE_SECS", DEFAULT_WEBHOOK_RETRY_BASE_SECS)?,
+        })
+    }
+}
+
+fn env_secs(key: &str, default: u64) -> Result<Duration> {
+    env_u64(key, default).map(Duration::from_secs)
+}
+
+/// [`env_secs`] for the period of a task, which `tokio::time::interval` needs above zero.
+fn env_interval(key: &str, default: u64) -> Result<Duration> {
+    let interval = env_secs(key, default)?;
+    anyhow::ensure!(!interval.is_zero(), "{} must be at least 1", key);
+    Ok(interval)
+}
+
+/// Retention in days, kept within [`MAX_RETENTION_DAYS`] so it can be subtracted from now.
+fn env_days(key: &str, default: u64) -> Result<Duration> {
+    let days = env_u64(key, default)?;
+    anyhow::ensure!(
+        days <= MAX_RETENTION_DAYS,
+        "{} must be at most {}",
+        key,
+        MAX_RETENTION_DAYS
+    );
+    scaled(key, days, 24 * 60 * 60)
+}
+
+/// `count` units of `unit_secs` seconds, read from `key`.
+fn scaled(key: &str, count: u64, unit_secs: u64) -> Result<Duration> {
+    count
+        .checked_mul(unit_secs)
+        .map(Duration::from_secs)
+        .with_context(|| format!("{} is too large", key))
+}
+
+fn env_u64(key: &str, default: u64) -> Result<u64> {
+    match std::env::var(key) {
+        Ok(v) => v
+            .parse()
+            .with_context(|| format!("{} must be a positive number", key)),
+        Err(..) => Ok(default),
+    }
+}
+
+fn env_or(key: &str, default: &str) -> String {
+    std::env::var(key).unwrap_or_else(|_| default.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scaled_refuses_to_overflow() {
+        assert_eq!(
+            scaled("TTL_HOURS", 24, 60 * 60).unwrap(),
+            Duration::from_secs(86_400)
+        );
+        assert!(scaled("TTL_HOURS", u64::MAX / 60, 60 * 60).is_err());
+    }
+}
@@ -0,0 +1,146 @@
+//! # error
+//! Error type for handlers, answered as an `ApiResponse` envelope.
+
+use aide::{
+    OperationOutput,
+    generate::GenContext,
+    openapi::{Operation, Response},
+};
+use axum::{
+    body::HttpBody,
+    extract::Request,
+    http::{StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response as AxumResponse},
+};
+use log::error;
+use schemars::JsonSchema;
+use thiserror::Error;
+
+use crate::{
+    format::{self, Format},
+    prelude::*,
+    validation::FieldError,
+};
+
+#[derive(Debug, Error)]
+pub enum AppError {
+    #[error("Database error : {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("{0} not found")]
+    NotFound(&'static str),
+    #[error("Bad request : {0}")]
+    BadRequest(String),
+    #[error("Unauthorized")]
+    Unauthorized,
+    #[error("Precondition failed : the resource has changed, fetch it again")]
+    PreconditionFailed,
+    #[error("Precondition required : send If-Match with the ETag of the resource")]
+    PreconditionRequired,
+    #[error("Unprocessable entity : {} invalid field(s)", .0.len())]
+    Invalid(Vec<FieldError>),
+    /// Request an extractor could not read, see `extract.rs`
+    #[error("{reason}")]
+    Rejected {
+        status: StatusCode,
+        reason: &'static str,
+        fields: Vec<FieldError>,
+    },
+    #[error("Method not allowed")]
+    MethodNotAllowed,
+}
+impl AppError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Database(sqlx::Error::RowNotFound) | Self::NotFound(..) => StatusCode::NOT_FOUND,
+            Self::Database(sqlx::Error::Database(err)) if err.is_unique_violation() => {
+                StatusCode::CONFLICT
+            }
+            Self::Database(..) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::BadRequest(..) => StatusCode::BAD_REQUEST,
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            Self::PreconditionRequired => StatusCode::PRECONDITION_REQUIRED,
+            Self::Invalid(..) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::Rejected { status, .. } => *status,
+            Self::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> AxumResponse {
+        let status = self.status();
+        if status.is_server_error() {
+            error!("Error occur: {}", self);
+        }
+        let code = status.as_u16() as isize;
+        let resp = self.to_string();
+        match self {
+            Self::Invalid(fields) | Self::Rejected { fields, .. } => envelope(
+                status,
+                ApiResponse {
+                    code,
+                    resp,
+                    data: fields,
+                },
+            ),
+            _ => envelope(
+                status,
+                ApiResponse::<Empty> {
+                    code,
+                    resp,
+                    data: Empty,
+                },
+            ),
+        }
+    }
+}
+
+/// Answers `body` in the negotiated format, but JSON instead of CSV,
+/// which would keep the list of fields and lose the rest of the envelope.
+fn envelope<T: Serialize + JsonSchema>(status: StatusCode, body: ApiResponse<T>) -> AxumResponse {
+    match format::accepted() {
+        Format::Csv => (status, axum::Json(body)).into_response(),
+        _ => (status, Json(body)).into_response(),
+    }
+}
+
+impl OperationOutput for AppError {
+    type Inner = ApiResponse<Empty>;
+
+    fn operation_response(ctx: &mut GenContext, operation: &mut Operation) -> Option<Response> {
+        Json::<ApiResponse<Empty>>::operation_response(ctx, operation)
+    }
+
+    fn inferred_responses(
+        ctx: &mut GenContext,
+        operation: &mut Operation,
+    ) -> Vec<(Option<aide::openapi::StatusCode>, Response)> {
+        match Self::operation_response(ctx, operation) {
+            Some(res) => vec![(None, res)],
+            None => Vec::new(),
+        }
+    }
+}
+
+/// # fallback
+/// Rewrites the empty 404 and 405 of axum, answered when no route or no method of a route matches,
+/// into the `ApiResponse` envelope. The `Allow` header of a 405 is kept.
+pub async fn fallback(req: Request, next: Next) -> AxumResponse {
+    let response = next.run(req).await;
+    let err = match response.status() {
+        StatusCode::NOT_FOUND => AppError::NotFound("Route"),
+        StatusCode::METHOD_NOT_ALLOWED => AppError::MethodNotAllowed,
+        _ => return response,
+    };
+    if response.body().size_hint().exact() != Some(0) {
+        return response;
+    }
+    let allow = response.headers().get(header::ALLOW).cloned();
+    let mut response = err.into_response();
+    if let Some(allow) = allow {
+        response.headers_mut().insert(header::ALLOW, allow);
+    }
+    response
+}
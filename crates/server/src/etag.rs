@@ -0,0 +1,251 @@
+//! # etag
+//! Conditional requests on versioned rows.
+//! The `version` column of a row is its `ETag`, e.g. `"3"`.
+//! ## Headers
+//! - `If-None-Match` on reads : answered 304 while the client copy is current
+//! - `If-Match` on writes : required (428 when missing), answered 412 once the row has changed
+
+#![cfg_attr(not(any(feature = "db", feature = "users")), allow(dead_code))]
+
+use aide::{
+    OperationInput, OperationOutput,
+    generate::GenContext,
+    openapi::{
+        HeaderStyle, Operation, Parameter, ParameterData, ParameterSchemaOrContent, Response,
+        SchemaObject,
+    },
+    operation::add_parameters,
+};
+use axum::{
+    extract::FromRequestParts,
+    http::{HeaderMap, HeaderValue, StatusCode, header, request::Parts},
+    response::{IntoResponse, Response as AxumResponse},
+};
+
+use crate::error::AppError;
+
+pub fn etag(version: i64) -> HeaderValue {
+    HeaderValue::from_str(&format!("\"{}\"", version)).expect("digits are a valid header value")
+}
+
+/// Entity tags listed in `name`, `None` for `*`.
+/// Weak tags compare like strong ones, tags that are not ours never match.
+fn parse(headers: &HeaderMap, name: header::HeaderName) -> Option<Option<Vec<i64>>> {
+    let value = headers.get(name)?.to_str().unwrap_or_default();
+    if value.trim() == "*" {
+        return Some(None);
+    }
+    let versions = value
+        .split(',')
+        .filter_map(|tag| {
+            let tag = tag.trim();
+            let tag = tag.strip_prefix("W/").unwrap_or(tag);
+            tag.strip_prefix('"')?.strip_suffix('"')?.parse().ok()
+        })
+        .collect();
+    Some(Some(versions))
+}
+
+/// # IfMatch
+/// `If-Match` of a write, rejecting the request with 428 when missing.
+/// ## How to use
+/// ```
+/// let current = lock_post(&uow, id).await?;
+/// if_match.check(current.version)?;
+/// ```
+pub struct IfMatch(Option<Vec<i64>>);
+impl IfMatch {
+    /// `Err(PreconditionFailed)` unless `version` is one of the listed tags.
+    pub fn check(&self, version: i64) -> Result<(), AppError> {
+        match &self.0 {
+            Some(versions) if !versions.contains(&version) => Err(AppError::PreconditionFailed),
+            _ => Ok(()),
+        }
+    }
+}
+impl<S: Send + Sync> FromRequestParts<S> for IfMatch {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parse(&parts.headers, header::IF_MATCH)
+            .map(IfMatch)
+            .ok_or(AppError::PreconditionRequired)
+    }
+}
+impl OperationInput for IfMatch {
+    fn operation_input(ctx: &mut GenContext, operation: &mut Operation) {
+        add_header(
+            ctx,
+            operation,
+            "If-Match",
+            "`ETag` of the version being changed",
+            true,
+        );
+    }
+}
+
+/// # IfNoneMatch
+/// Optional `If-None-Match` of a read.
+pub struct IfNoneMatch(Option<Option<Vec<i64>>>);
+impl IfNoneMatch {
+    /// `true` when the client already holds `version`.
+    pub fn matches(&self, version: i64) -> bool {
+        match &self.0 {
+            None => false,
+            Some(None) => true,
+            Some(Some(versions)) => versions.contains(&version),
+        }
+    }
+}
+impl<S: Send + Sync> FromRequestParts<S> for IfNoneMatch {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(IfNoneMatch(parse(&parts.headers, header::IF_NONE_MATCH)))
+    }
+}
+impl OperationInput for IfNoneMatch {
+    fn operation_input(ctx: &mut GenContext, operation: &mut Operation) {
+        add_header(
+            ctx,
+            operation,
+            "If-None-Match",
+            "`ETag` held by the client, answered 304 while current",
+            false,
+        );
+    }
+}
+
+fn add_header(
+    ctx: &mut GenContext,
+    operation: &mut Operation,
+    name: &str,
+    description: &str,
+    required: bool,
+) {
+    let schema = ctx.schema.subschema_for::<String>();
+    add_parameters(
+        ctx,
+        operation,
+        [Parameter::Header {
+            parameter_data: ParameterData {
+                name: name.to_string(),
+                description: Some(description.to_string()),
+                required,
+                deprecated: None,
+                format: ParameterSchemaOrContent::Schema(SchemaObject {
+                    json_schema: schema,
+                    example: None,
+                    external_docs: None,
+                }),
+                example: None,
+                examples: Default::default(),
+                explode: None,
+                extensions: Default::default(),
+            },
+            style: HeaderStyle::Simple,
+        }],
+    );
+}
+
+/// # Versioned
+/// Response of a versioned row, with its `ETag`.
+pub enum Versioned<T> {
+    Body(i64, T),
+    /// 304 without body, the client copy is current
+    NotModified(i64),
+}
+impl<T> Versioned<T> {
+    /// 304 when `if_none_match` already holds `version`, `body` otherwise.
+    pub fn new(version: i64, if_none_match: &IfNoneMatch, body: T) -> Self {
+        if if_none_match.matches(version) {
+            Self::NotModified(version)
+        } else {
+            Self::Body(version, body)
+        }
+    }
+}
+impl<T: IntoResponse> IntoResponse for Versioned<T> {
+    fn into_response(self) -> AxumResponse {
+        match self {
+            Self::Body(version, body) => ([(header::ETAG, etag(version))], body).into_response(),
+            Self::NotModified(version) => {
+                (StatusCode::NOT_MODIFIED, [(header::ETAG, etag(version))]).into_response()
+            }
+        }
+    }
+}
+impl<T: OperationOutput> OperationOutput for Versioned<T> {
+    type Inner = T::Inner;
+
+    fn operation_response(ctx: &mut GenContext, operation: &mut Operation) -> Option<Response> {
+        T::operation_response(ctx, operation)
+    }
+
+    fn inferred_responses(
+        ctx: &mut GenContext,
+        operation: &mut Operation,
+    ) -> Vec<(Option<aide::openapi::StatusCode>, Response)> {
+        T::inferred_responses(ctx, operation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn if_match(value: &str) -> Option<Option<Vec<i64>>> {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_MATCH, HeaderValue::from_str(value).unwrap());
+        parse(&headers, header::IF_MATCH)
+    }
+
+    #[test]
+    fn parse_is_none_without_the_header() {
+        assert_eq!(parse(&HeaderMap::new(), header::IF_MATCH), None);
+    }
+
+    #[test]
+    fn parse_reads_star_as_any_version() {
+        assert_eq!(if_match("*"), Some(None));
+        assert_eq!(if_match(" * "), Some(None));
+    }
+
+    #[test]
+    fn parse_lists_strong_and_weak_tags() {
+        assert_eq!(if_match(r#""3""#), Some(Some(vec![3])));
+        assert_eq!(if_match(r#""3", W/"5" ,"8""#), Some(Some(vec![3, 5, 8])));
+    }
+
+    #[test]
+    fn parse_skips_tags_that_are_not_ours() {
+        assert_eq!(if_match(r#"3, "abc", "4"#), Some(Some(vec![])));
+        assert_eq!(if_match(r#"abc, "7""#), Some(Some(vec![7])));
+    }
+
+    #[test]
+    fn check_passes_a_listed_version() {
+        assert!(IfMatch(Some(vec![2, 3])).check(3).is_ok());
+        assert!(IfMatch(None).check(42).is_ok());
+    }
+
+    #[test]
+    fn check_fails_another_version() {
+        assert!(matches!(
+            IfMatch(Some(vec![2])).check(3),
+            Err(AppError::PreconditionFailed)
+        ));
+        assert!(matches!(
+            IfMatch(Some(vec![])).check(1),
+            Err(AppError::PreconditionFailed)
+        ));
+    }
+
+    #[test]
+    fn if_none_match_matches_held_versions() {
+        assert!(!IfNoneMatch(None).matches(1));
+        assert!(IfNoneMatch(Some(None)).matches(1));
+        assert!(IfNoneMatch(Some(Some(vec![1]))).matches(1));
+        assert!(!IfNoneMatch(Some(Some(vec![2]))).matches(1));
+    }
+}
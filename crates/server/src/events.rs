@@ -0,0 +1,155 @@
+//! # events
+//! In-process pub/sub of committed writes to users and posts, served by `routes/events.rs`.
+//! Events of every write go through the outbox (see `repository/outbox.rs`),
+//! whose relay notifies `row_changes` with them.
+//! `tasks::events` feeds those notifications to the [`Hub`], so rolled back writes are never announced
+//! and every instance sees the writes of the others.
+//! ## Topics
+//! - `posts` : every post
+//! - `post:{id}` : one post, its tags included
+//! - `user:{id}` : one user and the posts they write
+//!
+//! Changes to posts that are not published only reach admins.
+
+use std::{fmt, str::FromStr};
+
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use tokio::sync::broadcast;
+
+use crate::{prelude::*, repository::posts::PostStatus, shutdown::Shutdown};
+
+/// Changes kept for subscribers that fall behind, the oldest are dropped past it.
+const BACKLOG: usize = 1024;
+
+/// # Change
+/// An event of a committed write, as relayed from the outbox.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct Change {
+    /// Id of the outbox event, increasing
+    pub id: i64,
+    /// Same names as webhook events, e.g. `post.published`
+    pub event: String,
+    /// `None` for user events
+    pub post_id: Option<i64>,
+    /// The user itself, or the author of the post
+    pub user_id: Option<i64>,
+    /// Status of the post after the write, `None` for users
+    pub status: Option<PostStatus>,
+    /// Columns the write changed, e.g. `deleted_at` for a soft delete, `tag_id` for tags
+    pub changed: Vec<String>,
+    pub at: DateTime<Utc>,
+}
+impl Change {
+    fn is_public(&self) -> bool {
+        matches!(self.status, None | Some(PostStatus::Published))
+    }
+}
+
+/// # Topic
+/// What a subscriber listens to, written `posts`, `post:{id}` or `user:{id}`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Topic {
+    Posts,
+    Post(i64),
+    User(i64),
+}
+impl Topic {
+    fn matches(self, change: &Change) -> bool {
+        match self {
+            Self::Posts => change.post_id.is_some(),
+            Self::Post(id) => change.post_id == Some(id),
+            Self::User(id) => change.user_id == Some(id),
+        }
+    }
+}
+impl FromStr for Topic {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let id = |id: &str| {
+            id.parse()
+                .map_err(|_| format!("{} : the id is not a number", s))
+        };
+        match s.trim().split_once(':') {
+            None if s.trim() == "posts" => Ok(Self::Posts),
+            Some(("post", post_id)) => id(post_id).map(Self::Post),
+            Some(("user", user_id)) => id(user_id).map(Self::User),
+            _ => Err(format!(
+                "{} : expected posts, post:{{id}} or user:{{id}}",
+                s
+            )),
+        }
+    }
+}
+impl fmt::Display for Topic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Posts => write!(f, "posts"),
+            Self::Post(id) => write!(f, "post:{}", id),
+            Self::User(id) => write!(f, "user:{}", id),
+        }
+    }
+}
+
+/// # Hub
+/// Fans changes out to every subscriber.
+#[derive(Clone)]
+pub struct Hub(broadcast::Sender<Arc<Change>>);
+impl Default for Hub {
+    fn default() -> Self {
+        Self(broadcast::channel(BACKLOG).0)
+    }
+}
+impl Hub {
+    pub fn publish(&self, change: Change) {
+        // Nobody listening is fine
+        let _ = self.0.send(Arc::new(change));
+    }
+
+    /// `admin` subscribers also see changes to posts that are not published.
+    pub fn subscribe(&self, topics: Vec<Topic>, admin: bool, shutdown: Shutdown) -> Subscription {
+        Subscription {
+            rx: self.0.subscribe(),
+            topics,
+            admin,
+            shutdown,
+        }
+    }
+}
+
+pub struct Subscription {
+    rx: broadcast::Receiver<Arc<Change>>,
+    pub topics: Vec<Topic>,
+    admin: bool,
+    shutdown: Shutdown,
+}
+
+/// What a subscriber receives.
+pub enum Received {
+    Change(Arc<Change>),
+    /// The subscriber fell behind and `n` changes were dropped, reading again is needed
+    Lagged(u64),
+}
+
+impl Subscription {
+    /// Next change matching the topics, `None` once the server stops.
+    pub async fn next(&mut self) -> Option<Received> {
+        loop {
+            let received = tokio::select! {
+                received = self.rx.recv() => received,
+                _ = self.shutdown.stopped() => return None,
+            };
+            match received {
+                Ok(change) if self.wants(&change) => return Some(Received::Change(change)),
+                Ok(..) => (),
+                Err(broadcast::error::RecvError::Lagged(n)) => return Some(Received::Lagged(n)),
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    fn wants(&self, change: &Change) -> bool {
+        (self.admin || change.is_public()) && self.topics.iter().any(|t| t.matches(change))
+    }
+}
@@ -0,0 +1,310 @@
+//! # extract
+//! `Query`, `Json` and `Path` answering their rejections as an `ApiResponse` envelope,
+//! naming the offending field when there is one. Re-exported from `prelude.rs`.
+
+#![cfg_attr(not(any(feature = "db", feature = "users")), allow(dead_code))]
+
+use std::error::Error as StdError;
+
+use aide::{
+    OperationInput, OperationOutput,
+    generate::GenContext,
+    openapi::{MediaType, Operation, ReferenceOr, Response, SchemaObject},
+};
+use axum::{
+    body::Bytes,
+    extract::{
+        FromRequest, FromRequestParts, Request,
+        path::ErrorKind,
+        rejection::{JsonRejection, PathRejection, QueryRejection},
+    },
+    http::{HeaderValue, StatusCode, header, request::Parts},
+    response::{IntoResponse, Response as AxumResponse},
+};
+use indexmap::IndexMap;
+use log::error;
+use schemars::JsonSchema;
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::{
+    error::AppError,
+    format::{self, Format},
+    validation::FieldError,
+};
+
+/// # Query
+/// `axum::extract::Query`, rejected with 400.
+pub struct Query<T>(pub T);
+
+impl<T, S> FromRequestParts<S> for Query<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        match axum::extract::Query::<T>::from_request_parts(parts, state).await {
+            Ok(axum::extract::Query(value)) => Ok(Query(value)),
+            Err(rejection) => Err(query_rejection(rejection)),
+        }
+    }
+}
+impl<T: JsonSchema> OperationInput for Query<T> {
+    fn operation_input(ctx: &mut GenContext, operation: &mut Operation) {
+        axum::extract::Query::<T>::operation_input(ctx, operation);
+    }
+}
+
+fn query_rejection(rejection: QueryRejection) -> AppError {
+    AppError::Rejected {
+        status: StatusCode::BAD_REQUEST,
+        reason: "Bad request : invalid query string",
+        fields: deserialize_error::<serde_urlencoded::de::Error>(&rejection)
+            .into_iter()
+            .collect(),
+    }
+}
+
+/// # Json
+/// Body in any format of [`Format::BODIES`], answered in the format negotiated from `Accept`
+/// (see `format.rs`), JSON by default.
+/// Rejected with 415 for other `Content-Type`s, 400 on malformed bodies and 422 when it does not fit `T`.
+pub struct Json<T>(pub T);
+
+impl<T, S> FromRequest<S> for Json<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let format = match Format::from_content_type(req.headers()) {
+            Some(format @ (Format::MessagePack | Format::Cbor)) => format,
+            _ => {
+                return match axum::Json::<T>::from_request(req, state).await {
+                    Ok(axum::Json(value)) => Ok(Json(value)),
+                    Err(rejection) => Err(json_rejection(rejection)),
+                };
+            }
+        };
+        let body =
+            Bytes::from_request(req, state)
+                .await
+                .map_err(|rejection| AppError::Rejected {
+                    status: rejection.status(),
+                    reason: "The request body could not be read",
+                    fields: Vec::new(),
+                })?;
+        format.decode(&body).map(Json).map_err(|err| {
+            let (status, reason) = match err.path {
+                Some(..) => (
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "Unprocessable entity : the body does not fit the schema",
+                ),
+                None => (StatusCode::BAD_REQUEST, "Bad request : malformed body"),
+            };
+            AppError::Rejected {
+                status,
+                reason,
+                fields: vec![field_error(err.path.as_deref().unwrap_or("?"), err.message)],
+            }
+        })
+    }
+}
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> AxumResponse {
+        let format = format::accepted();
+        match format.encode(&self.0) {
+            Ok(body) => (
+                [(
+                    header::CONTENT_TYPE,
+                    HeaderValue::from_static(format.mime()),
+                )],
+                body,
+            )
+                .into_response(),
+            // Only lists are answered as CSV
+            Err(..) if format == Format::Csv => axum::Json(self.0).into_response(),
+            Err(err) => {
+                error!("Failed to encode {} response : {}", format.mime(), err);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+impl<T: JsonSchema> OperationInput for Json<T> {
+    fn operation_input(ctx: &mut GenContext, operation: &mut Operation) {
+        axum::Json::<T>::operation_input(ctx, operation);
+        if let Some(ReferenceOr::Item(body)) = &mut operation.request_body {
+            add_media_types(ctx, &mut body.content, false);
+        }
+    }
+}
+impl<T: JsonSchema> OperationOutput for Json<T> {
+    type Inner = T;
+
+    fn operation_response(ctx: &mut GenContext, operation: &mut Operation) -> Option<Response> {
+        let mut res = axum::Json::<T>::operation_response(ctx, operation)?;
+        add_media_types(ctx, &mut res.content, true);
+        Some(res)
+    }
+
+    fn inferred_responses(
+        ctx: &mut GenContext,
+        operation: &mut Operation,
+    ) -> Vec<(Option<aide::openapi::StatusCode>, Response)> {
+        match Self::operation_response(ctx, operation) {
+            Some(res) => vec![(Some(aide::openapi::StatusCode::Code(200)), res)],
+            None => Vec::new(),
+        }
+    }
+}
+
+/// Documents the formats besides JSON, CSV only for `ApiResponse`s holding a list.
+fn add_media_types(
+    ctx: &mut GenContext,
+    content: &mut IndexMap<String, MediaType>,
+    response: bool,
+) {
+    let Some(json) = content.get(Format::Json.mime()).cloned() else {
+        return;
+    };
+    for format in [Format::MessagePack, Format::Cbor] {
+        content.insert(format.mime().to_string(), json.clone());
+    }
+    let is_list = json.schema.as_ref().is_some_and(|schema| {
+        let schema = ctx.resolve_schema(&schema.json_schema);
+        let data = schema.get("properties").and_then(|p| p.get("data"));
+        data.and_then(|d| d.get("type")) == Some(&"array".into())
+    });
+    if response && is_list {
+        let csv = MediaType {
+            schema: Some(SchemaObject {
+                json_schema: schemars::json_schema!({ "type": "string" }),
+                example: None,
+                external_docs: None,
+            }),
+            ..Default::default()
+        };
+        content.insert(Format::Csv.mime().to_string(), csv);
+    }
+}
+
+fn json_rejection(rejection: JsonRejection) -> AppError {
+    let (status, reason) = match &rejection {
+        JsonRejection::JsonDataError(..) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Unprocessable entity : the JSON body does not fit the schema",
+        ),
+        JsonRejection::JsonSyntaxError(..) => {
+            (StatusCode::BAD_REQUEST, "Bad request : malformed JSON body")
+        }
+        JsonRejection::MissingJsonContentType(..) => (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Unsupported media type : expected application/json, application/msgpack or application/cbor",
+        ),
+        _ => (rejection.status(), "The request body could not be read"),
+    };
+    AppError::Rejected {
+        status,
+        reason,
+        fields: deserialize_error::<serde_json::Error>(&rejection)
+            .into_iter()
+            .collect(),
+    }
+}
+
+/// # Path
+/// `axum::extract::Path`, rejected with 400.
+pub struct Path<T>(pub T);
+
+impl<T, S> FromRequestParts<S> for Path<T>
+where
+    T: DeserializeOwned + Send,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        match axum::extract::Path::<T>::from_request_parts(parts, state).await {
+            Ok(axum::extract::Path(value)) => Ok(Path(value)),
+            Err(rejection) => Err(path_rejection(rejection)),
+        }
+    }
+}
+impl<T: JsonSchema> OperationInput for Path<T> {
+    fn operation_input(ctx: &mut GenContext, operation: &mut Operation) {
+        axum::extract::Path::<T>::operation_input(ctx, operation);
+    }
+}
+
+fn path_rejection(rejection: PathRejection) -> AppError {
+    let PathRejection::FailedToDeserializePathParams(err) = &rejection else {
+        // Routing bug, not the client's fault
+        return AppError::Rejected {
+            status: rejection.status(),
+            reason: "Internal server error : path parameters are missing",
+            fields: Vec::new(),
+        };
+    };
+    let field = match err.kind() {
+        ErrorKind::ParseErrorAtKey { key, .. }
+        | ErrorKind::InvalidUtf8InPathParam { key }
+        | ErrorKind::DeserializeError { key, .. } => Some(key.clone()),
+        ErrorKind::ParseErrorAtIndex { index, .. } => Some(index.to_string()),
+        _ => None,
+    };
+    AppError::Rejected {
+        status: err.status(),
+        reason: "Bad request : invalid path parameter",
+        fields: field
+            .map(|field| FieldError {
+                field,
+                code: "invalid".to_string(),
+                message: err.kind().to_string(),
+            })
+            .into_iter()
+            .collect(),
+    }
+}
+
+/// The field `serde` failed on, found in the source chain of an axum rejection.
+fn deserialize_error<E: StdError + 'static>(
+    rejection: &(dyn StdError + 'static),
+) -> Option<FieldError> {
+    let mut source = Some(rejection);
+    while let Some(err) = source {
+        if let Some(err) = err.downcast_ref::<serde_path_to_error::Error<E>>() {
+            return Some(field_error(
+                &err.path().to_string(),
+                err.inner().to_string(),
+            ));
+        }
+        source = err.source();
+    }
+    None
+}
+
+/// `path` as printed by `serde_path_to_error`, `?` when unknown.
+fn field_error(path: &str, message: String) -> FieldError {
+    // Missing and unknown fields are reported on their parent
+    let (field, code) = match (backticked(&message, "missing field `"), path) {
+        (Some(name), ".") => (name.to_string(), "missing"),
+        (Some(name), _) => (format!("{}.{}", path, name), "missing"),
+        (None, _) if message.starts_with("unknown field") => (path.to_string(), "unknown"),
+        // Syntax errors leave the path unknown
+        (None, "?") => (".".to_string(), "syntax"),
+        (None, _) => (path.to_string(), "invalid"),
+    };
+    FieldError {
+        field,
+        code: code.to_string(),
+        message,
+    }
+}
+
+fn backticked<'a>(message: &'a str, prefix: &str) -> Option<&'a str> {
+    message.strip_prefix(prefix)?.split('`').next()
+}
@@ -0,0 +1,375 @@
+//! # format
+//! Media types read and answered by [`crate::extract::Json`].
+//! The middleware [`negotiate`] picks the response format from `Accept` for the whole request,
+//! `Json` reads it back with [`accepted`].
+//! ## Formats
+//! - `application/json` : default
+//! - `application/msgpack` and `application/cbor` : same shape as the JSON, for embedded clients
+//! - `text/csv` : responses holding a list only, one row per item, anything else is answered as JSON,
+//!   errors included
+//!
+//! An `Accept` naming none of them is answered 406, unless it names a type answered by routes of their
+//! own (the HTML docs, the events stream) or the request is a gRPC call.
+
+use anyhow::{Context, anyhow};
+use axum::{
+    extract::Request,
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::{Map, Value};
+
+use crate::error::AppError;
+
+/// Types answered by routes that do not go through [`Format`], e.g. `text/event-stream` by `events.rs`.
+const OTHER_TYPES: [&str; 2] = ["text/html", "text/event-stream"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    MessagePack,
+    Cbor,
+    Csv,
+}
+impl Format {
+    /// Formats request bodies may be written in.
+    pub const BODIES: [Format; 3] = [Format::Json, Format::MessagePack, Format::Cbor];
+
+    pub fn mime(self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::MessagePack => "application/msgpack",
+            Self::Cbor => "application/cbor",
+            Self::Csv => "text/csv",
+        }
+    }
+
+    fn from_mime(mime: &str) -> Option<Self> {
+        match mime.trim().to_ascii_lowercase().as_str() {
+            "application/json" | "application/*" | "*/*" => Some(Self::Json),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                Some(Self::MessagePack)
+            }
+            "application/cbor" => Some(Self::Cbor),
+            "text/csv" | "text/*" => Some(Self::Csv),
+            mime if mime.ends_with("+json") => Some(Self::Json),
+            _ => None,
+        }
+    }
+
+    /// Preferred format of `Accept`, JSON when it is missing and `None` when it names none of ours.
+    /// Ties go to the first listed.
+    pub fn from_accept(headers: &HeaderMap) -> Option<Self> {
+        let Some(ranges) = accepted_ranges(headers) else {
+            return Some(Self::Json);
+        };
+        let mut best: Option<(Self, f32)> = None;
+        for (mime, q) in ranges {
+            let Some(format) = Self::from_mime(mime) else {
+                continue;
+            };
+            if best.is_none_or(|(_, best_q)| q > best_q) {
+                best = Some((format, q));
+            }
+        }
+        best.map(|(format, _)| format)
+    }
+
+    /// Format of the request body, `None` when `Content-Type` is missing or not one of ours.
+    pub fn from_content_type(headers: &HeaderMap) -> Option<Self> {
+        let content_type = headers.get(header::CONTENT_TYPE)?.to_str().ok()?;
+        Self::from_mime(content_type.split(';').next()?)
+            .filter(|format| Self::BODIES.contains(format) && !content_type.contains('*'))
+    }
+
+    /// Fails for CSV when `value` holds no list.
+    pub fn encode<T: Serialize>(self, value: &T) -> anyhow::Result<Vec<u8>> {
+        Ok(match self {
+            Self::Json => serde_json::to_vec(value)?,
+            // Named, so structs are maps like in JSON
+            Self::MessagePack => rmp_serde::to_vec_named(value)?,
+            Self::Cbor => {
+                let mut buf = Vec::new();
+                ciborium::into_writer(value, &mut buf)?;
+                buf
+            }
+            Self::Csv => to_csv(serde_json::to_value(value)?)?,
+        })
+    }
+
+    /// Decodes a request body in this format.
+    pub fn decode<T: DeserializeOwned>(self, body: &[u8]) -> Result<T, DecodeError> {
+        match self {
+            Self::MessagePack => {
+                serde_path_to_error::deserialize(&mut rmp_serde::Deserializer::new(body))
+                    .map_err(DecodeError::from)
+            }
+            // ciborium has no public deserializer, so fields are found on the decoded value
+            Self::Cbor => {
+                let value: Value = ciborium::from_reader(body).map_err(|err| DecodeError {
+                    path: None,
+                    message: err.to_string(),
+                })?;
+                serde_path_to_error::deserialize(value).map_err(DecodeError::from)
+            }
+            _ => serde_path_to_error::deserialize(&mut serde_json::Deserializer::from_slice(body))
+                .map_err(DecodeError::from),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DecodeError {
+    /// Field the decoding failed on, `None` for malformed bodies
+    pub path: Option<String>,
+    pub message: String,
+}
+impl<E: std::fmt::Display> From<serde_path_to_error::Error<E>> for DecodeError {
+    fn from(err: serde_path_to_error::Error<E>) -> Self {
+        let path = err.path().to_string();
+        Self {
+            path: (path != "?").then_some(path),
+            message: err.inner().to_string(),
+        }
+    }
+}
+
+/// Media ranges of `Accept` with their weight, those weighing `q=0` left out.
+/// `None` when the header is missing or unreadable.
+fn accepted_ranges(headers: &HeaderMap) -> Option<impl Iterator<Item = (&str, f32)>> {
+    let accept = headers.get(header::ACCEPT)?.to_str().ok()?;
+    let ranges = accept.split(',').filter_map(|range| {
+        let mut params = range.split(';');
+        let mime = params.next()?.trim();
+        let q = params
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .find_map(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        (q > 0.0).then_some((mime, q))
+    });
+    Some(ranges)
+}
+
+/// `true` when `Accept` names one of [`OTHER_TYPES`].
+fn accepts_other(headers: &HeaderMap) -> bool {
+    accepted_ranges(headers).is_some_and(|mut ranges| {
+        ranges.any(|(mime, _)| {
+            OTHER_TYPES
+                .iter()
+                .any(|other| mime.eq_ignore_ascii_case(other))
+        })
+    })
+}
+
+/// One row per item of the list, i.e. `data` of an `ApiResponse` or `value` itself.
+/// Columns are the fields of the items, nested values are written as JSON.
+/// Strings a spreadsheet would run as a formula are quoted with `'`, see [`cell`].
+fn to_csv(value: Value) -> anyhow::Result<Vec<u8>> {
+    let rows = match value {
+        Value::Array(rows) => rows,
+        Value::Object(mut map) => match map.remove("data") {
+            Some(Value::Array(rows)) => rows,
+            _ => return Err(anyhow!("text/csv only holds lists")),
+        },
+        _ => return Err(anyhow!("text/csv only holds lists")),
+    };
+    let rows: Vec<Map<String, Value>> = rows
+        .into_iter()
+        .map(|row| match row {
+            Value::Object(map) => map,
+            value => Map::from_iter([("value".to_string(), value)]),
+        })
+        .collect();
+    let mut columns: Vec<&String> = Vec::new();
+    for key in rows.iter().flat_map(Map::keys) {
+        if !columns.contains(&key) {
+            columns.push(key);
+        }
+    }
+
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(&columns)?;
+    for row in &rows {
+        writer.write_record(columns.iter().map(|column| match row.get(*column) {
+            None | Some(Value::Null) => String::new(),
+            Some(Value::String(s)) => cell(s),
+            Some(value) => value.to_string(),
+        }))?;
+    }
+    writer.into_inner().context("flushing CSV")
+}
+
+/// `s` prefixed with `'` when it starts like a formula, so spreadsheets show it as text.
+fn cell(s: &str) -> String {
+    if s.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{}", s)
+    } else {
+        s.to_string()
+    }
+}
+
+/// `true` for gRPC calls, whose messages are framed by `grpc.rs` rather than any [`Format`].
+pub fn is_grpc(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/grpc"))
+}
+
+tokio::task_local! {
+    static ACCEPTED: Format;
+}
+
+/// Format negotiated for the running request, JSON outside of any.
+pub fn accepted() -> Format {
+    ACCEPTED.try_with(|format| *format).unwrap_or(Format::Json)
+}
+
+/// Negotiates the response format from `Accept` for the whole request.
+/// Answers 406 when it names none of ours, before the request runs.
+pub async fn negotiate(req: Request, next: Next) -> Response {
+    let format = match Format::from_accept(req.headers()) {
+        Some(format) => Some(format),
+        // Answered in a type of their own, errors are still JSON
+        None if is_grpc(req.headers()) || accepts_other(req.headers()) => Some(Format::Json),
+        None => None,
+    };
+    let mut res = match format {
+        Some(format) => ACCEPTED.scope(format, next.run(req)).await,
+        None => AppError::Rejected {
+            status: StatusCode::NOT_ACCEPTABLE,
+            reason: "Not acceptable : Accept names none of application/json, application/msgpack, \
+                     application/cbor or text/csv",
+            fields: Vec::new(),
+        }
+        .into_response(),
+    };
+    res.headers_mut()
+        .append(header::VARY, HeaderValue::from_static("accept"));
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn accept(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    fn csv(value: Value) -> String {
+        String::from_utf8(to_csv(value).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn from_accept_defaults_to_json() {
+        assert_eq!(Format::from_accept(&HeaderMap::new()), Some(Format::Json));
+        assert_eq!(Format::from_accept(&accept("*/*")), Some(Format::Json));
+        assert_eq!(
+            Format::from_accept(&accept("application/problem+json")),
+            Some(Format::Json)
+        );
+    }
+
+    #[test]
+    fn from_accept_picks_the_heaviest_of_ours() {
+        assert_eq!(
+            Format::from_accept(&accept("application/json;q=0.5, application/cbor")),
+            Some(Format::Cbor)
+        );
+        assert_eq!(
+            Format::from_accept(&accept("text/html, text/csv;q=0.9, */*;q=0.1")),
+            Some(Format::Csv)
+        );
+        assert_eq!(
+            Format::from_accept(&accept("application/x-msgpack, application/json")),
+            Some(Format::MessagePack)
+        );
+    }
+
+    #[test]
+    fn from_accept_skips_refused_types() {
+        assert_eq!(
+            Format::from_accept(&accept("application/json;q=0, text/csv;q=0.2")),
+            Some(Format::Csv)
+        );
+        assert_eq!(Format::from_accept(&accept("application/json;q=0")), None);
+    }
+
+    #[test]
+    fn from_accept_is_none_for_other_types() {
+        assert_eq!(Format::from_accept(&accept("application/xml")), None);
+        assert_eq!(Format::from_accept(&accept("text/html")), None);
+        assert!(accepts_other(&accept("application/xml, Text/HTML")));
+        assert!(!accepts_other(&accept("application/xml")));
+    }
+
+    #[test]
+    fn to_csv_writes_one_row_per_item() {
+        let value = json!({
+            "code": 0,
+            "data": [
+                {"id": 1, "name": "a, b", "tags": ["x"]},
+                {"id": 2, "extra": null},
+            ],
+        });
+        assert_eq!(
+            csv(value),
+            "id,name,tags,extra\n1,\"a, b\",\"[\"\"x\"\"]\",\n2,,,\n"
+        );
+    }
+
+    #[test]
+    fn to_csv_wraps_plain_values() {
+        assert_eq!(csv(json!([1, "two"])), "value\n1\ntwo\n");
+    }
+
+    #[test]
+    fn to_csv_refuses_anything_but_lists() {
+        assert!(to_csv(json!({"data": {"id": 1}})).is_err());
+        assert!(to_csv(json!("text")).is_err());
+    }
+
+    #[test]
+    fn to_csv_quotes_formulas() {
+        let value = json!([
+            {"cell": "=HYPERLINK(\"http://evil\")"},
+            {"cell": "+1"},
+            {"cell": "-1"},
+            {"cell": "@SUM(A1)"},
+            {"cell": "\t=1"},
+            {"cell": "a=b"},
+            {"cell": -1},
+        ]);
+        assert_eq!(
+            csv(value),
+            "cell\n\"'=HYPERLINK(\"\"http://evil\"\")\"\n'+1\n'-1\n'@SUM(A1)\n'\t=1\na=b\n-1\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn errors_keep_their_envelope_under_csv() {
+        let error = AppError::Invalid(vec![crate::validation::FieldError {
+            field: "name".to_string(),
+            code: "length".to_string(),
+            message: "is too long".to_string(),
+        }]);
+        let res = ACCEPTED
+            .scope(Format::Csv, async { error.into_response() })
+            .await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(res.headers()[header::CONTENT_TYPE], "application/json");
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], 422);
+        assert_eq!(body["data"][0]["field"], "name");
+    }
+}
@@ -0,0 +1,320 @@
+//! # graphql
+//! Read-only GraphQL schema over users and posts, served by `routes/graphql.rs`.
+//! Nested fields are loaded through per-request [`DataLoader`]s,
+//! so `{ user { posts { author { name } } } }` runs one query per level, not one per row.
+//! There are no comments yet (`repository::comment` is still commented out), so no `comments` field.
+//! ## Auth
+//! Same as REST : anyone sees live users and published posts,
+//! arguments reaching further, e.g. `includeUnpublished`, require `X-Auth-Key`.
+
+use std::collections::HashMap;
+
+use async_graphql::{
+    Context, EmptyMutation, EmptySubscription, Enum, ErrorExtensions, Object,
+    dataloader::{DataLoader, Loader},
+};
+use chrono::{DateTime, Utc};
+use log::error;
+
+use crate::{
+    error::AppError,
+    markdown::{self, Render},
+    prelude::*,
+    repository::{ReadOptions, RepoFactory, posts, tags, users},
+};
+
+pub type ApiSchema = async_graphql::Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+/// Deepest nesting a query may ask for, e.g. `user { posts { author { name } } }` is 4.
+const MAX_DEPTH: usize = 8;
+/// Fields a query may resolve, each list field counted once.
+const MAX_COMPLEXITY: usize = 500;
+
+pub fn schema() -> ApiSchema {
+    async_graphql::Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .limit_depth(MAX_DEPTH)
+        .limit_complexity(MAX_COMPLEXITY)
+        .finish()
+}
+
+/// # Viewer
+/// Who sends the request, checked by fields guarded like admin routes.
+#[derive(Clone, Copy)]
+pub struct Viewer {
+    pub admin: bool,
+}
+
+/// Attaches what resolvers read to `request`.
+/// Loaders are made per request, so nothing is cached across requests or callers.
+pub fn prepare(
+    request: async_graphql::Request,
+    repos: Arc<RepoFactory>,
+    viewer: Viewer,
+) -> async_graphql::Request {
+    request
+        .data(viewer)
+        .data(DataLoader::new(
+            UserLoader {
+                repos: repos.clone(),
+            },
+            tokio::spawn,
+        ))
+        .data(DataLoader::new(
+            PostsLoader {
+                repos: repos.clone(),
+            },
+            tokio::spawn,
+        ))
+        .data(repos)
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// Live user by id.
+    async fn user(&self, ctx: &Context<'_>, id: i64) -> async_graphql::Result<Option<User>> {
+        let users = ctx.data_unchecked::<DataLoader<UserLoader>>();
+        Ok(users.load_one(id).await?.map(User))
+    }
+
+    /// Live user by name.
+    async fn user_by_name(
+        &self,
+        ctx: &Context<'_>,
+        name: String,
+    ) -> async_graphql::Result<Option<User>> {
+        let repos = ctx.data_unchecked::<Arc<RepoFactory>>();
+        let user = repos.user.find_by_name(&name).await.map_err(gql_error)?;
+        Ok(user.map(User))
+    }
+
+    /// Published post by id.
+    async fn post(&self, ctx: &Context<'_>, id: i64) -> async_graphql::Result<Option<Post>> {
+        let repos = ctx.data_unchecked::<Arc<RepoFactory>>();
+        let post = repos
+            .posts
+            .find(id, ReadOptions::default())
+            .await
+            .map_err(gql_error)?;
+        Ok(post.map(Post))
+    }
+
+    /// Posts tagged with `tag`, newest first.
+    async fn posts_by_tag(
+        &self,
+        ctx: &Context<'_>,
+        tag: String,
+        #[graphql(default)] include_unpublished: bool,
+    ) -> async_graphql::Result<Vec<Post>> {
+        let opts = ReadOptions {
+            include_unpublished: admin_only(ctx, include_unpublished)?,
+            ..Default::default()
+        };
+        let tag = tags::normalize(&tag)
+            .ok_or_else(|| gql_error(AppError::BadRequest(format!("Invalid tag : {}", tag))))?;
+        let repos = ctx.data_unchecked::<Arc<RepoFactory>>();
+        let posts = repos
+            .posts
+            .select_by_tag(&tag, opts)
+            .await
+            .map_err(gql_error)?;
+        Ok(posts.into_iter().map(Post).collect())
+    }
+}
+
+pub struct User(users::Users);
+
+#[Object]
+impl User {
+    async fn id(&self) -> i64 {
+        self.0.id
+    }
+    async fn name(&self) -> &str {
+        &self.0.name
+    }
+    /// Incremented on every change, the `ETag` of the REST routes
+    async fn version(&self) -> i64 {
+        self.0.version
+    }
+    async fn created_at(&self) -> DateTime<Utc> {
+        self.0.created_at
+    }
+    async fn updated_at(&self) -> DateTime<Utc> {
+        self.0.updated_at
+    }
+
+    /// Posts of the user, oldest first.
+    async fn posts(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default)] include_unpublished: bool,
+    ) -> async_graphql::Result<Vec<Post>> {
+        let key = PostsOf {
+            user_id: self.0.id,
+            include_unpublished: admin_only(ctx, include_unpublished)?,
+        };
+        let posts = ctx.data_unchecked::<DataLoader<PostsLoader>>();
+        let posts = posts.load_one(key).await?.unwrap_or_default();
+        Ok(posts.into_iter().map(Post).collect())
+    }
+}
+
+pub struct Post(posts::Posts);
+
+#[Object]
+impl Post {
+    async fn id(&self) -> i64 {
+        self.0.id
+    }
+    async fn title(&self) -> &str {
+        &self.0.title
+    }
+    /// Markdown as written, or sanitized HTML
+    async fn content(&self, #[graphql(default)] render: ContentFormat) -> String {
+        match (Render::from(render), &self.0.content_html) {
+            (Render::Raw, _) => self.0.content.clone(),
+            (Render::Html, Some(html)) => html.clone(),
+            (Render::Html, None) => markdown::render(&self.0.content),
+        }
+    }
+    async fn status(&self) -> PostStatus {
+        self.0.status.into()
+    }
+    /// When the post was or will be published
+    async fn publish_at(&self) -> Option<DateTime<Utc>> {
+        self.0.publish_at
+    }
+    /// Tag names, sorted
+    async fn tags(&self) -> &[String] {
+        &self.0.tags
+    }
+    /// Incremented on every change, the `ETag` of the REST routes
+    async fn version(&self) -> i64 {
+        self.0.version
+    }
+    async fn created_at(&self) -> DateTime<Utc> {
+        self.0.created_at
+    }
+    async fn updated_at(&self) -> DateTime<Utc> {
+        self.0.updated_at
+    }
+
+    /// `None` once the author is deleted.
+    async fn author(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<User>> {
+        let users = ctx.data_unchecked::<DataLoader<UserLoader>>();
+        Ok(users.load_one(self.0.user_id).await?.map(User))
+    }
+}
+
+/// `render` of the REST routes.
+#[derive(Enum, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ContentFormat {
+    #[default]
+    Markdown,
+    Html,
+}
+impl From<ContentFormat> for Render {
+    fn from(format: ContentFormat) -> Self {
+        match format {
+            ContentFormat::Markdown => Render::Raw,
+            ContentFormat::Html => Render::Html,
+        }
+    }
+}
+
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "posts::PostStatus")]
+pub enum PostStatus {
+    Draft,
+    Scheduled,
+    Published,
+    Archived,
+}
+
+/// # UserLoader
+/// Live users by id, in one query per batch.
+pub struct UserLoader {
+    repos: Arc<RepoFactory>,
+}
+
+impl Loader<i64> for UserLoader {
+    type Value = users::Users;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, ids: &[i64]) -> Result<HashMap<i64, users::Users>, Self::Error> {
+        let users = self.repos.user.find_many(ids).await.map_err(gql_error)?;
+        Ok(users.into_iter().map(|user| (user.id, user)).collect())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PostsOf {
+    user_id: i64,
+    include_unpublished: bool,
+}
+
+/// # PostsLoader
+/// Live posts of users, in one query per batch and visibility.
+pub struct PostsLoader {
+    repos: Arc<RepoFactory>,
+}
+
+impl Loader<PostsOf> for PostsLoader {
+    type Value = Vec<posts::Posts>;
+    type Error = async_graphql::Error;
+
+    async fn load(
+        &self,
+        keys: &[PostsOf],
+    ) -> Result<HashMap<PostsOf, Vec<posts::Posts>>, Self::Error> {
+        let mut found = HashMap::new();
+        for include_unpublished in [false, true] {
+            let user_ids: Vec<i64> = keys
+                .iter()
+                .filter(|key| key.include_unpublished == include_unpublished)
+                .map(|key| key.user_id)
+                .collect();
+            if user_ids.is_empty() {
+                continue;
+            }
+            let opts = ReadOptions {
+                include_unpublished,
+                ..Default::default()
+            };
+            let posts = self
+                .repos
+                .posts
+                .select_by_users(&user_ids, opts)
+                .await
+                .map_err(gql_error)?;
+            for post in posts {
+                let key = PostsOf {
+                    user_id: post.user_id,
+                    include_unpublished,
+                };
+                found.entry(key).or_insert_with(Vec::new).push(post);
+            }
+        }
+        Ok(found)
+    }
+}
+
+/// `requested`, once the viewer is checked to be an admin when it is `true`.
+fn admin_only(ctx: &Context<'_>, requested: bool) -> async_graphql::Result<bool> {
+    if requested && !ctx.data_unchecked::<Viewer>().admin {
+        return Err(gql_error(AppError::Unauthorized));
+    }
+    Ok(requested)
+}
+
+/// `err` as a GraphQL error, with the status REST would answer under `extensions.status`.
+fn gql_error(err: impl Into<AppError>) -> async_graphql::Error {
+    let err = err.into();
+    let status = err.status();
+    if status.is_server_error() {
+        error!("Error occur: {}", err);
+    }
+    async_graphql::Error::new(err.to_string())
+        .extend_with(|_, ext| ext.set("status", status.as_u16()))
+}
@@ -0,0 +1,75 @@
+//! # calc
+//! `api.v1.Calc`, over `services::*` like the `calc` routes.
+
+use log::info;
+use tonic::{
+    Request, Response, Status,
+    codegen::tokio_stream::{StreamExt, adapters::Map, wrappers::ReceiverStream},
+};
+
+use super::{
+    proto::{FiboReply, FiboRequest, HanoiMove, HanoiRequest, calc_server::Calc},
+    validate,
+};
+use crate::{
+    services::{CalcError, fibo, hanoi},
+    shutdown::Shutdown,
+    state::AppState,
+};
+
+/// Disks the moves are streamed for, about a million moves.
+/// Past it the REST route only counts them.
+pub const MAX_HANOI_STREAMED: u32 = 20;
+
+pub struct CalcService {
+    cache: fibo::FiboCache,
+    shutdown: Shutdown,
+}
+impl CalcService {
+    pub fn new(state: &AppState) -> Self {
+        Self {
+            cache: state.fibo_cache.clone(),
+            shutdown: state.shutdown.clone(),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Calc for CalcService {
+    async fn fibo(&self, request: Request<FiboRequest>) -> Result<Response<FiboReply>, Status> {
+        let request = request.into_inner();
+        validate(&request)?;
+        info!("gRPC client requests fibonacci {}'th number", request.n);
+        Ok(Response::new(FiboReply {
+            value: fibo::calc_fibo_rec(request.n as usize, &self.cache).to_string(),
+        }))
+    }
+
+    type HanoiStream = Map<ReceiverStream<Result<(u8, u8), CalcError>>, ToMove>;
+
+    async fn hanoi(
+        &self,
+        request: Request<HanoiRequest>,
+    ) -> Result<Response<Self::HanoiStream>, Status> {
+        let request = request.into_inner();
+        validate(&request)?;
+        info!("gRPC client requests hanoi {}'th sequence", request.n);
+        let orders = hanoi::stream_hanoi_rec(request.n as usize, &self.shutdown);
+        Ok(Response::new(
+            ReceiverStream::new(orders).map(to_move as ToMove),
+        ))
+    }
+}
+
+type ToMove = fn(Result<(u8, u8), CalcError>) -> Result<HanoiMove, Status>;
+
+fn to_move(order: Result<(u8, u8), CalcError>) -> Result<HanoiMove, Status> {
+    match order {
+        Ok((from, to)) => Ok(HanoiMove {
+            from: from.into(),
+            to: to.into(),
+        }),
+        // Only on shutdown, the client may retry on another instance
+        Err(err) => Err(Status::unavailable(err.to_string())),
+    }
+}
@@ -0,0 +1,130 @@
+//! # grpc
+//! gRPC services of `proto/api.proto`, for internal callers.
+//! They are merged into the HTTP router, so they share its listener, its audit middleware
+//! and its `X-Auth-Key`, sent as `x-auth-key` metadata.
+//! HTTP/2 is served without TLS (h2c) next to HTTP/1.1.
+//! ## How to try
+//! Reflection is enabled, so grpcurl needs no `.proto` :
+//! ```
+//! grpcurl -plaintext -d '{"n": 3}' localhost:8080 api.v1.Calc/Hanoi
+//! ```
+
+pub mod calc;
+pub mod posts;
+pub mod users;
+
+use axum::{
+    body::Body,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use log::error;
+use tonic::{Code, Status, service::Routes};
+use validator::Validate;
+
+use crate::{error::AppError, format, state::AppState, validation::field_errors};
+
+pub mod proto {
+    tonic::include_proto!("api.v1");
+
+    pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("api_descriptor");
+}
+
+/// Every service, reflection included, to merge into the HTTP router.
+pub fn routes(state: AppState) -> axum::Router {
+    let routes = Routes::new(proto::calc_server::CalcServer::new(calc::CalcService::new(
+        &state,
+    )))
+    .add_service(proto::users_server::UsersServer::new(
+        users::UsersService::new(&state),
+    ))
+    .add_service(proto::posts_server::PostsServer::new(
+        posts::PostsService::new(&state),
+    ));
+    // grpcurl asks for v1alpha, newer clients for v1
+    let reflection = || {
+        tonic_reflection::server::Builder::configure()
+            .register_encoded_file_descriptor_set(proto::FILE_DESCRIPTOR_SET)
+    };
+    let routes = match (reflection().build_v1(), reflection().build_v1alpha()) {
+        (Ok(v1), Ok(v1alpha)) => routes.add_service(v1).add_service(v1alpha),
+        (Err(err), _) | (_, Err(err)) => {
+            error!("gRPC reflection is unavailable : {}", err);
+            routes
+        }
+    };
+    // The fallback of tonic would answer every unknown REST path too
+    routes.prepare().into_axum_router().fallback(not_found)
+}
+
+/// Unimplemented for unknown gRPC methods, 404 for anything else,
+/// rewritten into the `ApiResponse` envelope by `error::fallback`.
+async fn not_found(headers: HeaderMap) -> Response {
+    if format::is_grpc(&headers) {
+        Status::unimplemented("").into_http::<Body>()
+    } else {
+        StatusCode::NOT_FOUND.into_response()
+    }
+}
+
+/// Same meaning as the status REST answers.
+impl From<AppError> for Status {
+    fn from(err: AppError) -> Self {
+        let status = err.status();
+        if status.is_server_error() {
+            error!("Error occur: {}", err);
+        }
+        let code = match status.as_u16() {
+            400 | 422 => Code::InvalidArgument,
+            401 => Code::Unauthenticated,
+            404 => Code::NotFound,
+            409 => Code::AlreadyExists,
+            // The client has to read the resource again, then retry
+            412 => Code::Aborted,
+            428 => Code::FailedPrecondition,
+            _ => Code::Internal,
+        };
+        let message = match err {
+            AppError::Invalid(fields) => fields
+                .iter()
+                .map(|field| format!("{} : {}", field.field, field.message))
+                .collect::<Vec<_>>()
+                .join(", "),
+            AppError::PreconditionRequired => {
+                "Precondition required : send the current version of the resource".to_string()
+            }
+            err => err.to_string(),
+        };
+        Status::new(code, message)
+    }
+}
+
+/// Checks the `#[validate(...)]` attributes `build.rs` puts on requests.
+fn validate<T: Validate>(message: &T) -> Result<(), Status> {
+    message
+        .validate()
+        .map_err(|errors| AppError::Invalid(field_errors(&errors)).into())
+}
+
+/// `version` of a request against the current one, like `If-Match` against the `ETag`.
+/// Versions start at 1, so 0 is a missing field.
+fn check_version(requested: i64, current: i64) -> Result<(), AppError> {
+    match requested {
+        0 => Err(AppError::PreconditionRequired),
+        v if v != current => Err(AppError::PreconditionFailed),
+        _ => Ok(()),
+    }
+}
+
+fn timestamp(at: DateTime<Utc>) -> prost_types::Timestamp {
+    prost_types::Timestamp {
+        seconds: at.timestamp(),
+        nanos: at.timestamp_subsec_nanos() as i32,
+    }
+}
+
+fn datetime(at: prost_types::Timestamp) -> Result<DateTime<Utc>, Status> {
+    DateTime::from_timestamp(at.seconds, at.nanos.try_into().unwrap_or_default())
+        .ok_or_else(|| Status::invalid_argument("timestamp is out of range"))
+}
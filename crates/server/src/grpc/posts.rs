@@ -0,0 +1,181 @@
+//! # posts
+//! `api.v1.Posts`, over `RepoFactory` like the `posts` routes.
+
+use tonic::{Request, Response, Status};
+
+use super::{
+    check_version, datetime,
+    proto::{
+        self, CreatePostRequest, DeleteRequest, EditPostRequest, ListPostsRequest, PostId,
+        PostList, posts_server,
+    },
+    timestamp, validate,
+};
+use crate::{
+    config::Config,
+    error::AppError,
+    prelude::*,
+    repository::{
+        ReadOptions, Repo, RepoFactory, UnitOfWork,
+        posts::{PostStatus, Posts},
+    },
+    routes::posts::{publish_at, visible_to},
+    state::AppState,
+};
+
+pub struct PostsService {
+    repos: Arc<RepoFactory>,
+    config: Arc<Config>,
+}
+impl PostsService {
+    pub fn new(state: &AppState) -> Self {
+        Self {
+            repos: state.repos.clone(),
+            config: state.config.clone(),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl posts_server::Posts for PostsService {
+    async fn get_post(&self, request: Request<PostId>) -> Result<Response<proto::Post>, Status> {
+        let post = self
+            .repos
+            .posts
+            .find(request.into_inner().id, ReadOptions::default())
+            .await
+            .map_err(AppError::from)?
+            .ok_or(AppError::NotFound("Post"))?;
+        Ok(Response::new(post.into()))
+    }
+
+    async fn list_posts(
+        &self,
+        request: Request<ListPostsRequest>,
+    ) -> Result<Response<PostList>, Status> {
+        let criteria = Posts {
+            user_id: request.into_inner().user_id,
+            ..Default::default()
+        };
+        let posts = self
+            .repos
+            .posts
+            .select(&criteria)
+            .await
+            .map_err(AppError::from)?;
+        Ok(Response::new(PostList {
+            posts: posts.into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    async fn create_post(
+        &self,
+        request: Request<CreatePostRequest>,
+    ) -> Result<Response<proto::Post>, Status> {
+        let request = request.into_inner();
+        validate(&request)?;
+        let status = match request.status() {
+            proto::PostStatus::Unspecified | proto::PostStatus::Draft => PostStatus::Draft,
+            proto::PostStatus::Scheduled => PostStatus::Scheduled,
+            proto::PostStatus::Published => PostStatus::Published,
+            proto::PostStatus::Archived => PostStatus::Archived,
+        };
+        let requested = request.publish_at.map(datetime).transpose()?;
+        let publish_at = publish_at(status, requested, None)?;
+        let post = self
+            .repos
+            .transaction(|uow| async move {
+                if uow
+                    .user
+                    .find(request.user_id, ReadOptions::default())
+                    .await?
+                    .is_none()
+                {
+                    return Err(AppError::NotFound("User"));
+                }
+                let row = Posts {
+                    title: request.title,
+                    content: request.content,
+                    user_id: request.user_id,
+                    status,
+                    publish_at,
+                    ..Default::default()
+                };
+                Ok(uow.posts.insert(&row).await?)
+            })
+            .await?;
+        Ok(Response::new(post.into()))
+    }
+
+    async fn edit_post(
+        &self,
+        request: Request<EditPostRequest>,
+    ) -> Result<Response<proto::Post>, Status> {
+        let (metadata, _, request) = request.into_parts();
+        validate(&request)?;
+        let opts = visible_to(&self.config, &metadata.into_headers());
+        let post = self
+            .repos
+            .transaction(|uow| async move {
+                let current = lock_post(&uow, request.id, opts).await?;
+                check_version(request.version, current.version)?;
+                uow.posts
+                    .edit(request.id, request.title, request.content)
+                    .await?;
+                lock_post(&uow, request.id, opts).await
+            })
+            .await?;
+        Ok(Response::new(post.into()))
+    }
+
+    async fn delete_post(&self, request: Request<DeleteRequest>) -> Result<Response<()>, Status> {
+        let (metadata, _, request) = request.into_parts();
+        let opts = visible_to(&self.config, &metadata.into_headers());
+        self.repos
+            .transaction(|uow| async move {
+                let current = lock_post(&uow, request.id, opts).await?;
+                check_version(request.version, current.version)?;
+                uow.posts.delete(&current).await?;
+                Ok(())
+            })
+            .await?;
+        Ok(Response::new(()))
+    }
+}
+
+/// Live post as `opts` allow, locked until the transaction ends so its version cannot change under us.
+async fn lock_post(uow: &UnitOfWork, id: i64, opts: ReadOptions) -> Result<Posts, AppError> {
+    let opts = ReadOptions { lock: true, ..opts };
+    uow.posts
+        .find(id, opts)
+        .await?
+        .ok_or(AppError::NotFound("Post"))
+}
+
+impl From<Posts> for proto::Post {
+    fn from(post: Posts) -> Self {
+        Self {
+            id: post.id,
+            title: post.title,
+            content: post.content,
+            user_id: post.user_id,
+            status: proto::PostStatus::from(post.status).into(),
+            publish_at: post.publish_at.map(timestamp),
+            tags: post.tags,
+            version: post.version,
+            created_at: Some(timestamp(post.created_at)),
+            updated_at: Some(timestamp(post.updated_at)),
+        }
+    }
+}
+
+impl From<PostStatus> for proto::PostStatus {
+    fn from(status: PostStatus) -> Self {
+        match status {
+            PostStatus::Draft => Self::Draft,
+            PostStatus::Scheduled => Self::Scheduled,
+            PostStatus::Published => Self::Published,
+            PostStatus::Archived => Self::Archived,
+        }
+    }
+}
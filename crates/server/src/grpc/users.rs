@@ -0,0 +1,114 @@
+//! # users
+//! `api.v1.Users`, over `RepoFactory` like the `users` routes.
+
+use tonic::{Request, Response, Status};
+
+use super::{
+    check_version,
+    proto::{self, CreateUserRequest, DeleteRequest, RenameUserRequest, UserId, users_server},
+    timestamp, validate,
+};
+use crate::{
+    error::AppError,
+    prelude::*,
+    repository::{ReadOptions, Repo, RepoFactory, UnitOfWork, users::Users},
+    state::AppState,
+};
+
+pub struct UsersService {
+    repos: Arc<RepoFactory>,
+}
+impl UsersService {
+    pub fn new(state: &AppState) -> Self {
+        Self {
+            repos: state.repos.clone(),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl users_server::Users for UsersService {
+    async fn get_user(&self, request: Request<UserId>) -> Result<Response<proto::User>, Status> {
+        let user = self
+            .repos
+            .user
+            .find(request.into_inner().id, ReadOptions::default())
+            .await
+            .map_err(AppError::from)?
+            .ok_or(AppError::NotFound("User"))?;
+        Ok(Response::new(user.into()))
+    }
+
+    async fn create_user(
+        &self,
+        request: Request<CreateUserRequest>,
+    ) -> Result<Response<proto::User>, Status> {
+        let request = request.into_inner();
+        validate(&request)?;
+        let row = Users {
+            name: request.name,
+            ..Default::default()
+        };
+        let user = self.repos.user.insert(&row).await.map_err(AppError::from)?;
+        Ok(Response::new(user.into()))
+    }
+
+    async fn rename_user(
+        &self,
+        request: Request<RenameUserRequest>,
+    ) -> Result<Response<proto::User>, Status> {
+        let request = request.into_inner();
+        validate(&request)?;
+        let user = self
+            .repos
+            .transaction(|uow| async move {
+                let current = lock_user(&uow, request.id).await?;
+                check_version(request.version, current.version)?;
+                uow.user.rename(request.id, request.name).await?;
+                uow.user
+                    .find(request.id, ReadOptions::default())
+                    .await?
+                    .ok_or(AppError::NotFound("User"))
+            })
+            .await?;
+        Ok(Response::new(user.into()))
+    }
+
+    async fn delete_user(&self, request: Request<DeleteRequest>) -> Result<Response<()>, Status> {
+        let request = request.into_inner();
+        self.repos
+            .transaction(|uow| async move {
+                let current = lock_user(&uow, request.id).await?;
+                check_version(request.version, current.version)?;
+                uow.posts.delete_by_user(request.id).await?;
+                uow.user.delete(&current).await?;
+                Ok(())
+            })
+            .await?;
+        Ok(Response::new(()))
+    }
+}
+
+/// Live user, locked until the transaction ends so its version cannot change under us.
+async fn lock_user(uow: &UnitOfWork, id: i64) -> Result<Users, AppError> {
+    let opts = ReadOptions {
+        lock: true,
+        ..Default::default()
+    };
+    uow.user
+        .find(id, opts)
+        .await?
+        .ok_or(AppError::NotFound("User"))
+}
+
+impl From<Users> for proto::User {
+    fn from(user: Users) -> Self {
+        Self {
+            id: user.id,
+            name: user.name,
+            version: user.version,
+            created_at: Some(timestamp(user.created_at)),
+            updated_at: Some(timestamp(user.updated_at)),
+        }
+    }
+}
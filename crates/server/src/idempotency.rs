@@ -0,0 +1,310 @@
+//! # idempotency
+//! Safe retries of `POST` requests sent with an `Idempotency-Key` header.
+//! The middleware [`replay`] runs the first request with a key and stores its response
+//! for `IDEMPOTENCY_TTL_HOURS`, retries get the stored response instead of writing twice.
+//! Keys are scoped by actor, `admin` or `anonymous` (see `audit.rs`), and by the request itself :
+//! the API knows no other caller, so a response is only replayed to a client sending
+//! the very same method, path and body. The same key with another request runs that request.
+//! Send unique keys such as UUIDs.
+//! gRPC calls are left alone, their trailers cannot be stored.
+//! ## Answers
+//! - stored response, with `Idempotent-Replayed: true`, for a retry of the same request
+//! - 409 while the first request is still running
+//!
+//! 5xx responses are not stored, so the next retry runs the request again.
+//! Neither are responses over 1 MiB or of unknown length, e.g. streams.
+//! A running request renews its key every [`LEASE`] / 3, one cut short (e.g. the client left)
+//! holds it for a minute at most.
+
+use std::time::Duration;
+
+use aide::openapi::{
+    HeaderStyle, OpenApi, Parameter, ParameterData, ParameterSchemaOrContent, ReferenceOr,
+    SchemaObject,
+};
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    extract::{Request, State},
+    http::{HeaderName, HeaderValue, Method, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use log::error;
+use tokio::time::Instant;
+
+use crate::{
+    audit,
+    error::AppError,
+    format,
+    repository::idempotency::{Claim, StoredResponse},
+    state::AppState,
+};
+
+pub const KEY_HEADER: &str = "Idempotency-Key";
+pub const REPLAYED_HEADER: &str = "Idempotent-Replayed";
+/// Longest key accepted, UUIDs fit many times over.
+const MAX_KEY_LEN: usize = 255;
+/// Bodies read to fingerprint a request, like the default limit of axum extractors.
+const MAX_BODY: usize = 2 * 1024 * 1024;
+/// Largest response stored, larger ones are answered without being stored.
+const MAX_STORED: u64 = 1024 * 1024;
+/// Time a running request holds its key, renewed until it answers.
+const LEASE: Duration = Duration::from_secs(60);
+
+/// Middleware replaying the response of `POST` requests sent again with the same `Idempotency-Key`.
+/// Requests without the header, with another method or over gRPC, run as usual.
+pub async fn replay(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    if req.method() != Method::POST || format::is_grpc(req.headers()) {
+        return next.run(req).await;
+    }
+    let Some(key) = req.headers().get(KEY_HEADER).cloned() else {
+        return next.run(req).await;
+    };
+    run_once(&state, key, req, next)
+        .await
+        .unwrap_or_else(IntoResponse::into_response)
+}
+
+async fn run_once(
+    state: &AppState,
+    key: HeaderValue,
+    req: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let key = key
+        .to_str()
+        .ok()
+        .filter(|key| (1..=MAX_KEY_LEN).contains(&key.len()))
+        .ok_or_else(|| {
+            AppError::BadRequest(format!(
+                "{} must be 1 to {} visible ASCII characters",
+                KEY_HEADER, MAX_KEY_LEN
+            ))
+        })?
+        .to_string();
+    let (parts, body) = req.into_parts();
+    let body = axum::body::to_bytes(body, MAX_BODY)
+        .await
+        .map_err(|_| AppError::Rejected {
+            status: StatusCode::PAYLOAD_TOO_LARGE,
+            reason: "Payload too large : the request body is over 2 MiB or could not be read",
+            fields: Vec::new(),
+        })?;
+    let actor = audit::current().actor;
+    let repo = &state.repos.idempotency;
+    let request = fingerprint(&parts, &body);
+    match repo.claim(&actor, &key, &request, LEASE).await? {
+        Claim::Claimed => (),
+        Claim::Answered(stored) => return Ok(restore(stored)),
+        Claim::Running => {
+            return Err(AppError::Rejected {
+                status: StatusCode::CONFLICT,
+                reason: "Conflict : the first request with this Idempotency-Key is still running",
+                fields: Vec::new(),
+            });
+        }
+    }
+
+    let run = next.run(Request::from_parts(parts, Body::from(body)));
+    tokio::pin!(run);
+    let mut renew = tokio::time::interval_at(Instant::now() + LEASE / 3, LEASE / 3);
+    let res = loop {
+        tokio::select! {
+            res = &mut run => break res,
+            _ = renew.tick() => {
+                if let Err(err) = repo.extend(&actor, &key, &request, LEASE).await {
+                    error!("Fail to renew idempotency key : {}", err);
+                }
+            }
+        }
+    };
+    let storable = res
+        .body()
+        .size_hint()
+        .exact()
+        .is_some_and(|size| size <= MAX_STORED);
+    if res.status().is_server_error() || !storable {
+        if let Err(err) = repo.release(&actor, &key, &request).await {
+            error!("Fail to release idempotency key : {}", err);
+        }
+        return Ok(res);
+    }
+    let (parts, body) = res.into_parts();
+    let body = match axum::body::to_bytes(body, MAX_STORED as usize).await {
+        Ok(body) => body,
+        Err(err) => {
+            error!("Fail to read the response to store : {}", err);
+            if let Err(err) = repo.release(&actor, &key, &request).await {
+                error!("Fail to release idempotency key : {}", err);
+            }
+            return Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+        }
+    };
+    let stored = StoredResponse {
+        status: parts.status.as_u16(),
+        headers: parts
+            .headers
+            .iter()
+            .filter(|(name, _)| {
+                *name != header::CONTENT_LENGTH && *name != header::TRANSFER_ENCODING
+            })
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect(),
+        body: body.to_vec(),
+    };
+    if let Err(err) = repo
+        .complete(
+            &actor,
+            &key,
+            &request,
+            &stored,
+            state.config.idempotency.ttl,
+        )
+        .await
+    {
+        // The response still goes out, a retry will find the key running until the lease ends.
+        error!("Fail to store idempotent response : {}", err);
+    }
+    Ok(Response::from_parts(parts, Body::from(body)))
+}
+
+/// What tells two requests apart : method, path and query, body type and body.
+fn fingerprint(parts: &axum::http::request::Parts, body: &Bytes) -> Vec<u8> {
+    let content_type = parts
+        .headers
+        .get(header::CONTENT_TYPE)
+        .map(HeaderValue::as_bytes)
+        .unwrap_or_default();
+    let target = parts
+        .uri
+        .path_and_query()
+        .map(|target| target.as_str())
+        .unwrap_or_default();
+    [
+        parts.method.as_str().as_bytes(),
+        b" ",
+        target.as_bytes(),
+        b"\n",
+        content_type,
+        b"\n",
+        body,
+    ]
+    .concat()
+}
+
+fn restore(stored: StoredResponse) -> Response {
+    let mut res = Response::new(Body::from(stored.body));
+    *res.status_mut() = StatusCode::from_u16(stored.status).unwrap_or(StatusCode::OK);
+    for (name, value) in stored.headers {
+        if let (Ok(name), Ok(value)) = (HeaderName::try_from(name), HeaderValue::try_from(value)) {
+            res.headers_mut().append(name, value);
+        }
+    }
+    res.headers_mut()
+        .insert(REPLAYED_HEADER, HeaderValue::from_static("true"));
+    res
+}
+
+/// Documents `Idempotency-Key` on every `POST` operation of `api`.
+pub fn document(api: &mut OpenApi) {
+    let Some(paths) = api.paths.as_mut() else {
+        return;
+    };
+    let operations = paths.paths.values_mut().filter_map(|item| match item {
+        ReferenceOr::Item(item) => item.post.as_mut(),
+        ReferenceOr::Reference { .. } => None,
+    });
+    for operation in operations {
+        operation.parameters.push(ReferenceOr::Item(Parameter::Header {
+            parameter_data: ParameterData {
+                name: KEY_HEADER.to_string(),
+                description: Some(format!(
+                    "Unique key of the request, e.g. a UUID. Retries with the same key, path and body \
+                     get the first response, with `{}: true`",
+                    REPLAYED_HEADER
+                )),
+                required: false,
+                deprecated: None,
+                format: ParameterSchemaOrContent::Schema(SchemaObject {
+                    json_schema: schemars::json_schema!({
+                        "type": "string",
+                        "minLength": 1,
+                        "maxLength": MAX_KEY_LEN,
+                    }),
+                    example: None,
+                    external_docs: None,
+                }),
+                example: None,
+                examples: Default::default(),
+                explode: None,
+                extensions: Default::default(),
+            },
+            style: HeaderStyle::Simple,
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parts(method: Method, uri: &str, content_type: Option<&str>) -> axum::http::request::Parts {
+        let mut req = Request::builder().method(method).uri(uri);
+        if let Some(content_type) = content_type {
+            req = req.header(header::CONTENT_TYPE, content_type);
+        }
+        req.body(()).unwrap().into_parts().0
+    }
+
+    #[test]
+    fn fingerprint_holds_method_target_type_and_body() {
+        let parts = parts(
+            Method::POST,
+            "http://localhost/v2/posts?draft=1",
+            Some("application/json"),
+        );
+        assert_eq!(
+            fingerprint(&parts, &Bytes::from_static(b"{}")),
+            b"POST /v2/posts?draft=1\napplication/json\n{}".to_vec()
+        );
+    }
+
+    #[test]
+    fn fingerprint_tells_requests_apart() {
+        let body = Bytes::from_static(b"{}");
+        let base = fingerprint(&parts(Method::POST, "/a", Some("application/json")), &body);
+        let others = [
+            fingerprint(&parts(Method::PUT, "/a", Some("application/json")), &body),
+            fingerprint(&parts(Method::POST, "/b", Some("application/json")), &body),
+            fingerprint(
+                &parts(Method::POST, "/a?x=1", Some("application/json")),
+                &body,
+            ),
+            fingerprint(&parts(Method::POST, "/a", Some("application/cbor")), &body),
+            fingerprint(&parts(Method::POST, "/a", None), &body),
+            fingerprint(
+                &parts(Method::POST, "/a", Some("application/json")),
+                &Bytes::from_static(b"[]"),
+            ),
+        ];
+        for other in others {
+            assert_ne!(base, other);
+        }
+        assert_eq!(
+            base,
+            fingerprint(&parts(Method::POST, "/a", Some("application/json")), &body)
+        );
+    }
+
+    #[test]
+    fn restore_marks_replays() {
+        let res = restore(StoredResponse {
+            status: 201,
+            headers: vec![("content-type".to_string(), "application/json".to_string())],
+            body: b"{}".to_vec(),
+        });
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(res.headers()[header::CONTENT_TYPE], "application/json");
+        assert_eq!(res.headers()[REPLAYED_HEADER], "true");
+    }
+}
@@ -0,0 +1,143 @@
+//! # listener
+//! Binds the socket the server accepts connections on.
+//! Supports plain TCP, Unix domain sockets and sockets inherited
+//! from systemd socket activation (`LISTEN_FDS`).
+
+use anyhow::{Context, Result};
+use log::{debug, info};
+use tokio::net::TcpListener;
+
+use crate::config::ListenConfig;
+
+pub enum ServerListener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener),
+}
+impl ServerListener {
+    /// Picks a listener in order of precedence:
+    /// systemd socket, Unix domain socket, TCP address.
+    pub async fn bind(config: &ListenConfig) -> Result<Self> {
+        #[cfg(unix)]
+        {
+            if let Some(listener) = systemd::take_listener()? {
+                info!("Using socket passed by systemd");
+                return Ok(listener);
+            }
+            if let Some(unix) = &config.unix_socket {
+                return unix::bind(&unix.path, unix.mode);
+            }
+        }
+        let listener = TcpListener::bind(config.tcp_addr)
+            .await
+            .with_context(|| format!("Fail to bind {}", config.tcp_addr))?;
+        Ok(Self::Tcp(listener))
+    }
+
+    /// Human readable address for logging.
+    pub fn describe(&self) -> String {
+        match self {
+            Self::Tcp(l) => match l.local_addr() {
+                Ok(addr) => format!("http://{}", addr),
+                Err(..) => "tcp socket".to_string(),
+            },
+            #[cfg(unix)]
+            Self::Unix(l) => match l
+                .local_addr()
+                .ok()
+                .and_then(|a| a.as_pathname().map(|p| p.display().to_string()))
+            {
+                Some(path) => format!("unix:{}", path),
+                None => "unix socket".to_string(),
+            },
+        }
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::{fs, os::unix::fs::FileTypeExt, path::Path};
+
+    use super::*;
+
+    pub fn bind(path: &Path, mode: u32) -> Result<ServerListener> {
+        // A socket file left behind by a previous run blocks `bind`, anything else is kept.
+        match fs::symlink_metadata(path) {
+            Ok(meta) if meta.file_type().is_socket() => {
+                debug!("Removing stale socket {}", path.display());
+                fs::remove_file(path)
+                    .with_context(|| format!("Fail to remove stale socket {}", path.display()))?;
+            }
+            Ok(..) => anyhow::bail!(
+                "{} already exists and is not a socket, refusing to replace it",
+                path.display()
+            ),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+            Err(err) => {
+                return Err(err).with_context(|| format!("Fail to inspect {}", path.display()));
+            }
+        }
+        // The socket is created with `mode` already, so it is never reachable with looser permissions.
+        let listener = with_umask(!mode & 0o777, || tokio::net::UnixListener::bind(path))
+            .with_context(|| format!("Fail to bind {}", path.display()))?;
+        Ok(ServerListener::Unix(listener))
+    }
+
+    /// Runs `f` with the process umask set to `mask`, then puts the previous one back.
+    /// Only called on start, before anything else creates files.
+    fn with_umask<T>(mask: u32, f: impl FnOnce() -> T) -> T {
+        // SAFETY: `umask` only swaps the mask of the process and cannot fail.
+        let previous = unsafe { libc::umask(mask as libc::mode_t) };
+        let out = f();
+        // SAFETY: as above.
+        unsafe { libc::umask(previous) };
+        out
+    }
+}
+
+#[cfg(unix)]
+mod systemd {
+    use std::os::fd::{FromRawFd, IntoRawFd, RawFd};
+
+    use super::*;
+
+    /// First descriptor passed by systemd, see `sd_listen_fds(3)`.
+    const SD_LISTEN_FDS_START: RawFd = 3;
+
+    /// Returns the first socket passed by systemd, if this process was socket activated.
+    pub fn take_listener() -> Result<Option<ServerListener>> {
+        let for_us = std::env::var("LISTEN_PID")
+            .ok()
+            .and_then(|pid| pid.parse::<u32>().ok())
+            .is_some_and(|pid| pid == std::process::id());
+        let num_fds = std::env::var("LISTEN_FDS")
+            .ok()
+            .and_then(|n| n.parse::<usize>().ok())
+            .unwrap_or(0);
+        if !for_us || num_fds == 0 {
+            return Ok(None);
+        }
+        if num_fds > 1 {
+            info!("systemd passed {} sockets, only the first is used", num_fds);
+        }
+
+        // SAFETY: systemd guarantees the descriptor is open and owned by this process
+        // when LISTEN_PID matches, and nothing else in the process takes it.
+        let tcp = unsafe { std::net::TcpListener::from_raw_fd(SD_LISTEN_FDS_START) };
+        if tcp.local_addr().is_ok() {
+            tcp.set_nonblocking(true)?;
+            return Ok(Some(ServerListener::Tcp(TcpListener::from_std(tcp)?)));
+        }
+
+        // Not an inet socket, so it should be a Unix one.
+        let fd = tcp.into_raw_fd();
+        // SAFETY: ownership of the same descriptor is moved over from `tcp` above.
+        let unix = unsafe { std::os::unix::net::UnixListener::from_raw_fd(fd) };
+        unix.local_addr()
+            .context("Socket passed by systemd is neither TCP nor Unix")?;
+        unix.set_nonblocking(true)?;
+        Ok(Some(ServerListener::Unix(
+            tokio::net::UnixListener::from_std(unix)?,
+        )))
+    }
+}
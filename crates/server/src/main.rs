@@ -1,19 +1,45 @@
-use aide::{
-    axum::{IntoApiResponse, routing::get},
-    openapi::OpenApi,
-    transform::TransformOpenApi,
-};
+#[cfg(feature = "docs")]
+use aide::axum::{IntoApiResponse, routing::get};
+use aide::{openapi::OpenApi, transform::TransformOpenApi};
 use anyhow::Result;
-use axum::{Extension, Json};
-use log::{debug, error, info};
+#[cfg(feature = "docs")]
+use axum::{Json, extract::State};
+use axum::{middleware, serve::Listener};
+use log::{debug, error, info, warn};
 use sqlx::PgPool;
 
+use listener::ServerListener;
 use prelude::*;
+use shutdown::Shutdown;
+#[cfg(feature = "docs")]
+use state::ApiDoc;
+use state::AppState;
 
+mod audit;
+mod auth;
+mod config;
+mod error;
+mod etag;
+#[cfg(feature = "events")]
+mod events;
+mod extract;
+mod format;
+#[cfg(feature = "graphql")]
+mod graphql;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod idempotency;
+mod listener;
+mod markdown;
 mod prelude;
 mod repository;
-mod services;
 mod routes;
+#[cfg(feature = "calc")]
+mod services;
+mod shutdown;
+mod state;
+mod tasks;
+mod validation;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -23,45 +49,149 @@ async fn main() -> Result<()> {
     // Load database
     debug!("Loading env variables");
     dotenv::dotenv().ok();
-    let database_url = match std::env::var("DATABASE_URL") {
-        Ok(v) => v,
-        Err(err) => {
-            error!("Fail to load DATABASE_URL from .env : {}", err);
-            panic!()
-        }
-    };
-    debug!("Complete to load variable DATABASE_URL");
-    let pool = PgPool::connect(&database_url).await?;
-    let state = Arc::new(repository::RepoFactory::new(pool));
+    let config = config::Config::from_env()?;
+    debug!("Complete to load env variables");
+    let pool = PgPool::connect(&config.database_url).await?;
     debug!("Succesfully connect to Database");
+    let shutdown = Shutdown::new(config.shutdown.clone());
+    tokio::spawn(shutdown.clone().watch_signals());
+    let state = AppState::new(config, repository::RepoFactory::new(pool.clone()), shutdown);
+
+    tokio::spawn(audit::with_context(
+        audit::AuditContext::system("purge"),
+        tasks::purge::run(
+            state.repos.clone(),
+            state.config.soft_delete.clone(),
+            state.shutdown.clone(),
+        ),
+    ));
+    tokio::spawn(audit::with_context(
+        audit::AuditContext::system("publish"),
+        tasks::publish::run(
+            state.repos.clone(),
+            state.config.publish.clone(),
+            state.shutdown.clone(),
+        ),
+    ));
+    tokio::spawn(tasks::outbox::run(
+        pool.clone(),
+        state.repos.clone(),
+        state.config.outbox.clone(),
+        state.shutdown.clone(),
+    ));
+    #[cfg(feature = "webhooks")]
+    if state.config.routes.is_enabled("webhooks") {
+        tokio::spawn(audit::with_context(
+            audit::AuditContext::system("webhooks"),
+            tasks::webhooks::run(
+                state.repos.clone(),
+                state.config.webhooks.clone(),
+                state.shutdown.clone(),
+            ),
+        ));
+    }
+    #[cfg(feature = "events")]
+    if state.config.routes.is_enabled("events") {
+        tokio::spawn(tasks::events::run(
+            pool.clone(),
+            state.events.clone(),
+            state.shutdown.clone(),
+        ));
+    }
+    if state.config.admin_key.is_none() {
+        warn!("ADMIN_API_KEY is not set, admin routes will reject every request");
+    }
 
     // Build application with all routes
+    if !state.config.routes.disabled.is_empty() {
+        info!(
+            "Route modules disabled : {}",
+            state.config.routes.disabled.join(", ")
+        );
+    }
     let (app, api) = routes::apis::route_settings(state.clone());
-    let app = app
-        .nest_api_service("/docs", routes::apis::docs_routes(state.clone()))
-        .route("/full_api.json", get(serve_api));
-    run_server(app, api).await?;
+    #[cfg(feature = "docs")]
+    let app = if state.config.routes.is_enabled("docs") {
+        app.nest_api_service("/docs", routes::apis::docs_routes(state.clone()))
+            .route(
+                "/full_api.json",
+                get(serve_api).with_state(state.api_doc.clone()),
+            )
+    } else {
+        app
+    };
+    #[cfg(feature = "grpc")]
+    let app = if state.config.routes.is_enabled("grpc") {
+        app.merge(grpc::routes(state.clone()))
+    } else {
+        app
+    };
+    let graceful = run_server(app, api, state).await?;
 
+    debug!("Closing database pool");
+    pool.close().await;
+    if !graceful {
+        // Blocking jobs that ignore cancellation would keep the runtime alive.
+        error!("Forcing exit");
+        std::process::exit(1);
+    }
+    info!("Server closed gracefully");
     Ok(())
 }
 
-async fn run_server(app: ApiRouter, mut api: OpenApi) -> Result<()> {
-    let listener = tokio::net::TcpListener::bind(&"0.0.0.0:8080").await?;
-    info!("Server listening on http://0.0.0.0:8080");
-    match axum::serve(
-        listener,
-        app.finish_api_with(&mut api, api_docs)
-            .layer(Extension(std::sync::Arc::new(api)))
-            .into_make_service(),
-    )
-    .with_graceful_shutdown(wait_for_signal())
-    .await
-    {
-        Ok(..) => (),
-        Err(err) => error!("Error occur while shutting down : {}", err),
+/// Serves until shutdown completes.
+/// Returns `false` when in-flight work did not finish within the shutdown timeout.
+async fn run_server(app: ApiRouter, mut api: OpenApi, state: AppState) -> Result<bool> {
+    let listener = ServerListener::bind(&state.config.listen).await?;
+    info!("Server listening on {}", listener.describe());
+    let app = app
+        .finish_api_with(&mut api, api_docs)
+        .layer(middleware::from_fn(error::fallback))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            idempotency::replay,
+        ))
+        .layer(middleware::from_fn(format::negotiate))
+        .layer(middleware::from_fn_with_state(
+            state.config.clone(),
+            audit::scope,
+        ));
+    idempotency::document(&mut api);
+    state.api_doc.set(api, &routes::apis::VERSIONS);
+    let shutdown = state.shutdown.clone();
+    let mut server = match listener {
+        ServerListener::Tcp(l) => tokio::spawn(serve(l, app, shutdown.clone())),
+        #[cfg(unix)]
+        ServerListener::Unix(l) => tokio::spawn(serve(l, app, shutdown.clone())),
     };
-    info!("Server closed gracefully");
-    Ok(())
+
+    tokio::select! {
+        served = &mut server => {
+            // The server stopped on its own, without a shutdown being requested.
+            served??;
+            return Ok(true);
+        }
+        _ = shutdown.stopped() => (),
+    }
+    Ok(shutdown
+        .finish(async {
+            match server.await {
+                Ok(Ok(..)) => (),
+                Ok(Err(err)) => error!("Error occur while shutting down : {}", err),
+                Err(err) => error!("Server task failed : {}", err),
+            }
+        })
+        .await)
+}
+
+async fn serve<L>(listener: L, app: axum::Router, shutdown: Shutdown) -> std::io::Result<()>
+where
+    L: Listener,
+    L::Addr: std::fmt::Debug,
+{
+    axum::serve(listener, app.into_make_service())
+        .with_graceful_shutdown(async move { shutdown.stopped().await })
+        .await
 }
 
 fn api_docs(api: TransformOpenApi) -> TransformOpenApi {
@@ -71,8 +201,8 @@ fn api_docs(api: TransformOpenApi) -> TransformOpenApi {
             "ApiKey",
             aide::openapi::SecurityScheme::ApiKey {
                 location: aide::openapi::ApiKeyLocation::Header,
-                name: "X-Auth-Key".into(),
-                description: Some("A key that is ignored.".to_string()),
+                name: auth::AUTH_HEADER.into(),
+                description: Some("Admin key, set with `ADMIN_API_KEY`.".to_string()),
                 extensions: Default::default(),
             },
         )
@@ -92,32 +222,8 @@ fn init_logger() {
     }
 }
 
-/// Signal handler for graceful shutdown
-/// Essential for container environments
-async fn wait_for_signal() {
-    #[cfg(unix)]
-    {
-        use tokio::signal::unix::{SignalKind, signal};
-        let mut sigterm = signal(SignalKind::terminate()).unwrap();
-        let mut sigint = signal(SignalKind::interrupt()).unwrap();
-        tokio::select! {
-            _ = sigterm.recv() => info!("Received SIGTERM"),
-            _ = sigint.recv() => {println!(); info!("Received SIGINT")},
-        }
-    }
-
-    #[cfg(windows)]
-    {
-        use tokio::signal;
-        let _ = signal::ctrl_c().await;
-        println!();
-        info!("Received Ctrl+C");
-    }
-}
-
-// Note that this clones the document on each request.
-// To be more efficient, we could wrap it into an Arc,
-// or even store it as a serialized string.
-async fn serve_api(Extension(api): Extension<OpenApi>) -> impl IntoApiResponse {
+#[cfg(feature = "docs")]
+async fn serve_api(State(api): State<ApiDoc>) -> impl IntoApiResponse {
+    let api = api.get();
     Json(api)
 }
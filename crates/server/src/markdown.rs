@@ -0,0 +1,188 @@
+//! # markdown
+//! Renders post content, written in Markdown, to HTML safe to embed in a page.
+//! ## Output
+//! - Headings get an `id` made from their text, for `#anchor` links
+//! - Every `id`, footnotes included, is prefixed with [`ID_PREFIX`] and so are `#anchor` links,
+//!   so a post cannot take over ids of the page embedding it
+//! - Fenced code blocks keep a `language-*` class, for client-side highlighters
+//! - Everything else goes through `ammonia`, so raw HTML cannot run scripts
+
+use std::{borrow::Cow, collections::HashSet, sync::LazyLock};
+
+use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd, html};
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+/// Prefix of every `id` in rendered posts, as GitHub does.
+pub const ID_PREFIX: &str = "user-content-";
+
+static SANITIZER: LazyLock<ammonia::Builder<'static>> = LazyLock::new(|| {
+    let mut builder = ammonia::Builder::default();
+    builder
+        .add_tag_attributes("code", ["class"])
+        .attribute_filter(|element, attribute, value| match (element, attribute) {
+            ("code", "class") if !value.starts_with("language-") => None,
+            (_, "id") => Some(format!("{}{}", ID_PREFIX, value).into()),
+            ("a", "href") => match value.strip_prefix('#') {
+                Some(anchor) => Some(format!("#{}{}", ID_PREFIX, anchor).into()),
+                None => Some(Cow::Borrowed(value)),
+            },
+            _ => Some(Cow::Borrowed(value)),
+        });
+    // `div` holds footnote definitions
+    for element in ["h1", "h2", "h3", "h4", "h5", "h6", "div"] {
+        builder.add_tag_attributes(element, ["id"]);
+    }
+    builder
+});
+
+/// How a post's content is answered.
+#[derive(Deserialize, JsonSchema, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Render {
+    /// Markdown as written
+    #[default]
+    Raw,
+    /// Sanitized HTML
+    Html,
+}
+
+pub fn render(markdown: &str) -> String {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_FOOTNOTES;
+    let mut events: Vec<Event> = Parser::new_ext(markdown, options).collect();
+    add_heading_ids(&mut events);
+
+    let mut unsafe_html = String::with_capacity(markdown.len() * 3 / 2);
+    html::push_html(&mut unsafe_html, events.into_iter());
+    SANITIZER.clean(&unsafe_html).to_string()
+}
+
+/// Sets the `id` of every heading to the slug of its text, suffixed with `-1`, `-2`... when taken.
+fn add_heading_ids(events: &mut [Event]) {
+    let mut seen: HashSet<String> = HashSet::new();
+    for start in 0..events.len() {
+        if !matches!(events[start], Event::Start(Tag::Heading { .. })) {
+            continue;
+        }
+        let text: String = events[start + 1..]
+            .iter()
+            .take_while(|e| !matches!(e, Event::End(TagEnd::Heading(..))))
+            .filter_map(|e| match e {
+                Event::Text(t) | Event::Code(t) => Some(t.as_ref()),
+                _ => None,
+            })
+            .collect();
+        let base = slugify(&text);
+        // A heading may be named like the suffixed slug of another, e.g. `# A` `# A` `# A-1`
+        let mut slug = base.clone();
+        let mut n = 0;
+        while !seen.insert(slug.clone()) {
+            n += 1;
+            slug = format!("{}-{}", base, n);
+        }
+        if let Event::Start(Tag::Heading { id, .. }) = &mut events[start] {
+            *id = Some(slug.into());
+        }
+    }
+}
+
+/// `"Hello, World!"` becomes `"hello-world"`.
+fn slugify(text: &str) -> String {
+    let mut slug = String::with_capacity(text.len());
+    for c in text.trim().chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if (c.is_whitespace() || c == '-') && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = slug.trim_matches('-');
+    if slug.is_empty() {
+        "section".to_string()
+    } else {
+        slug.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_scripts() {
+        let html = render("hello <script>alert(1)</script>\n\n<script>alert(2)</script>");
+        assert!(!html.contains("script"), "{}", html);
+        assert!(!html.contains("alert"), "{}", html);
+    }
+
+    #[test]
+    fn drops_javascript_links() {
+        for markdown in [
+            "[click](javascript:alert(1))",
+            "<a href=\"javascript:alert(1)\">click</a>",
+            "<a href=\"JaVaScRiPt:alert(1)\">click</a>",
+        ] {
+            let html = render(markdown);
+            assert!(!html.to_lowercase().contains("javascript"), "{}", html);
+        }
+    }
+
+    #[test]
+    fn keeps_http_links() {
+        let html = render("[site](https://example.com)");
+        assert!(html.contains("href=\"https://example.com\""), "{}", html);
+    }
+
+    #[test]
+    fn drops_event_handlers() {
+        let html =
+            render("<img src=\"x.png\" onerror=\"alert(1)\"> <p onclick=\"alert(1)\">hi</p>");
+        assert!(!html.contains("onerror"), "{}", html);
+        assert!(!html.contains("onclick"), "{}", html);
+        assert!(!html.contains("alert"), "{}", html);
+    }
+
+    #[test]
+    fn keeps_language_classes_of_code_only() {
+        let html = render("```rust\nfn main() {}\n```");
+        assert!(html.contains("<code class=\"language-rust\">"), "{}", html);
+
+        let html = render("<code class=\"evil\">x</code> <p class=\"language-rust\">y</p>");
+        assert!(!html.contains("class"), "{}", html);
+    }
+
+    #[test]
+    fn gives_headings_unique_ids() {
+        let html = render("# Intro\n\n# Intro\n\n# Intro 1\n\n# Intro");
+        assert_eq!(html.matches("<h1 id=").count(), 4, "{}", html);
+        for id in ["intro", "intro-1", "intro-1-1", "intro-2"] {
+            let id = format!("<h1 id=\"{}{}\">", ID_PREFIX, id);
+            assert!(html.contains(&id), "{}", html);
+        }
+    }
+
+    #[test]
+    fn prefixes_ids_and_anchor_links() {
+        let html = render("<h2 id=\"app\">x</h2> <p id=\"main\">y</p>\n\n[top](#intro)");
+        assert!(html.contains("<h2 id=\"user-content-app\">"), "{}", html);
+        assert!(!html.contains("main"), "{}", html);
+        assert!(html.contains("href=\"#user-content-intro\""), "{}", html);
+    }
+
+    #[test]
+    fn links_footnotes_to_their_definitions() {
+        let html = render("Text[^note].\n\n[^note]: The note");
+        assert!(html.contains("href=\"#user-content-note\""), "{}", html);
+        assert!(html.contains("<div id=\"user-content-note\">"), "{}", html);
+    }
+
+    #[test]
+    fn slugifies_heading_text() {
+        assert_eq!(slugify("Hello, World!"), "hello-world");
+        assert_eq!(slugify("  --a  b--  "), "a-b");
+        assert_eq!(slugify("!!!"), "section");
+    }
+}
@@ -0,0 +1,129 @@
+//! # audit
+//! Records every repository write in `audit_log`, and reads it back.
+//! Writes are wrapped with [`audited`] so the log entry is inserted
+//! by the same statement as the change itself.
+
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::{
+    Error, FromRow, Postgres,
+    postgres::{PgArguments, PgRow},
+    query::{QueryAs, QueryScalar},
+};
+
+use super::{Db, on_db};
+use crate::audit;
+
+/// # audited
+/// Wraps the data-modifying statement `change` so each row it touches is logged.
+/// - `change` returns `row_id`, `before_row` and `after_row` (JSON of the whole row, `NULL` when absent)
+/// - `select` is the final query, reading from `change`
+/// - `$1` and `$2` are the actor and request id, parameters of `change` start at `$3`
+pub fn audited(table: &str, change: &str, select: &str) -> String {
+    format!(
+        "WITH change AS ({change}), \
+         audit AS ( \
+             INSERT INTO audit_log (actor, request_id, table_name, row_id, action, before, after) \
+             SELECT $1, $2, '{table}', row_id, \
+                 CASE WHEN before_row IS NULL THEN 'insert' \
+                      WHEN after_row IS NULL THEN 'delete' \
+                      ELSE 'update' END, \
+                 jsonb_changed(before_row, after_row), jsonb_changed(after_row, before_row) \
+             FROM change \
+         ) \
+         {select}"
+    )
+}
+
+type Scalar<'q> = QueryScalar<'q, Postgres, i64, PgArguments>;
+type Rows<'q, T> = QueryAs<'q, Postgres, T, PgArguments>;
+
+/// Runs an [`audited`] `change`, returning the number of rows it touched.
+/// `bind` binds the parameters of `change`, from `$3` on.
+pub async fn execute<F>(db: &Db, table: &str, change: &str, bind: F) -> Result<u64, Error>
+where
+    F: for<'q> FnOnce(Scalar<'q>) -> Scalar<'q>,
+{
+    let sql = audited(table, change, "SELECT count(*) FROM change");
+    let ctx = audit::current();
+    let query = bind(
+        sqlx::query_scalar(&sql)
+            .bind(ctx.actor)
+            .bind(ctx.request_id),
+    );
+    let n = on_db!(db, |conn| query.fetch_one(conn).await)?;
+    Ok(n as u64)
+}
+
+/// Runs an [`audited`] `change`, returning the row read by `select`.
+pub async fn fetch_one<T, F>(
+    db: &Db,
+    table: &str,
+    change: &str,
+    select: &str,
+    bind: F,
+) -> Result<T, Error>
+where
+    T: for<'r> FromRow<'r, PgRow> + Send + Unpin,
+    F: for<'q> FnOnce(Rows<'q, T>) -> Rows<'q, T>,
+{
+    let sql = audited(table, change, select);
+    let ctx = audit::current();
+    let query = bind(sqlx::query_as(&sql).bind(ctx.actor).bind(ctx.request_id));
+    on_db!(db, |conn| query.fetch_one(conn).await)
+}
+
+#[derive(Clone)]
+pub struct AuditRepo {
+    db: Db,
+}
+impl AuditRepo {
+    pub fn new(db: Db) -> Self {
+        Self { db }
+    }
+    /// Newest entries first. Every `None` filter matches anything.
+    pub async fn select(&self, filter: &AuditFilter) -> Result<Vec<AuditEntry>, Error> {
+        let query = sqlx::query_as::<_, AuditEntry>(
+            "SELECT id, actor, table_name, row_id, action, before, after, request_id, created_at \
+             FROM audit_log \
+             WHERE ($1::text IS NULL OR actor = $1) \
+               AND ($2::text IS NULL OR table_name = $2) \
+               AND ($3::timestamptz IS NULL OR created_at >= $3) \
+               AND ($4::timestamptz IS NULL OR created_at < $4) \
+             ORDER BY id DESC LIMIT $5",
+        )
+        .bind(&filter.actor)
+        .bind(&filter.table)
+        .bind(filter.from)
+        .bind(filter.to)
+        .bind(filter.limit);
+        on_db!(self.db, |conn| query.fetch_all(conn).await)
+    }
+}
+
+#[derive(Default)]
+pub struct AuditFilter {
+    pub actor: Option<String>,
+    pub table: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub limit: i64,
+}
+
+#[derive(FromRow, Serialize, JsonSchema)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub actor: String,
+    pub table_name: String,
+    pub row_id: i64,
+    /// `insert`, `update` or `delete`
+    pub action: String,
+    /// Changed columns before the write
+    pub before: Option<Value>,
+    /// Changed columns after the write
+    pub after: Option<Value>,
+    pub request_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
@@ -0,0 +1,204 @@
+//! # idempotency
+//! Responses stored under an `Idempotency-Key`, see `idempotency.rs`.
+//! Keys are scoped by actor and request : the sha256 of the method, path and body, computed by Postgres.
+
+use std::time::Duration;
+
+use sqlx::{Error, FromRow, types::Json};
+
+use super::{Db, on_db};
+
+/// Row of `key` for `request` of `actor`, bound as `$1`, `$2` and `$3`.
+const HELD: &str = "actor = $1 AND key = $2 AND fingerprint = sha256($3)";
+
+#[derive(Clone)]
+pub struct IdempotencyRepo {
+    db: Db,
+}
+impl IdempotencyRepo {
+    pub fn new(db: Db) -> Self {
+        Self { db }
+    }
+    /// Takes `key` for `request` until `lease` runs out, unless a live claim already holds it.
+    /// Expired keys are taken again as if they were new.
+    pub async fn claim(
+        &self,
+        actor: &str,
+        key: &str,
+        request: &[u8],
+        lease: Duration,
+    ) -> Result<Claim, Error> {
+        let query = sqlx::query(
+            "INSERT INTO idempotency_keys (actor, key, fingerprint, expires_at) \
+             VALUES ($1, $2, sha256($3), now() + make_interval(secs => $4)) \
+             ON CONFLICT (actor, key, fingerprint) DO UPDATE SET \
+                 status = NULL, headers = NULL, body = NULL, \
+                 created_at = now(), expires_at = EXCLUDED.expires_at \
+             WHERE idempotency_keys.expires_at <= now()",
+        )
+        .bind(actor)
+        .bind(key)
+        .bind(request)
+        .bind(lease.as_secs_f64());
+        if on_db!(self.db, |conn| query.execute(conn).await)?.rows_affected() == 1 {
+            return Ok(Claim::Claimed);
+        }
+        let sql = format!("SELECT status, headers, body FROM idempotency_keys WHERE {HELD}");
+        let query = sqlx::query_as::<_, Held>(&sql)
+            .bind(actor)
+            .bind(key)
+            .bind(request);
+        let held = on_db!(self.db, |conn| query.fetch_optional(conn).await)?;
+        Ok(match held {
+            Some(Held {
+                status: Some(status),
+                headers,
+                body,
+            }) => Claim::Answered(StoredResponse {
+                status: status as u16,
+                headers: headers.map(|headers| headers.0).unwrap_or_default(),
+                body: body.unwrap_or_default(),
+            }),
+            // Not answered yet, or released between both queries because its request just failed
+            _ => Claim::Running,
+        })
+    }
+    /// Pushes the lease of a claimed key `lease` from now, while its request is still running.
+    pub async fn extend(
+        &self,
+        actor: &str,
+        key: &str,
+        request: &[u8],
+        lease: Duration,
+    ) -> Result<(), Error> {
+        let sql = format!(
+            "UPDATE idempotency_keys SET expires_at = now() + make_interval(secs => $4) \
+             WHERE {HELD} AND status IS NULL"
+        );
+        let query = sqlx::query(&sql)
+            .bind(actor)
+            .bind(key)
+            .bind(request)
+            .bind(lease.as_secs_f64());
+        on_db!(self.db, |conn| query.execute(conn).await)?;
+        Ok(())
+    }
+    /// Stores the response of a claimed key, replayed for `ttl`.
+    pub async fn complete(
+        &self,
+        actor: &str,
+        key: &str,
+        request: &[u8],
+        response: &StoredResponse,
+        ttl: Duration,
+    ) -> Result<(), Error> {
+        let sql = format!(
+            "UPDATE idempotency_keys SET status = $4, headers = $5, body = $6, \
+                 expires_at = now() + make_interval(secs => $7) \
+             WHERE {HELD} AND status IS NULL"
+        );
+        let query = sqlx::query(&sql)
+            .bind(actor)
+            .bind(key)
+            .bind(request)
+            .bind(response.status as i16)
+            .bind(Json(&response.headers))
+            .bind(&response.body)
+            .bind(ttl.as_secs_f64());
+        on_db!(self.db, |conn| query.execute(conn).await)?;
+        Ok(())
+    }
+    /// Frees a claimed key, so the next retry runs again.
+    pub async fn release(&self, actor: &str, key: &str, request: &[u8]) -> Result<(), Error> {
+        let sql = format!("DELETE FROM idempotency_keys WHERE {HELD} AND status IS NULL");
+        let query = sqlx::query(&sql).bind(actor).bind(key).bind(request);
+        on_db!(self.db, |conn| query.execute(conn).await)?;
+        Ok(())
+    }
+    /// Removes expired keys, returning how many.
+    pub async fn purge_expired(&self) -> Result<u64, Error> {
+        let query = sqlx::query("DELETE FROM idempotency_keys WHERE expires_at <= now()");
+        let res = on_db!(self.db, |conn| query.execute(conn).await)?;
+        Ok(res.rows_affected())
+    }
+}
+
+pub enum Claim {
+    /// The key is new, run the request then `complete` or `release` it
+    Claimed,
+    /// Answered before, replay the response
+    Answered(StoredResponse),
+    /// The first request with this key has not answered yet
+    Running,
+}
+
+pub struct StoredResponse {
+    pub status: u16,
+    /// `[name, value]` pairs, in order
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+#[derive(FromRow)]
+struct Held {
+    status: Option<i16>,
+    headers: Option<Json<Vec<(String, String)>>>,
+    body: Option<Vec<u8>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::PgPool;
+
+    use super::*;
+
+    /// Needs `DATABASE_URL` with the migrations applied : `cargo test -- --ignored`
+    async fn repo() -> IdempotencyRepo {
+        dotenv::dotenv().ok();
+        let url = std::env::var("DATABASE_URL").unwrap();
+        IdempotencyRepo::new(Db::Pool(PgPool::connect(&url).await.unwrap()))
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn keys_are_scoped_by_request() {
+        let repo = repo().await;
+        let key = uuid::Uuid::new_v4().to_string();
+        let lease = Duration::from_secs(60);
+        let claim = |request: &'static [u8]| repo.claim("anonymous", &key, request, lease);
+
+        assert!(matches!(
+            claim(b"POST /a\n\n{}").await.unwrap(),
+            Claim::Claimed
+        ));
+        assert!(matches!(
+            claim(b"POST /a\n\n{}").await.unwrap(),
+            Claim::Running
+        ));
+        assert!(matches!(
+            claim(b"POST /b\n\n{}").await.unwrap(),
+            Claim::Claimed
+        ));
+        for request in [b"POST /a\n\n{}", b"POST /b\n\n{}"] {
+            repo.release("anonymous", &key, request).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn extend_keeps_a_running_request_claimed() {
+        let repo = repo().await;
+        let key = uuid::Uuid::new_v4().to_string();
+        let request = b"POST /a\n\n{}";
+        let lease = Duration::from_millis(500);
+
+        let claim = repo.claim("admin", &key, request, lease).await.unwrap();
+        assert!(matches!(claim, Claim::Claimed));
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        repo.extend("admin", &key, request, lease).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let claim = repo.claim("admin", &key, request, lease).await.unwrap();
+        assert!(matches!(claim, Claim::Running));
+        repo.release("admin", &key, request).await.unwrap();
+    }
+}
@@ -1,33 +1,204 @@
 #![allow(dead_code)]
 
+pub mod audit;
+pub mod idempotency;
+pub mod outbox;
 pub mod posts;
+pub mod revisions;
+pub mod tags;
 pub mod users;
+pub mod webhooks;
 // pub mod comment;
 
+use chrono::{DateTime, Utc};
+use log::error;
 use schemars::JsonSchema;
 use serde::Serialize;
-use sqlx::{Error, PgPool};
+use sqlx::{Error, PgPool, Postgres, Transaction};
+use tokio::sync::Mutex;
+
+use crate::{error::AppError, prelude::*};
 
 #[async_trait::async_trait]
 pub trait Repo<T: Table> {
-    fn new(pool: PgPool) -> Self;
-    async fn select(&self, criteria: &T) -> Result<Vec<T>, Error>;
-    async fn insert(&self, row: &T) -> Result<(), Error>;
+    fn new(db: Db) -> Self;
+    /// Soft-deleted rows are left out, see [`Repo::select_with`].
+    async fn select(&self, criteria: &T) -> Result<Vec<T>, Error> {
+        self.select_with(criteria, ReadOptions::default()).await
+    }
+    async fn select_with(&self, criteria: &T, opts: ReadOptions) -> Result<Vec<T>, Error>;
+    /// Returns the stored row, with generated columns filled in.
+    async fn insert(&self, row: &T) -> Result<T, Error>;
+    /// Soft-deletes the row with `criteria`'s id, returning the number of deleted rows.
+    async fn delete(&self, criteria: &T) -> Result<u64, Error>;
+    /// Undoes [`Repo::delete`], returning the number of restored rows.
+    async fn restore(&self, criteria: &T) -> Result<u64, Error>;
+    /// Removes the row with `criteria`'s id for good, deleted or not.
+    async fn purge(&self, criteria: &T) -> Result<u64, Error>;
+    /// Removes every row soft-deleted before `before`.
+    async fn purge_deleted_before(&self, before: DateTime<Utc>) -> Result<u64, Error>;
+}
+pub trait Table: JsonSchema + Serialize + Sized + Send + Sync {}
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct ReadOptions {
+    /// Also return soft-deleted rows. Admin only.
+    pub include_deleted: bool,
+    /// Also return posts that are not published. Only read by posts.
+    pub include_unpublished: bool,
+    /// Lock the returned rows until the transaction ends (`FOR UPDATE`).
+    /// Only read by `find`, and only useful in a [`UnitOfWork`].
+    pub lock: bool,
+}
+impl ReadOptions {
+    fn lock_clause(&self) -> &'static str {
+        if self.lock { "FOR UPDATE" } else { "" }
+    }
+}
+
+/// # Db
+/// What a repository runs its queries on:
+/// the pool, or a transaction shared by every repo of a [`UnitOfWork`].
+#[derive(Clone)]
+pub enum Db {
+    Pool(PgPool),
+    Tx(Arc<Mutex<Option<Transaction<'static, Postgres>>>>),
 }
-pub trait Table: JsonSchema + Serialize + Sized {}
+
+/// # on_db
+/// Runs `$body` with `$conn` bound to an executor of `$db`.
+/// The body is expanded once per `Db` variant, so it may use `query!` macros.
+/// ## How to use
+/// ```
+/// let res = on_db!(self.db, |conn| query.fetch_all(conn).await)?;
+/// ```
+macro_rules! on_db {
+    ($db:expr, |$conn:ident| $body:expr) => {
+        match &$db {
+            $crate::repository::Db::Pool(pool) => {
+                let $conn = pool;
+                $body
+            }
+            $crate::repository::Db::Tx(tx) => {
+                let mut guard = tx.lock().await;
+                match guard.as_mut() {
+                    Some(tx) => {
+                        let $conn = &mut **tx;
+                        $body
+                    }
+                    None => Err(::sqlx::Error::Protocol(
+                        "transaction already finished".to_string(),
+                    )),
+                }
+            }
+        }
+    };
+}
+pub(crate) use on_db;
 
 #[derive(Clone)]
 pub struct RepoFactory {
+    pool: PgPool,
     pub user: users::UsersRepo,
     pub posts: posts::PostsRepo,
+    pub tags: tags::TagsRepo,
+    pub revisions: revisions::RevisionsRepo,
+    pub audit: audit::AuditRepo,
+    pub idempotency: idempotency::IdempotencyRepo,
+    pub outbox: outbox::OutboxRepo,
+    pub webhooks: webhooks::WebhooksRepo,
     // pub comment: comment::CommentRepo
 }
 impl RepoFactory {
     pub fn new(pool: PgPool) -> Self {
+        let db = Db::Pool(pool.clone());
         Self {
-            user: users::UsersRepo::new(pool.clone()),
-            posts: posts::PostsRepo::new(pool.clone()),
-            // comment: comment::CommentRepo::new(pool.clone())
+            pool,
+            user: users::UsersRepo::new(db.clone()),
+            posts: posts::PostsRepo::new(db.clone()),
+            tags: tags::TagsRepo::new(db.clone()),
+            revisions: revisions::RevisionsRepo::new(db.clone()),
+            audit: audit::AuditRepo::new(db.clone()),
+            idempotency: idempotency::IdempotencyRepo::new(db.clone()),
+            outbox: outbox::OutboxRepo::new(db.clone()),
+            webhooks: webhooks::WebhooksRepo::new(db.clone()),
+            // comment: comment::CommentRepo::new(db.clone())
+        }
+    }
+
+    /// Starts a transaction. Every repo of the returned [`UnitOfWork`] runs inside it.
+    pub async fn begin(&self) -> Result<UnitOfWork, Error> {
+        let tx = Arc::new(Mutex::new(Some(self.pool.begin().await?)));
+        let db = Db::Tx(tx.clone());
+        Ok(UnitOfWork {
+            tx,
+            user: users::UsersRepo::new(db.clone()),
+            posts: posts::PostsRepo::new(db.clone()),
+            tags: tags::TagsRepo::new(db.clone()),
+            revisions: revisions::RevisionsRepo::new(db.clone()),
+            outbox: outbox::OutboxRepo::new(db.clone()),
+            webhooks: webhooks::WebhooksRepo::new(db.clone()),
+        })
+    }
+
+    /// Runs `f` in a transaction, committing on `Ok` and rolling back on `Err`.
+    /// A failed rollback is logged, the caller gets the error of `f`.
+    /// ## How to use
+    /// ```
+    /// let user = state
+    ///     .transaction(|uow| async move {
+    ///         let user = uow.user.insert(&row).await?;
+    ///         uow.posts.insert(&first_post(&user)).await?;
+    ///         Ok(user)
+    ///     })
+    ///     .await?;
+    /// ```
+    pub async fn transaction<F, Fut, T>(&self, f: F) -> Result<T, AppError>
+    where
+        F: FnOnce(UnitOfWork) -> Fut,
+        Fut: Future<Output = Result<T, AppError>>,
+    {
+        let uow = self.begin().await?;
+        match f(uow.clone()).await {
+            Ok(v) => {
+                uow.commit().await?;
+                Ok(v)
+            }
+            Err(err) => {
+                // The connection is dropped if the rollback fails, so `err` is what the caller needs
+                if let Err(rollback) = uow.rollback().await {
+                    error!("Fail to roll back after \"{}\" : {}", err, rollback);
+                }
+                Err(err)
+            }
+        }
+    }
+}
+
+/// # UnitOfWork
+/// Transaction-bound repositories, from [`RepoFactory::begin`].
+/// Dropping it without [`UnitOfWork::commit`] rolls the transaction back.
+#[derive(Clone)]
+pub struct UnitOfWork {
+    tx: Arc<Mutex<Option<Transaction<'static, Postgres>>>>,
+    pub user: users::UsersRepo,
+    pub posts: posts::PostsRepo,
+    pub tags: tags::TagsRepo,
+    pub revisions: revisions::RevisionsRepo,
+    pub outbox: outbox::OutboxRepo,
+    pub webhooks: webhooks::WebhooksRepo,
+}
+impl UnitOfWork {
+    pub async fn commit(self) -> Result<(), Error> {
+        match self.tx.lock().await.take() {
+            Some(tx) => tx.commit().await,
+            None => Ok(()),
+        }
+    }
+    pub async fn rollback(self) -> Result<(), Error> {
+        match self.tx.lock().await.take() {
+            Some(tx) => tx.rollback().await,
+            None => Ok(()),
         }
     }
 }
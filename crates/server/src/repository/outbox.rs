@@ -0,0 +1,80 @@
+//! # outbox
+//! Events of audited writes, written by the trigger on `audit_log` in the transaction of the write
+//! (see `migrations/*_add_outbox.sql`) and relayed to sinks by `tasks/outbox.rs`.
+//! An event is published once every sink has it, so sinks see it at least once.
+
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use sqlx::{Error, FromRow};
+
+use super::{Db, on_db};
+
+/// Channel notified on commit when events are written.
+pub const CHANNEL: &str = "outbox";
+/// Channel of the live events fed to `events::Hub`.
+pub const CHANGES_CHANNEL: &str = "row_changes";
+
+#[derive(Clone)]
+pub struct OutboxRepo {
+    db: Db,
+}
+impl OutboxRepo {
+    pub fn new(db: Db) -> Self {
+        Self { db }
+    }
+    /// Up to `limit` events not published yet, oldest first,
+    /// locked until the transaction ends so other relays skip them.
+    /// Only useful in a `UnitOfWork`.
+    pub async fn claim(&self, limit: i64) -> Result<Vec<OutboxEntry>, Error> {
+        let query = sqlx::query_as::<_, OutboxEntry>(
+            "SELECT id, event, payload, created_at FROM outbox \
+             WHERE published_at IS NULL ORDER BY id LIMIT $1 \
+             FOR UPDATE SKIP LOCKED",
+        )
+        .bind(limit);
+        on_db!(self.db, |conn| query.fetch_all(conn).await)
+    }
+    pub async fn mark_published(&self, ids: &[i64]) -> Result<u64, Error> {
+        let query =
+            sqlx::query("UPDATE outbox SET published_at = now() WHERE id = ANY($1)").bind(ids);
+        let res = on_db!(self.db, |conn| query.execute(conn).await)?;
+        Ok(res.rows_affected())
+    }
+    /// Notifies [`CHANGES_CHANNEL`] of the events `ids`, in order, as `events::Change`s.
+    /// Notifications are sent on commit, to every instance.
+    pub async fn notify_changes(&self, ids: &[i64]) -> Result<(), Error> {
+        let query = sqlx::query(
+            "SELECT pg_notify($2, jsonb_build_object( \
+                 'id', id, \
+                 'event', event, \
+                 'post_id', payload->'data'->'post_id', \
+                 'user_id', payload->'data'->'user_id', \
+                 'status', payload->'data'->'status', \
+                 'changed', (SELECT COALESCE(jsonb_agg(key), '[]'::jsonb) \
+                             FROM jsonb_object_keys(payload->'data'->'changes') AS key), \
+                 'at', payload->'occurred_at' \
+             )::text) \
+             FROM outbox WHERE id = ANY($1) ORDER BY id",
+        )
+        .bind(ids)
+        .bind(CHANGES_CHANNEL);
+        on_db!(self.db, |conn| query.execute(conn).await)?;
+        Ok(())
+    }
+    /// Removes events published before `before`, returning how many.
+    pub async fn purge_published_before(&self, before: DateTime<Utc>) -> Result<u64, Error> {
+        let query = sqlx::query("DELETE FROM outbox WHERE published_at < $1").bind(before);
+        let res = on_db!(self.db, |conn| query.execute(conn).await)?;
+        Ok(res.rows_affected())
+    }
+}
+
+#[derive(FromRow)]
+pub struct OutboxEntry {
+    pub id: i64,
+    /// e.g. `post.published`
+    pub event: String,
+    /// `event`, `occurred_at` and `data`, as webhooks receive it
+    pub payload: Value,
+    pub created_at: DateTime<Utc>,
+}
@@ -1,41 +1,390 @@
+use chrono::{DateTime, Utc};
 use schemars::JsonSchema;
-use serde::Serialize;
-use sqlx::{Error, FromRow, PgPool};
+use serde::{Deserialize, Serialize};
+use sqlx::{Error, FromRow};
 
-use super::{Repo, Table};
+use super::{Db, ReadOptions, Repo, Table, audit, on_db};
+use crate::markdown::{self, Render};
+
+/// Columns of [`Posts`], tags included.
+const COLUMNS: &str = "id, title, content, content_html, user_id, status, publish_at, version, \
+    created_at, updated_at, deleted_at, \
+    ARRAY(SELECT tags.name FROM post_tags JOIN tags ON tags.id = post_tags.tag_id \
+          WHERE post_tags.post_id = posts.id ORDER BY tags.name) AS tags";
+
+/// Filter on `ReadOptions::include_unpublished`, bound as `$3`.
+const VISIBLE: &str = "AND ($3 OR status = 'published')";
 
 #[derive(Clone)]
 pub struct PostsRepo {
-    pool: PgPool,
+    db: Db,
 }
 impl PostsRepo {
-    pub fn new(pool: PgPool) -> Self {
-        Self { pool }
+    pub async fn find(&self, id: i64, opts: ReadOptions) -> Result<Option<Posts>, Error> {
+        let sql = format!(
+            "SELECT {COLUMNS} FROM posts WHERE id = $1 AND ($2 OR deleted_at IS NULL) {VISIBLE} {}",
+            opts.lock_clause()
+        );
+        let query = sqlx::query_as::<_, Posts>(&sql)
+            .bind(id)
+            .bind(opts.include_deleted)
+            .bind(opts.include_unpublished);
+        on_db!(self.db, |conn| query.fetch_optional(conn).await)
+    }
+    /// Posts tagged with `tag`, newest first.
+    pub async fn select_by_tag(&self, tag: &str, opts: ReadOptions) -> Result<Vec<Posts>, Error> {
+        let sql = format!(
+            "SELECT {COLUMNS} FROM posts \
+             WHERE id IN (SELECT post_id FROM post_tags JOIN tags ON tags.id = post_tags.tag_id \
+                          WHERE tags.name = $1) \
+               AND ($2 OR deleted_at IS NULL) {VISIBLE} \
+             ORDER BY id DESC"
+        );
+        let query = sqlx::query_as::<_, Posts>(&sql)
+            .bind(tag)
+            .bind(opts.include_deleted)
+            .bind(opts.include_unpublished);
+        on_db!(self.db, |conn| query.fetch_all(conn).await)
+    }
+    /// Posts written by any of `user_ids`, oldest first, like [`Repo::select_with`] for several users.
+    pub async fn select_by_users(
+        &self,
+        user_ids: &[i64],
+        opts: ReadOptions,
+    ) -> Result<Vec<Posts>, Error> {
+        let sql = format!(
+            "SELECT {COLUMNS} FROM posts \
+             WHERE user_id = ANY($1) AND ($2 OR deleted_at IS NULL) {VISIBLE} ORDER BY id"
+        );
+        let query = sqlx::query_as::<_, Posts>(&sql)
+            .bind(user_ids)
+            .bind(opts.include_deleted)
+            .bind(opts.include_unpublished);
+        on_db!(self.db, |conn| query.fetch_all(conn).await)
+    }
+    /// Soft-deletes every post written by `user_id`, returning the number of deleted rows.
+    pub async fn delete_by_user(&self, user_id: i64) -> Result<u64, Error> {
+        let change = update(
+            "deleted_at = now()",
+            "posts.user_id = $3 AND posts.deleted_at IS NULL",
+        );
+        audit::execute(&self.db, "posts", &change, |q| q.bind(user_id)).await
+    }
+    /// Restores the posts of `user_id` that were deleted at `deleted_at`,
+    /// i.e. together with the user.
+    pub async fn restore_by_user(
+        &self,
+        user_id: i64,
+        deleted_at: DateTime<Utc>,
+    ) -> Result<u64, Error> {
+        let change = update(
+            "deleted_at = NULL",
+            "posts.user_id = $3 AND posts.deleted_at = $4",
+        );
+        audit::execute(&self.db, "posts", &change, |q| {
+            q.bind(user_id).bind(deleted_at)
+        })
+        .await
+    }
+    /// Changes the title and/or content of a live post, returning the number of edited rows.
+    /// A revision is recorded when either actually changes.
+    pub async fn edit(
+        &self,
+        id: i64,
+        title: Option<String>,
+        content: Option<String>,
+    ) -> Result<u64, Error> {
+        let content_html = content.as_deref().map(markdown::render);
+        let change = update(
+            "title = COALESCE($4, posts.title), \
+             content = COALESCE($5, posts.content), \
+             content_html = COALESCE($6, posts.content_html)",
+            "posts.id = $3 AND posts.deleted_at IS NULL",
+        );
+        audit::execute(&self.db, "posts", &change, |q| {
+            q.bind(id).bind(title).bind(content).bind(content_html)
+        })
+        .await
+    }
+    /// Moves a live post to `status`, returning the number of changed rows.
+    pub async fn set_status(
+        &self,
+        id: i64,
+        status: PostStatus,
+        publish_at: Option<DateTime<Utc>>,
+    ) -> Result<u64, Error> {
+        let change = update(
+            "status = $4, publish_at = $5",
+            "posts.id = $3 AND posts.deleted_at IS NULL",
+        );
+        audit::execute(&self.db, "posts", &change, |q| {
+            q.bind(id).bind(status).bind(publish_at)
+        })
+        .await
+    }
+    /// Publishes scheduled posts whose `publish_at` has passed, returning how many.
+    pub async fn publish_due(&self) -> Result<u64, Error> {
+        let change = update(
+            "status = 'published'",
+            "posts.status = 'scheduled' AND posts.publish_at <= now() AND posts.deleted_at IS NULL",
+        );
+        audit::execute(&self.db, "posts", &change, |q| q).await
+    }
+    /// Posts matching `query` (see [`to_tsquery`]), best ranked first.
+    /// Title matches weigh more than content ones.
+    /// Content is HTML-escaped before highlighting, so `<mark>` is the only tag of a snippet.
+    pub async fn search(&self, query: &str, limit: i64) -> Result<Vec<SearchHit>, Error> {
+        let query = sqlx::query_as::<_, SearchHit>(
+            "SELECT id, title, user_id, created_at, \
+                 ts_rank(search, query) AS rank, \
+                 ts_headline('simple', \
+                     replace(replace(replace(content, '&', '&amp;'), '<', '&lt;'), '>', '&gt;'), \
+                     query, \
+                     'StartSel=<mark>, StopSel=</mark>, MaxFragments=2') AS snippet \
+             FROM posts, to_tsquery('simple', $1) query \
+             WHERE search @@ query AND deleted_at IS NULL AND status = 'published' \
+             ORDER BY rank DESC, id DESC LIMIT $2",
+        )
+        .bind(query)
+        .bind(limit);
+        on_db!(self.db, |conn| query.fetch_all(conn).await)
     }
 }
 #[async_trait::async_trait]
 impl Repo<Posts> for PostsRepo {
-    fn new(pool: PgPool) -> Self {
-        Self { pool }
+    fn new(db: Db) -> Self {
+        Self { db }
+    }
+    async fn select_with(&self, criteria: &Posts, opts: ReadOptions) -> Result<Vec<Posts>, Error> {
+        let sql = format!(
+            "SELECT {COLUMNS} FROM posts \
+             WHERE user_id = $1 AND ($2 OR deleted_at IS NULL) {VISIBLE} ORDER BY id"
+        );
+        let query = sqlx::query_as::<_, Posts>(&sql)
+            .bind(criteria.user_id)
+            .bind(opts.include_deleted)
+            .bind(opts.include_unpublished);
+        on_db!(self.db, |conn| query.fetch_all(conn).await)
+    }
+    async fn insert(&self, row: &Posts) -> Result<Posts, Error> {
+        let (title, content, user_id) = (row.title.clone(), row.content.clone(), row.user_id);
+        let content_html = markdown::render(&content);
+        let status = row.status;
+        let publish_at = match status {
+            PostStatus::Published => row.publish_at.or_else(|| Some(Utc::now())),
+            _ => row.publish_at,
+        };
+        let change = format!(
+            "INSERT INTO posts (title, content, content_html, user_id, status, publish_at) \
+             VALUES ($3, $4, $5, $6, $7, $8) \
+             RETURNING *, id AS row_id, NULL::jsonb AS before_row, {} AS after_row",
+            row_json("posts")
+        );
+        audit::fetch_one(
+            &self.db,
+            "posts",
+            &change,
+            "SELECT id, title, content, content_html, user_id, status, publish_at, version, \
+                 created_at, updated_at, deleted_at \
+             FROM change",
+            |q| {
+                q.bind(title)
+                    .bind(content)
+                    .bind(content_html)
+                    .bind(user_id)
+                    .bind(status)
+                    .bind(publish_at)
+            },
+        )
+        .await
+    }
+    async fn delete(&self, criteria: &Posts) -> Result<u64, Error> {
+        let id = criteria.id;
+        let change = update(
+            "deleted_at = now()",
+            "posts.id = $3 AND posts.deleted_at IS NULL",
+        );
+        audit::execute(&self.db, "posts", &change, |q| q.bind(id)).await
     }
-    async fn select(&self, criteria: &Posts) -> Result<Vec<Posts>, Error> {
-        let query = "SELECT id, title, content, user_id FROM posts WHERE name = $1".to_string();
-        let res = sqlx::query_as::<_, Posts>(&query)
-            .bind(&criteria.user_id)
-            .fetch_one(&self.pool)
-            .await?;
-        Ok(vec![res])
+    async fn restore(&self, criteria: &Posts) -> Result<u64, Error> {
+        let id = criteria.id;
+        let change = update(
+            "deleted_at = NULL",
+            "posts.id = $3 AND posts.deleted_at IS NOT NULL",
+        );
+        audit::execute(&self.db, "posts", &change, |q| q.bind(id)).await
     }
-    async fn insert(&self, _row: &Posts) -> Result<(), Error> {
-        todo!();
+    async fn purge(&self, criteria: &Posts) -> Result<u64, Error> {
+        let id = criteria.id;
+        audit::execute(&self.db, "posts", &delete("id = $3"), |q| q.bind(id)).await
     }
+    async fn purge_deleted_before(&self, before: DateTime<Utc>) -> Result<u64, Error> {
+        audit::execute(&self.db, "posts", &delete("deleted_at < $3"), |q| {
+            q.bind(before)
+        })
+        .await
+    }
+}
+
+/// UPDATE of the posts matching `filter`, returning each row before and after for [`audit::audited`].
+fn update(set: &str, filter: &str) -> String {
+    format!(
+        "UPDATE posts SET {set} FROM posts prev \
+         WHERE posts.id = prev.id AND {filter} \
+         RETURNING posts.id AS row_id, {} AS before_row, {} AS after_row",
+        row_json("prev"),
+        row_json("posts")
+    )
+}
+
+/// DELETE returning the removed rows for [`audit::audited`].
+pub(super) fn delete(filter: &str) -> String {
+    format!(
+        "DELETE FROM posts WHERE {filter} \
+         RETURNING id AS row_id, {} AS before_row, NULL::jsonb AS after_row",
+        row_json("posts")
+    )
+}
+
+/// JSON of the row `alias` for the audit log, without the columns derived from `content`.
+fn row_json(alias: &str) -> String {
+    format!("to_jsonb({alias}) - '{{search,content_html}}'::text[]")
 }
 
-#[derive(FromRow, Serialize, JsonSchema, Default)]
+#[derive(FromRow, Serialize, JsonSchema, Default, Clone)]
 pub struct Posts {
     pub id: i64,
     pub title: String,
+    /// Markdown
     pub content: String,
+    /// `content` rendered by [`markdown::render`], `None` for posts written before it existed
+    #[serde(skip)]
+    #[sqlx(default)]
+    pub content_html: Option<String>,
     pub user_id: i64,
+    pub status: PostStatus,
+    /// When the post was or will be published
+    pub publish_at: Option<DateTime<Utc>>,
+    /// Incremented on every change, answered as the `ETag`
+    pub version: i64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,
+    /// Tag names, sorted
+    #[sqlx(default)]
+    pub tags: Vec<String>,
+}
+/// Only `published` posts are shown by public routes.
+#[derive(
+    sqlx::Type, Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, Default, PartialEq, Eq,
+)]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum PostStatus {
+    Draft,
+    /// Published by the scheduler once `publish_at` has passed
+    Scheduled,
+    #[default]
+    Published,
+    Archived,
+}
+
+impl Posts {
+    /// Answers `content` as asked by `render`.
+    pub fn render(mut self, render: Render) -> Self {
+        if render == Render::Html {
+            self.content = match self.content_html.take() {
+                Some(html) => html,
+                None => markdown::render(&self.content),
+            };
+        }
+        self
+    }
 }
 impl Table for Posts {}
+
+#[derive(FromRow, Serialize, JsonSchema)]
+pub struct SearchHit {
+    pub id: i64,
+    pub title: String,
+    pub user_id: i64,
+    pub created_at: DateTime<Utc>,
+    pub rank: f32,
+    /// Best matching parts of the content as HTML, escaped but for the `<mark>` around matches
+    pub snippet: String,
+}
+
+/// # to_tsquery
+/// Turns a search box input into `to_tsquery` syntax, `None` when there is nothing to search.
+/// - `"some words"` : the words in this order
+/// - `word*` : any word starting with `word`
+/// - Every other word must appear somewhere
+pub fn to_tsquery(input: &str) -> Option<String> {
+    let mut terms = Vec::new();
+    for (i, part) in input.split('"').enumerate() {
+        if i % 2 == 1 {
+            terms.extend(phrase(part, false));
+        } else {
+            for word in part.split_whitespace() {
+                terms.extend(phrase(word, word.ends_with('*')));
+            }
+        }
+    }
+    (!terms.is_empty()).then(|| terms.join(" & "))
+}
+
+/// Words of `text` as a phrase, punctuation dropped so it cannot reach the tsquery parser.
+fn phrase(text: &str, prefix: bool) -> Option<String> {
+    let words: Vec<&str> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect();
+    if words.is_empty() {
+        return None;
+    }
+    let suffix = if prefix { ":*" } else { "" };
+    Some(format!("({}{})", words.join(" <-> "), suffix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_tsquery_requires_every_word() {
+        assert_eq!(to_tsquery("rust  axum").as_deref(), Some("(rust) & (axum)"));
+    }
+
+    #[test]
+    fn to_tsquery_keeps_quoted_words_in_order() {
+        assert_eq!(
+            to_tsquery(r#"web "hello big world""#).as_deref(),
+            Some("(web) & (hello <-> big <-> world)")
+        );
+    }
+
+    #[test]
+    fn to_tsquery_matches_prefixes() {
+        assert_eq!(to_tsquery("rus*").as_deref(), Some("(rus:*)"));
+    }
+
+    #[test]
+    fn to_tsquery_drops_operators() {
+        assert_eq!(
+            to_tsquery("a&b | !c:* <-> (d)").as_deref(),
+            Some("(a <-> b) & (c:*) & (d)")
+        );
+    }
+
+    #[test]
+    fn to_tsquery_is_none_without_words() {
+        assert_eq!(to_tsquery(""), None);
+        assert_eq!(to_tsquery(r#" "" & | ! "#), None);
+    }
+
+    #[test]
+    fn phrase_splits_on_punctuation() {
+        assert_eq!(phrase("don't", false).as_deref(), Some("(don <-> t)"));
+        assert_eq!(phrase("---", true), None);
+    }
+}
@@ -0,0 +1,110 @@
+//! # revisions
+//! Every version of a post, recorded in `post_revisions` by a trigger on insert and edit.
+//! The latest revision always matches the post.
+
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use similar::{ChangeTag, TextDiff};
+use sqlx::{Error, FromRow};
+
+use super::{Db, on_db};
+
+#[derive(Clone)]
+pub struct RevisionsRepo {
+    db: Db,
+}
+impl RevisionsRepo {
+    pub fn new(db: Db) -> Self {
+        Self { db }
+    }
+    /// Revisions of `post_id`, latest first.
+    pub async fn select(&self, post_id: i64) -> Result<Vec<Revision>, Error> {
+        let query = sqlx::query_as::<_, Revision>(
+            "SELECT post_id, rev, title, content, created_at FROM post_revisions \
+             WHERE post_id = $1 ORDER BY rev DESC",
+        )
+        .bind(post_id);
+        on_db!(self.db, |conn| query.fetch_all(conn).await)
+    }
+    pub async fn find(&self, post_id: i64, rev: i32) -> Result<Option<Revision>, Error> {
+        let query = sqlx::query_as::<_, Revision>(
+            "SELECT post_id, rev, title, content, created_at FROM post_revisions \
+             WHERE post_id = $1 AND rev = $2",
+        )
+        .bind(post_id)
+        .bind(rev);
+        on_db!(self.db, |conn| query.fetch_optional(conn).await)
+    }
+}
+
+#[derive(FromRow, Serialize, JsonSchema)]
+pub struct Revision {
+    pub post_id: i64,
+    pub rev: i32,
+    pub title: String,
+    pub content: String,
+    pub created_at: DateTime<Utc>,
+}
+impl Revision {
+    /// Line-based diff from `self` to `to`.
+    pub fn diff(&self, to: &Revision) -> RevisionDiff {
+        // A missing final newline would make the last line differ
+        let (old, new) = (terminated(&self.content), terminated(&to.content));
+        let lines = TextDiff::from_lines(&old, &new)
+            .iter_all_changes()
+            .map(|change| DiffLine {
+                op: match change.tag() {
+                    ChangeTag::Equal => DiffOp::Equal,
+                    ChangeTag::Delete => DiffOp::Delete,
+                    ChangeTag::Insert => DiffOp::Insert,
+                },
+                old_line: change.old_index().map(|i| i + 1),
+                new_line: change.new_index().map(|i| i + 1),
+                text: change.to_string_lossy().trim_end_matches('\n').to_string(),
+            })
+            .collect();
+        RevisionDiff {
+            from: self.rev,
+            to: to.rev,
+            title: (self.title != to.title).then(|| [self.title.clone(), to.title.clone()]),
+            lines,
+        }
+    }
+}
+
+fn terminated(text: &str) -> String {
+    let mut text = text.to_string();
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+    text
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct RevisionDiff {
+    pub from: i32,
+    pub to: i32,
+    /// Old and new title, `None` when unchanged
+    pub title: Option<[String; 2]>,
+    /// Every line of both contents, in order
+    pub lines: Vec<DiffLine>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct DiffLine {
+    pub op: DiffOp,
+    /// 1-based line number in `from`, `None` for inserted lines
+    pub old_line: Option<usize>,
+    /// 1-based line number in `to`, `None` for deleted lines
+    pub new_line: Option<usize>,
+    pub text: String,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffOp {
+    Equal,
+    Delete,
+    Insert,
+}
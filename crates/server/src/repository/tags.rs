@@ -0,0 +1,117 @@
+//! # tags
+//! Many-to-many tags of posts, through `post_tags`.
+
+use schemars::JsonSchema;
+use serde::Serialize;
+use sqlx::{Error, FromRow};
+
+use super::{Db, audit, on_db};
+
+/// Longest tag name accepted by [`normalize`].
+pub const MAX_TAG_LEN: usize = 32;
+
+#[derive(Clone)]
+pub struct TagsRepo {
+    db: Db,
+}
+impl TagsRepo {
+    pub fn new(db: Db) -> Self {
+        Self { db }
+    }
+    /// Adds `names` to the tags of `post_id`, creating missing tags.
+    /// Returns the number of tags newly attached.
+    pub async fn tag(&self, post_id: i64, names: &[String]) -> Result<u64, Error> {
+        let names = names.to_vec();
+        audit::execute(
+            &self.db,
+            "tags",
+            "INSERT INTO tags (name) SELECT unnest($3::text[]) ON CONFLICT (name) DO NOTHING \
+             RETURNING id AS row_id, NULL::jsonb AS before_row, to_jsonb(tags) AS after_row",
+            |q| q.bind(names.clone()),
+        )
+        .await?;
+        audit::execute(
+            &self.db,
+            "post_tags",
+            "INSERT INTO post_tags (post_id, tag_id) \
+             SELECT $3, id FROM tags WHERE name = ANY($4) ON CONFLICT DO NOTHING \
+             RETURNING post_id AS row_id, NULL::jsonb AS before_row, \
+                 jsonb_build_object('tag_id', tag_id) AS after_row",
+            |q| q.bind(post_id).bind(names),
+        )
+        .await
+    }
+    /// Removes `names` from the tags of `post_id`, returning the number of tags detached.
+    /// The tags themselves are kept.
+    pub async fn untag(&self, post_id: i64, names: &[String]) -> Result<u64, Error> {
+        let names = names.to_vec();
+        audit::execute(
+            &self.db,
+            "post_tags",
+            "DELETE FROM post_tags USING tags \
+             WHERE tags.id = post_tags.tag_id AND post_tags.post_id = $3 AND tags.name = ANY($4) \
+             RETURNING post_id AS row_id, \
+                 jsonb_build_object('tag_id', tag_id) AS before_row, NULL::jsonb AS after_row",
+            |q| q.bind(post_id).bind(names),
+        )
+        .await
+    }
+    /// Every tag with the number of posts carrying it, most used first.
+    /// Only published posts are counted.
+    pub async fn counts(&self) -> Result<Vec<TagCount>, Error> {
+        let query = sqlx::query_as::<_, TagCount>(
+            "SELECT tags.name, count(posts.id) AS posts FROM tags \
+             LEFT JOIN post_tags ON post_tags.tag_id = tags.id \
+             LEFT JOIN posts ON posts.id = post_tags.post_id \
+                 AND posts.deleted_at IS NULL AND posts.status = 'published' \
+             GROUP BY tags.name ORDER BY posts DESC, tags.name",
+        );
+        on_db!(self.db, |conn| query.fetch_all(conn).await)
+    }
+}
+
+#[derive(FromRow, Serialize, JsonSchema)]
+pub struct TagCount {
+    pub name: String,
+    pub posts: i64,
+}
+
+/// # normalize
+/// Tag name as stored: trimmed and lowercase.
+/// `None` when empty, longer than [`MAX_TAG_LEN`] or holding whitespace.
+pub fn normalize(name: &str) -> Option<String> {
+    let name = name.trim().to_lowercase();
+    let valid = !name.is_empty()
+        && name.chars().count() <= MAX_TAG_LEN
+        && !name.chars().any(char::is_whitespace);
+    valid.then_some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_trims_and_lowercases() {
+        assert_eq!(normalize("  Rust ").as_deref(), Some("rust"));
+        assert_eq!(normalize("ÉTÉ").as_deref(), Some("été"));
+    }
+
+    #[test]
+    fn normalize_rejects_empty_names() {
+        assert_eq!(normalize(""), None);
+        assert_eq!(normalize(" \t "), None);
+    }
+
+    #[test]
+    fn normalize_rejects_inner_whitespace() {
+        assert_eq!(normalize("web dev"), None);
+        assert_eq!(normalize("web\u{a0}dev"), None);
+    }
+
+    #[test]
+    fn normalize_counts_characters_not_bytes() {
+        assert!(normalize(&"é".repeat(MAX_TAG_LEN)).is_some());
+        assert_eq!(normalize(&"a".repeat(MAX_TAG_LEN + 1)), None);
+    }
+}
@@ -1,36 +1,154 @@
+use chrono::{DateTime, Utc};
 use schemars::JsonSchema;
 use serde::Serialize;
-use sqlx::{Error, FromRow, PgPool};
+use sqlx::{Error, FromRow};
 
-use super::{Repo, Table};
+use super::{Db, ReadOptions, Repo, Table, audit, on_db, posts};
 
 #[derive(Clone)]
 pub struct UsersRepo {
-    pool: PgPool,
+    db: Db,
+}
+impl UsersRepo {
+    pub async fn find(&self, id: i64, opts: ReadOptions) -> Result<Option<Users>, Error> {
+        let sql = format!(
+            "SELECT id, name, version, created_at, updated_at, deleted_at FROM users \
+             WHERE id = $1 AND ($2 OR deleted_at IS NULL) {}",
+            opts.lock_clause()
+        );
+        let query = sqlx::query_as::<_, Users>(&sql)
+            .bind(id)
+            .bind(opts.include_deleted);
+        on_db!(self.db, |conn| query.fetch_optional(conn).await)
+    }
+    /// Live user named `name`.
+    pub async fn find_by_name(&self, name: &str) -> Result<Option<Users>, Error> {
+        let query = sqlx::query_as::<_, Users>(
+            "SELECT id, name, version, created_at, updated_at, deleted_at FROM users \
+             WHERE name = $1 AND deleted_at IS NULL",
+        )
+        .bind(name);
+        on_db!(self.db, |conn| query.fetch_optional(conn).await)
+    }
+    /// Live users among `ids`, in no particular order.
+    pub async fn find_many(&self, ids: &[i64]) -> Result<Vec<Users>, Error> {
+        let query = sqlx::query_as::<_, Users>(
+            "SELECT id, name, version, created_at, updated_at, deleted_at FROM users \
+             WHERE id = ANY($1) AND deleted_at IS NULL",
+        )
+        .bind(ids);
+        on_db!(self.db, |conn| query.fetch_all(conn).await)
+    }
+    /// Renames a live user, returning the number of renamed rows.
+    pub async fn rename(&self, id: i64, name: String) -> Result<u64, Error> {
+        let change = update("name = $4", "users.deleted_at IS NULL");
+        audit::execute(&self.db, "users", &change, |q| q.bind(id).bind(name)).await
+    }
 }
 #[async_trait::async_trait]
 impl Repo<Users> for UsersRepo {
-    fn new(pool: PgPool) -> Self {
-        Self { pool }
-    }
-    async fn insert(&self, row: &Users) -> Result<(), Error> {
-        sqlx::query_as!(Users, "INSERT INTO users (name) VALUES ($1)", row.name)
-            .execute(&self.pool)
-            .await?;
-        Ok(())
-    }
-    async fn select(&self, criteria: &Users) -> Result<Vec<Users>, Error> {
-        let res = sqlx::query_as::<_, Users>("SELECT id, name FROM users WHERE name = $1")
-            .bind(&criteria.name)
-            .fetch_one(&self.pool)
-            .await?;
-        Ok(vec![res])
+    fn new(db: Db) -> Self {
+        Self { db }
+    }
+    async fn insert(&self, row: &Users) -> Result<Users, Error> {
+        let ctx = crate::audit::current();
+        // Same statement as `audit::audited` builds, spelled out for the `query_as!` check.
+        on_db!(self.db, |conn| {
+            sqlx::query_as!(
+                Users,
+                r#"WITH change AS (
+                    INSERT INTO users (name) VALUES ($3)
+                    RETURNING *, id AS row_id, NULL::jsonb AS before_row, to_jsonb(users) AS after_row
+                ), audit AS (
+                    INSERT INTO audit_log (actor, request_id, table_name, row_id, action, before, after)
+                    SELECT $1, $2, 'users', row_id, 'insert', NULL, after_row FROM change
+                )
+                SELECT id AS "id!", name AS "name!", version AS "version!",
+                    created_at AS "created_at!", updated_at AS "updated_at!", deleted_at
+                FROM change"#,
+                ctx.actor,
+                ctx.request_id,
+                row.name
+            )
+            .fetch_one(conn)
+            .await
+        })
+    }
+    async fn select_with(&self, criteria: &Users, opts: ReadOptions) -> Result<Vec<Users>, Error> {
+        let query = sqlx::query_as::<_, Users>(
+            "SELECT id, name, version, created_at, updated_at, deleted_at FROM users \
+             WHERE name = $1 AND ($2 OR deleted_at IS NULL) ORDER BY id",
+        )
+        .bind(&criteria.name)
+        .bind(opts.include_deleted);
+        on_db!(self.db, |conn| query.fetch_all(conn).await)
+    }
+    async fn delete(&self, criteria: &Users) -> Result<u64, Error> {
+        let id = criteria.id;
+        audit::execute(
+            &self.db,
+            "users",
+            &update("deleted_at = now()", "users.deleted_at IS NULL"),
+            |q| q.bind(id),
+        )
+        .await
     }
+    async fn restore(&self, criteria: &Users) -> Result<u64, Error> {
+        let id = criteria.id;
+        audit::execute(
+            &self.db,
+            "users",
+            &update("deleted_at = NULL", "users.deleted_at IS NOT NULL"),
+            |q| q.bind(id),
+        )
+        .await
+    }
+    /// Removes their posts first, so each one is logged instead of going by `ON DELETE CASCADE`.
+    /// Run it in a transaction to remove both or neither.
+    async fn purge(&self, criteria: &Users) -> Result<u64, Error> {
+        let id = criteria.id;
+        audit::execute(&self.db, "posts", &posts::delete("user_id = $3"), |q| {
+            q.bind(id)
+        })
+        .await?;
+        audit::execute(&self.db, "users", &delete("id = $3"), |q| q.bind(id)).await
+    }
+    /// Removes the posts of those users first, like [`Repo::purge`].
+    async fn purge_deleted_before(&self, before: DateTime<Utc>) -> Result<u64, Error> {
+        let posts = posts::delete("user_id IN (SELECT id FROM users WHERE deleted_at < $3)");
+        audit::execute(&self.db, "posts", &posts, |q| q.bind(before)).await?;
+        audit::execute(&self.db, "users", &delete("deleted_at < $3"), |q| {
+            q.bind(before)
+        })
+        .await
+    }
+}
+
+/// UPDATE of the user `$3`, returning the row before and after for [`audit::audited`].
+fn update(set: &str, filter: &str) -> String {
+    format!(
+        "UPDATE users SET {set} FROM users prev \
+         WHERE users.id = prev.id AND users.id = $3 AND {filter} \
+         RETURNING users.id AS row_id, to_jsonb(prev) AS before_row, to_jsonb(users) AS after_row"
+    )
+}
+
+/// DELETE returning the removed rows for [`audit::audited`].
+fn delete(filter: &str) -> String {
+    format!(
+        "DELETE FROM users WHERE {filter} \
+         RETURNING id AS row_id, to_jsonb(users) AS before_row, NULL::jsonb AS after_row"
+    )
 }
 
-#[derive(FromRow, JsonSchema, Serialize, Default)]
+#[derive(FromRow, JsonSchema, Serialize, Default, Clone)]
 pub struct Users {
     pub id: i64,
     pub name: String,
+    /// Incremented on every change, answered as the `ETag`
+    pub version: i64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,
 }
 impl Table for Users {}
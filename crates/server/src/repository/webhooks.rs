@@ -0,0 +1,323 @@
+//! # webhooks
+//! Webhooks registered by integrators, the deliveries queued for them
+//! by the outbox relay (see `tasks/outbox.rs`), and the log of every attempt.
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{Error, FromRow};
+
+use super::{Db, audit, on_db};
+
+/// Events a webhook may subscribe to, as named by `audit_events` in `migrations/*_add_webhooks.sql`.
+/// There is no `calc.job.completed` : calculations answer in the request, no job outlives it.
+pub const EVENTS: [&str; 9] = [
+    "user.created",
+    "user.updated",
+    "user.deleted",
+    "user.restored",
+    "post.created",
+    "post.updated",
+    "post.published",
+    "post.deleted",
+    "post.restored",
+];
+/// Event of the deliveries queued by [`WebhooksRepo::ping`].
+pub const PING: &str = "webhook.ping";
+
+const COLUMNS: &str = "id, url, secret, events, active, created_at, updated_at";
+const DELIVERY_COLUMNS: &str =
+    "id, webhook_id, event, payload, status, attempts, next_attempt_at, created_at, delivered_at";
+
+#[derive(Clone)]
+pub struct WebhooksRepo {
+    db: Db,
+}
+impl WebhooksRepo {
+    pub fn new(db: Db) -> Self {
+        Self { db }
+    }
+    pub async fn select(&self) -> Result<Vec<Webhook>, Error> {
+        let sql = format!("SELECT {COLUMNS} FROM webhooks ORDER BY id");
+        let query = sqlx::query_as::<_, Webhook>(&sql);
+        on_db!(self.db, |conn| query.fetch_all(conn).await)
+    }
+    pub async fn find(&self, id: i64) -> Result<Option<Webhook>, Error> {
+        let sql = format!("SELECT {COLUMNS} FROM webhooks WHERE id = $1");
+        let query = sqlx::query_as::<_, Webhook>(&sql).bind(id);
+        on_db!(self.db, |conn| query.fetch_optional(conn).await)
+    }
+    pub async fn insert(
+        &self,
+        url: String,
+        secret: String,
+        events: Vec<String>,
+    ) -> Result<Webhook, Error> {
+        let change = format!(
+            "INSERT INTO webhooks (url, secret, events) VALUES ($3, $4, $5) \
+             RETURNING *, id AS row_id, NULL::jsonb AS before_row, {} AS after_row",
+            row_json("webhooks")
+        );
+        audit::fetch_one(
+            &self.db,
+            "webhooks",
+            &change,
+            &format!("SELECT {COLUMNS} FROM change"),
+            |q| q.bind(url).bind(secret).bind(events),
+        )
+        .await
+    }
+    /// Changes the given fields, leaving the `None` ones as they are.
+    /// Fails with `RowNotFound` when there is no webhook `id`.
+    pub async fn update(
+        &self,
+        id: i64,
+        url: Option<String>,
+        events: Option<Vec<String>>,
+        active: Option<bool>,
+    ) -> Result<Webhook, Error> {
+        let change = format!(
+            "UPDATE webhooks SET url = COALESCE($4, webhooks.url), \
+                 events = COALESCE($5, webhooks.events), active = COALESCE($6, webhooks.active) \
+             FROM webhooks prev \
+             WHERE webhooks.id = prev.id AND webhooks.id = $3 \
+             RETURNING webhooks.*, webhooks.id AS row_id, {} AS before_row, {} AS after_row",
+            row_json("prev"),
+            row_json("webhooks")
+        );
+        audit::fetch_one(
+            &self.db,
+            "webhooks",
+            &change,
+            &format!("SELECT {COLUMNS} FROM change"),
+            |q| q.bind(id).bind(url).bind(events).bind(active),
+        )
+        .await
+    }
+    /// Removes the webhook and its deliveries, returning the number of removed webhooks.
+    pub async fn delete(&self, id: i64) -> Result<u64, Error> {
+        let change = format!(
+            "DELETE FROM webhooks WHERE id = $3 \
+             RETURNING id AS row_id, {} AS before_row, NULL::jsonb AS after_row",
+            row_json("webhooks")
+        );
+        audit::execute(&self.db, "webhooks", &change, |q| q.bind(id)).await
+    }
+
+    /// Queues a delivery of the outbox events `ids` for every active webhook subscribed to them.
+    pub async fn enqueue(&self, ids: &[i64]) -> Result<u64, Error> {
+        let query = sqlx::query(
+            "INSERT INTO webhook_deliveries (webhook_id, event, payload) \
+             SELECT webhooks.id, outbox.event, outbox.payload \
+             FROM outbox JOIN webhooks ON webhooks.active AND outbox.event = ANY (webhooks.events) \
+             WHERE outbox.id = ANY($1) ORDER BY outbox.id, webhooks.id",
+        )
+        .bind(ids);
+        let res = on_db!(self.db, |conn| query.execute(conn).await)?;
+        Ok(res.rows_affected())
+    }
+    /// Queues a `webhook.ping` delivery, whatever the webhook subscribed to.
+    pub async fn ping(&self, id: i64) -> Result<Delivery, Error> {
+        let sql = format!(
+            "INSERT INTO webhook_deliveries (webhook_id, event, payload) \
+             VALUES ($1, $2, jsonb_build_object('event', $2::text, 'occurred_at', now(), \
+                 'data', jsonb_build_object('webhook_id', $1::bigint))) \
+             RETURNING {DELIVERY_COLUMNS}"
+        );
+        let query = sqlx::query_as::<_, Delivery>(&sql).bind(id).bind(PING);
+        on_db!(self.db, |conn| query.fetch_one(conn).await)
+    }
+    /// Deliveries of `webhook_id`, newest first.
+    pub async fn deliveries(
+        &self,
+        webhook_id: i64,
+        status: Option<DeliveryStatus>,
+        limit: i64,
+    ) -> Result<Vec<Delivery>, Error> {
+        let sql = format!(
+            "SELECT {DELIVERY_COLUMNS} FROM webhook_deliveries \
+             WHERE webhook_id = $1 AND ($2::text IS NULL OR status = $2) \
+             ORDER BY id DESC LIMIT $3"
+        );
+        let query = sqlx::query_as::<_, Delivery>(&sql)
+            .bind(webhook_id)
+            .bind(status)
+            .bind(limit);
+        on_db!(self.db, |conn| query.fetch_all(conn).await)
+    }
+    pub async fn find_delivery(&self, webhook_id: i64, id: i64) -> Result<Option<Delivery>, Error> {
+        let sql = format!(
+            "SELECT {DELIVERY_COLUMNS} FROM webhook_deliveries WHERE webhook_id = $1 AND id = $2"
+        );
+        let query = sqlx::query_as::<_, Delivery>(&sql)
+            .bind(webhook_id)
+            .bind(id);
+        on_db!(self.db, |conn| query.fetch_optional(conn).await)
+    }
+    /// Attempts of `delivery_id`, oldest first.
+    pub async fn attempts(&self, delivery_id: i64) -> Result<Vec<Attempt>, Error> {
+        let query = sqlx::query_as::<_, Attempt>(
+            "SELECT attempt, response_status, response_body, error, duration_ms, created_at \
+             FROM webhook_attempts WHERE delivery_id = $1 ORDER BY id",
+        )
+        .bind(delivery_id);
+        on_db!(self.db, |conn| query.fetch_all(conn).await)
+    }
+    /// Queues a delivery that is not delivered yet again, with a fresh set of attempts.
+    pub async fn retry(&self, webhook_id: i64, id: i64) -> Result<Option<Delivery>, Error> {
+        let sql = format!(
+            "UPDATE webhook_deliveries SET status = 'pending', attempts = 0, next_attempt_at = now() \
+             WHERE webhook_id = $1 AND id = $2 AND status <> 'delivered' \
+             RETURNING {DELIVERY_COLUMNS}"
+        );
+        let query = sqlx::query_as::<_, Delivery>(&sql)
+            .bind(webhook_id)
+            .bind(id);
+        on_db!(self.db, |conn| query.fetch_optional(conn).await)
+    }
+
+    /// Takes up to `limit` due deliveries of active webhooks, oldest first.
+    /// They are leased for `lease`: another sender takes them again only once it ends,
+    /// so one that crashes mid-delivery is retried.
+    pub async fn claim_due(&self, limit: i64, lease: Duration) -> Result<Vec<Due>, Error> {
+        let query = sqlx::query_as::<_, Due>(
+            "WITH due AS ( \
+                 SELECT d.id FROM webhook_deliveries d JOIN webhooks w ON w.id = d.webhook_id \
+                 WHERE d.status = 'pending' AND d.next_attempt_at <= now() AND w.active \
+                 ORDER BY d.next_attempt_at LIMIT $1 \
+                 FOR UPDATE OF d SKIP LOCKED \
+             ) \
+             UPDATE webhook_deliveries d SET next_attempt_at = now() + make_interval(secs => $2) \
+             FROM due, webhooks w \
+             WHERE d.id = due.id AND w.id = d.webhook_id \
+             RETURNING d.id, d.event, d.payload, d.attempts, w.url, w.secret",
+        )
+        .bind(limit)
+        .bind(lease.as_secs_f64());
+        on_db!(self.db, |conn| query.fetch_all(conn).await)
+    }
+    /// Logs an attempt at `due` and moves the delivery on:
+    /// `delivered`, `pending` again in `retry_in`, or `dead` without `retry_in`.
+    pub async fn record(
+        &self,
+        due: &Due,
+        result: &AttemptResult,
+        retry_in: Option<Duration>,
+    ) -> Result<(), Error> {
+        let attempt = due.attempts + 1;
+        let status = if result.is_success() {
+            DeliveryStatus::Delivered
+        } else if retry_in.is_some() {
+            DeliveryStatus::Pending
+        } else {
+            DeliveryStatus::Dead
+        };
+        let query = sqlx::query(
+            "WITH attempt AS ( \
+                 INSERT INTO webhook_attempts \
+                     (delivery_id, attempt, response_status, response_body, error, duration_ms) \
+                 VALUES ($1, $2, $3, $4, $5, $6) \
+             ) \
+             UPDATE webhook_deliveries SET attempts = $2, status = $7, \
+                 next_attempt_at = COALESCE(now() + make_interval(secs => $8), next_attempt_at), \
+                 delivered_at = CASE WHEN $7 = 'delivered' THEN now() END \
+             WHERE id = $1",
+        )
+        .bind(due.id)
+        .bind(attempt)
+        .bind(result.response_status)
+        .bind(&result.response_body)
+        .bind(&result.error)
+        .bind(result.duration_ms)
+        .bind(status)
+        .bind(retry_in.map(|d| d.as_secs_f64()));
+        on_db!(self.db, |conn| query.execute(conn).await)?;
+        Ok(())
+    }
+}
+
+/// The row as JSON, without its secret.
+fn row_json(alias: &str) -> String {
+    format!("to_jsonb({alias}) - 'secret'")
+}
+
+#[derive(FromRow, Serialize, JsonSchema, Clone)]
+pub struct Webhook {
+    pub id: i64,
+    pub url: String,
+    /// Only answered when the webhook is created
+    #[serde(skip)]
+    pub secret: String,
+    /// Names of the events sent, e.g. `post.published`
+    pub events: Vec<String>,
+    /// Inactive webhooks keep their pending deliveries until activated again
+    pub active: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(sqlx::Type, Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    /// Every attempt failed, retried by hand only
+    Dead,
+}
+
+#[derive(FromRow, Serialize, JsonSchema)]
+pub struct Delivery {
+    /// Also sent as `Webhook-Id`, the same across attempts
+    pub id: i64,
+    pub webhook_id: i64,
+    pub event: String,
+    /// Body sent
+    pub payload: Value,
+    pub status: DeliveryStatus,
+    /// Attempts since queued or retried by hand
+    pub attempts: i32,
+    /// When a `pending` delivery is attempted next
+    pub next_attempt_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+}
+
+#[derive(FromRow, Serialize, JsonSchema)]
+pub struct Attempt {
+    pub attempt: i32,
+    /// `None` when no response came back, see `error`
+    pub response_status: Option<i32>,
+    /// First KiB of the response
+    pub response_body: Option<String>,
+    pub error: Option<String>,
+    pub duration_ms: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A delivery taken by [`WebhooksRepo::claim_due`], with where and how to send it.
+#[derive(FromRow)]
+pub struct Due {
+    pub id: i64,
+    pub event: String,
+    pub payload: Value,
+    pub attempts: i32,
+    pub url: String,
+    pub secret: String,
+}
+
+/// What an attempt got back.
+pub struct AttemptResult {
+    pub response_status: Option<i32>,
+    pub response_body: Option<String>,
+    pub error: Option<String>,
+    pub duration_ms: i32,
+}
+impl AttemptResult {
+    pub fn is_success(&self) -> bool {
+        matches!(self.response_status, Some(200..=299))
+    }
+}
@@ -0,0 +1,263 @@
+//! # admin
+//! Moderation APIs, guarded by `X-Auth-Key`.
+
+use aide::axum::{
+    ApiRouter,
+    routing::{delete, get, post},
+};
+use axum::extract::State;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::{
+    auth::AdminKey,
+    error::AppError,
+    etag::IfMatch,
+    markdown::Render,
+    prelude::*,
+    repository::{
+        ReadOptions, Repo, RepoFactory,
+        audit::{AuditEntry, AuditFilter},
+        posts::Posts,
+        users::Users,
+    },
+    state::ReposState,
+    validation::Valid,
+};
+
+/// # Admin
+/// Mounted with `RouteRegistry::mount` in `routes/mod.rs`.
+pub struct Admin;
+impl RouteModule for Admin {
+    type State = ReposState;
+    const NAME: &'static str = "admin";
+    const DESCRIPTION: &'static str = "Moderation APIs, require `X-Auth-Key`";
+    const PREFIX: Option<&'static str> = Some("/admin");
+
+    fn router() -> ApiRouter<ReposState> {
+        ApiRouter::new()
+            .api_route("/users", get(get_users))
+            .api_route("/users/{id}", delete(purge_user))
+            .api_route("/users/{id}/restore", post(restore_user))
+            .api_route("/posts", get(get_posts))
+            .api_route("/posts/{id}", delete(purge_post))
+            .api_route("/posts/{id}/restore", post(restore_post))
+            .api_route("/audit", get(get_audit))
+    }
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct Affected {
+    rows: u64,
+}
+
+fn affected(rows: u64, what: &'static str) -> Result<Json<ApiResponse<Affected>>, AppError> {
+    if rows == 0 {
+        return Err(AppError::NotFound(what));
+    }
+    Ok(Json(ApiResponse {
+        code: 0,
+        resp: "ok".to_string(),
+        data: Affected { rows },
+    }))
+}
+
+/// # Finds users by name, deleted ones included on request
+/// Several deleted users may have had the same name, they are listed oldest first.
+pub async fn get_users(
+    _: AdminKey,
+    State(state): State<Arc<RepoFactory>>,
+    Valid(Query(query)): Valid<Query<GetUsersQuery>>,
+) -> Result<Json<ApiResponse<Vec<Users>>>, AppError> {
+    let criteria = Users {
+        name: query.name,
+        ..Default::default()
+    };
+    let opts = ReadOptions {
+        include_deleted: query.include_deleted,
+        ..Default::default()
+    };
+    let users = state.user.select_with(&criteria, opts).await?;
+    if users.is_empty() {
+        return Err(AppError::NotFound("User"));
+    }
+    Ok(Json(ApiResponse {
+        code: 0,
+        resp: "ok".to_string(),
+        data: users,
+    }))
+}
+#[derive(Deserialize, JsonSchema, Validate)]
+pub struct GetUsersQuery {
+    #[validate(length(min = 1))]
+    name: String,
+    #[serde(default)]
+    include_deleted: bool,
+}
+
+/// # Lists posts of a user, whatever their status, deleted ones included on request
+pub async fn get_posts(
+    _: AdminKey,
+    State(state): State<Arc<RepoFactory>>,
+    Query(query): Query<GetPostsQuery>,
+) -> Result<Json<ApiResponse<Vec<Posts>>>, AppError> {
+    let criteria = Posts {
+        user_id: query.user_id,
+        ..Default::default()
+    };
+    let opts = ReadOptions {
+        include_deleted: query.include_deleted,
+        include_unpublished: true,
+        ..Default::default()
+    };
+    let posts = state.posts.select_with(&criteria, opts).await?;
+    Ok(Json(ApiResponse {
+        code: 0,
+        resp: "ok".to_string(),
+        data: posts.into_iter().map(|p| p.render(query.render)).collect(),
+    }))
+}
+#[derive(Deserialize, JsonSchema)]
+pub struct GetPostsQuery {
+    user_id: i64,
+    #[serde(default)]
+    include_deleted: bool,
+    #[serde(default)]
+    render: Render,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct IdPath {
+    id: i64,
+}
+
+/// # Restores a deleted user
+/// Posts deleted together with the user come back as well.
+/// Answers 409 when a live user has taken its name since.
+pub async fn restore_user(
+    _: AdminKey,
+    State(state): State<Arc<RepoFactory>>,
+    Path(path): Path<IdPath>,
+) -> Result<Json<ApiResponse<Affected>>, AppError> {
+    let rows = state
+        .transaction(|uow| async move {
+            let opts = ReadOptions {
+                include_deleted: true,
+                ..Default::default()
+            };
+            let Some(deleted_at) = uow
+                .user
+                .find(path.id, opts)
+                .await?
+                .and_then(|user| user.deleted_at)
+            else {
+                return Ok(0);
+            };
+            let criteria = Users {
+                id: path.id,
+                ..Default::default()
+            };
+            let users = uow.user.restore(&criteria).await?;
+            let posts = uow.posts.restore_by_user(path.id, deleted_at).await?;
+            Ok(users + posts)
+        })
+        .await?;
+    affected(rows, "Deleted user")
+}
+
+/// # Removes a user and their posts for good
+/// Each post is logged in `audit_log` on its own.
+/// Requires `If-Match` with the current `ETag`, deleted or not.
+pub async fn purge_user(
+    _: AdminKey,
+    State(state): State<Arc<RepoFactory>>,
+    Path(path): Path<IdPath>,
+    if_match: IfMatch,
+) -> Result<Json<ApiResponse<Affected>>, AppError> {
+    let rows = state
+        .transaction(|uow| async move {
+            let opts = ReadOptions {
+                include_deleted: true,
+                lock: true,
+                ..Default::default()
+            };
+            let Some(current) = uow.user.find(path.id, opts).await? else {
+                return Ok(0);
+            };
+            if_match.check(current.version)?;
+            Ok(uow.user.purge(&current).await?)
+        })
+        .await?;
+    affected(rows, "User")
+}
+
+/// # Restores a deleted post
+pub async fn restore_post(
+    _: AdminKey,
+    State(state): State<Arc<RepoFactory>>,
+    Path(path): Path<IdPath>,
+) -> Result<Json<ApiResponse<Affected>>, AppError> {
+    let criteria = Posts {
+        id: path.id,
+        ..Default::default()
+    };
+    affected(state.posts.restore(&criteria).await?, "Deleted post")
+}
+
+/// # Removes a post for good
+/// Requires `If-Match` with the current `ETag`, deleted or not.
+pub async fn purge_post(
+    _: AdminKey,
+    State(state): State<Arc<RepoFactory>>,
+    Path(path): Path<IdPath>,
+    if_match: IfMatch,
+) -> Result<Json<ApiResponse<Affected>>, AppError> {
+    let rows = state
+        .transaction(|uow| async move {
+            let opts = ReadOptions {
+                include_deleted: true,
+                include_unpublished: true,
+                lock: true,
+            };
+            let Some(current) = uow.posts.find(path.id, opts).await? else {
+                return Ok(0);
+            };
+            if_match.check(current.version)?;
+            Ok(uow.posts.purge(&current).await?)
+        })
+        .await?;
+    affected(rows, "Post")
+}
+
+/// # Lists audit log entries, newest first
+/// `from` is inclusive and `to` exclusive.
+pub async fn get_audit(
+    _: AdminKey,
+    State(state): State<Arc<RepoFactory>>,
+    Valid(Query(query)): Valid<Query<GetAuditQuery>>,
+) -> Result<Json<ApiResponse<Vec<AuditEntry>>>, AppError> {
+    let filter = AuditFilter {
+        actor: query.actor,
+        table: query.table,
+        from: query.from,
+        to: query.to,
+        limit: query.limit.unwrap_or(100),
+    };
+    Ok(Json(ApiResponse {
+        code: 0,
+        resp: "ok".to_string(),
+        data: state.audit.select(&filter).await?,
+    }))
+}
+#[derive(Deserialize, JsonSchema, Validate)]
+pub struct GetAuditQuery {
+    actor: Option<String>,
+    table: Option<String>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    /// 100 when left out
+    #[validate(range(min = 1, max = 1000))]
+    limit: Option<i64>,
+}
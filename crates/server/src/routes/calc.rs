@@ -1,58 +1,64 @@
-use aide::axum::{ApiRouter, routing::get_with};
-use axum::{Json, extract::Query};
+use aide::axum::{ApiRouter, routing::get};
+use axum::extract::State;
 use log::{error, info};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use validator::Validate;
 
 use crate::{
     prelude::*,
-    services::{fibo, hanoi},
+    services::{
+        fibo::{self, FiboCache},
+        hanoi,
+    },
+    shutdown::Shutdown,
+    state::CalcState,
+    validation::Valid,
 };
 
-/// # get_router
-/// Adds route easily in `main.rs` file.
-pub fn get_router() -> (Option<Tag>, ApiRouter) {
-    (
-        Some(Tag {
-            name: "calc".to_string(),
-            description: Some("API for custom calculations".to_string()),
-            ..Default::default()
-        }),
+/// # Calc
+/// Mounted with `RouteRegistry::mount` in `routes/mod.rs`.
+pub struct Calc;
+impl RouteModule for Calc {
+    type State = CalcState;
+    const NAME: &'static str = "calc";
+    const DESCRIPTION: &'static str = "API for custom calculations";
+    const PREFIX: Option<&'static str> = Some("/calc");
+
+    fn router() -> ApiRouter<CalcState> {
         ApiRouter::new()
-            .api_route("/fibo", get_with(fibo, |op| op.tag("calc")))
-            .api_route("/hanoi", get_with(hanoi, |op| op.tag("calc")))
-            .with_prefix("/calc"),
-    )
+            .api_route("/fibo", get(fibo))
+            .api_route("/hanoi", get(hanoi))
+    }
 }
 
 /// # API for calculating n'th Fibonacci number
-pub async fn fibo(Query(query): Query<FiboQuery>) -> Json<ApiResponse<String>> {
+pub async fn fibo(
+    State(cache): State<FiboCache>,
+    Valid(Query(query)): Valid<Query<FiboQuery>>,
+) -> Json<ApiResponse<String>> {
     info!("user requests fibonacci {}'th number", query.n);
-    if query.n > 5_000 {
-        Json(ApiResponse {
-            code: 1,
-            resp: "Bad Request".to_string(),
-            data: "The number is too big! (>5,000)".to_string(),
-        })
-    } else {
-        Json(ApiResponse {
-            code: 0,
-            resp: "ok".to_string(),
-            data: fibo::calc_fibo_rec(query.n).to_string(),
-        })
-    }
+    Json(ApiResponse {
+        code: 0,
+        resp: "ok".to_string(),
+        data: fibo::calc_fibo_rec(query.n, &cache).to_string(),
+    })
 }
-#[derive(Deserialize, JsonSchema)]
+#[derive(Deserialize, JsonSchema, Validate)]
 pub struct FiboQuery {
+    #[validate(range(max = 5_000))]
     n: usize,
 }
 
 /// # API for calculating n'th Hanoi's tower
-pub async fn hanoi(Query(query): Query<HanoiQuery>) -> Json<ApiResponse<HanoiResponse>> {
+pub async fn hanoi(
+    State(shutdown): State<Shutdown>,
+    Valid(Query(query)): Valid<Query<HanoiQuery>>,
+) -> Json<ApiResponse<HanoiResponse>> {
     let mut res_default = ApiResponse::<HanoiResponse>::default();
     info!("user requests hanoi {}'th squence", query.n);
     if query.n < 15 {
-        res_default = match hanoi::calc_hanoi_rec(query.n).await {
+        res_default = match hanoi::calc_hanoi_rec(query.n, &shutdown).await {
             Ok(res) => res_default
                 .code(0)
                 .resp("ok".to_string())
@@ -61,14 +67,12 @@ pub async fn hanoi(Query(query): Query<HanoiQuery>) -> Json<ApiResponse<HanoiRes
                     orders: Some(res),
                 }),
             Err(err) => {
-                error!("Thread join error occur!: {}", err);
-                res_default
-                    .code(-1)
-                    .resp("Thread join Error occur!".to_string())
+                error!("Hanoi calculation failed: {}", err);
+                res_default.code(-1).resp(err.to_string())
             }
         };
-    } else if query.n < 10_000_000 {
-        let num_replacement = hanoi::calc_hanoi_num(query.n).await;
+    } else {
+        let num_replacement = hanoi::calc_hanoi_num(query.n, &shutdown).await;
         res_default = match num_replacement {
             Ok(v) => res_default
                 .code(1)
@@ -78,21 +82,17 @@ pub async fn hanoi(Query(query): Query<HanoiQuery>) -> Json<ApiResponse<HanoiRes
                     ..Default::default()
                 }),
             Err(err) => {
-                error!("Thread join error occur!: {}", err);
-                res_default
-                    .code(-1)
-                    .resp("Thread join Error occur!".to_string())
+                error!("Hanoi calculation failed: {}", err);
+                res_default.code(-1).resp(err.to_string())
             }
         };
-    } else {
-        res_default = res_default
-            .code(2)
-            .resp("Input number exceeds limit (10,000,000)".to_string());
     }
     Json(res_default)
 }
-#[derive(Deserialize, JsonSchema)]
+#[derive(Deserialize, JsonSchema, Validate)]
 pub struct HanoiQuery {
+    /// Orders are listed up to 14, only counted above
+    #[validate(range(min = 1, max = 9_999_999))]
     n: usize,
 }
 #[derive(Serialize, JsonSchema, Default)]
@@ -100,3 +100,35 @@ pub struct HanoiResponse {
     num_replacement: String,
     orders: Option<Vec<(u8, u8)>>,
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        extract::FromRequestParts,
+        http::{Request, StatusCode},
+        response::IntoResponse,
+    };
+
+    use super::*;
+
+    async fn hanoi_query(n: &str) -> Result<HanoiQuery, StatusCode> {
+        let (mut parts, _) = Request::builder()
+            .uri(format!("/calc/hanoi?n={}", n))
+            .body(())
+            .unwrap()
+            .into_parts();
+        Valid::<Query<HanoiQuery>>::from_request_parts(&mut parts, &())
+            .await
+            .map(|Valid(Query(query))| query)
+            .map_err(|err| err.into_response().status())
+    }
+
+    #[tokio::test]
+    async fn hanoi_rejects_zero_discs() {
+        assert_eq!(
+            hanoi_query("0").await.err(),
+            Some(StatusCode::UNPROCESSABLE_ENTITY)
+        );
+        assert_eq!(hanoi_query("1").await.map(|query| query.n), Ok(1));
+    }
+}
@@ -3,92 +3,178 @@
 //! # database
 //! module testing my database
 
-use aide::axum::{ApiRouter, routing::get};
-use axum::{
-    Json,
-    extract::{Query, State},
+use aide::axum::{
+    ApiRouter,
+    routing::{delete, get, post},
 };
-use log::error;
+use axum::extract::State;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use validator::Validate;
 
 use crate::{
+    error::AppError,
+    etag::IfMatch,
     prelude::*,
-    repository::{Repo, RepoFactory, posts::Posts, users::Users},
+    repository::{ReadOptions, Repo, RepoFactory, posts::Posts, users::Users},
+    validation::{MAX_CONTENT_LEN, MAX_TITLE_LEN, MAX_USER_NAME_LEN, USER_NAME, Valid},
 };
 
-pub fn get_router(state: Arc<RepoFactory>) -> (Option<Tag>, ApiRouter) {
-    (
-        Some(Tag {
-            name: "database".to_string(),
-            description: Some("APIs for manipulating database".to_string()),
-            ..Default::default()
-        }),
+pub struct Database;
+impl RouteModule for Database {
+    type State = Arc<RepoFactory>;
+    const NAME: &'static str = "database";
+    const DESCRIPTION: &'static str = "APIs for manipulating database";
+    const PREFIX: Option<&'static str> = Some("/db");
+
+    fn router() -> ApiRouter<Arc<RepoFactory>> {
         ApiRouter::new()
             .api_route("/get_user", get(get_user))
             .api_route("/set_user", get(set_user))
-            .with_state(state)
-            .with_prefix("/db")
-            .with_tag("database"),
-    )
+            .api_route("/set_user_with_post", post(set_user_with_post))
+            .api_route("/delete_user", delete(delete_user))
+    }
 }
 
 pub async fn get_user(
     State(state): State<Arc<RepoFactory>>,
-    Query(query): Query<GetUserQuery>,
-) -> Json<ApiResponse<Vec<Users>>> {
+    Valid(Query(query)): Valid<Query<GetUserQuery>>,
+) -> Result<Json<ApiResponse<Vec<Users>>>, AppError> {
     let state = state.user.clone();
     let criteria = Users {
         name: query.name,
         ..Default::default()
     };
-    match state.select(&criteria).await {
-        Ok(v) => Json(ApiResponse {
-            code: 0,
-            resp: "ok".to_string(),
-            data: v,
-        }),
-        Err(err) => {
-            error!("Error occur: {}", err);
-            Json(ApiResponse {
-                code: 1,
-                resp: format!("Error occur: {}", err),
-                ..Default::default()
-            })
-        }
+    let users = state.select(&criteria).await?;
+    if users.is_empty() {
+        return Err(AppError::NotFound("User"));
     }
+    Ok(Json(ApiResponse {
+        code: 0,
+        resp: "ok".to_string(),
+        data: users,
+    }))
 }
-#[derive(Deserialize, JsonSchema)]
+#[derive(Deserialize, JsonSchema, Validate)]
 pub struct GetUserQuery {
+    #[validate(length(min = 1))]
     name: String,
 }
 
+/// # Creates a user
+/// Answers 409 when the name is already taken.
 pub async fn set_user(
     State(state): State<Arc<RepoFactory>>,
-    Query(query): Query<SetUserQuery>,
-) -> Json<ApiResponse<Empty>> {
+    Valid(Query(query)): Valid<Query<SetUserQuery>>,
+) -> Result<Json<ApiResponse<Users>>, AppError> {
     let row = Users {
         name: query.name,
         ..Default::default()
     };
-    match state.user.insert(&row).await {
-        Ok(..) => Json(ApiResponse {
-            code: 0,
-            resp: "ok".to_string(),
-            ..Default::default()
-        }),
-        Err(err) => Json(ApiResponse {
-            code: 1,
-            resp: format!("Error occur: {}", err),
-            ..Default::default()
-        }),
-    }
+    Ok(Json(ApiResponse {
+        code: 0,
+        resp: "ok".to_string(),
+        data: state.user.insert(&row).await?,
+    }))
 }
-#[derive(Deserialize, JsonSchema)]
+#[derive(Deserialize, JsonSchema, Validate)]
 pub struct SetUserQuery {
+    #[validate(length(min = 1, max = MAX_USER_NAME_LEN), regex(path = *USER_NAME))]
     name: String,
 }
 
+/// # Creates a user together with their first post
+/// Both rows are written in one transaction.
+pub async fn set_user_with_post(
+    State(state): State<Arc<RepoFactory>>,
+    Valid(Json(body)): Valid<Json<SetUserWithPostBody>>,
+) -> Result<Json<ApiResponse<UserWithPosts>>, AppError> {
+    let data = state
+        .transaction(|uow| async move {
+            let user = uow
+                .user
+                .insert(&Users {
+                    name: body.name,
+                    ..Default::default()
+                })
+                .await?;
+            let post = uow
+                .posts
+                .insert(&Posts {
+                    title: body.title,
+                    content: body.content,
+                    user_id: user.id,
+                    ..Default::default()
+                })
+                .await?;
+            Ok(UserWithPosts {
+                user,
+                posts: vec![post],
+            })
+        })
+        .await?;
+    Ok(Json(ApiResponse {
+        code: 0,
+        resp: "ok".to_string(),
+        data,
+    }))
+}
+#[derive(Deserialize, JsonSchema, Validate)]
+pub struct SetUserWithPostBody {
+    #[validate(length(min = 1, max = MAX_USER_NAME_LEN), regex(path = *USER_NAME))]
+    name: String,
+    #[validate(length(min = 1, max = MAX_TITLE_LEN))]
+    title: String,
+    #[validate(length(max = MAX_CONTENT_LEN))]
+    content: String,
+}
+#[derive(Serialize, JsonSchema)]
+pub struct UserWithPosts {
+    user: Users,
+    posts: Vec<Posts>,
+}
+
+/// # Deletes a user and every post they wrote
+/// Both deletes run in one transaction.
+/// Rows are soft-deleted, admins can restore them until they are purged.
+/// Requires `If-Match` with the current `ETag` of the user.
+pub async fn delete_user(
+    State(state): State<Arc<RepoFactory>>,
+    Query(query): Query<DeleteUserQuery>,
+    if_match: IfMatch,
+) -> Result<Json<ApiResponse<DeleteUserResponse>>, AppError> {
+    let data = state
+        .transaction(|uow| async move {
+            let opts = ReadOptions {
+                lock: true,
+                ..Default::default()
+            };
+            let current = uow
+                .user
+                .find(query.id, opts)
+                .await?
+                .ok_or(AppError::NotFound("User"))?;
+            if_match.check(current.version)?;
+            let deleted_posts = uow.posts.delete_by_user(query.id).await?;
+            uow.user.delete(&current).await?;
+            Ok(DeleteUserResponse { deleted_posts })
+        })
+        .await?;
+    Ok(Json(ApiResponse {
+        code: 0,
+        resp: "ok".to_string(),
+        data,
+    }))
+}
+#[derive(Deserialize, JsonSchema)]
+pub struct DeleteUserQuery {
+    id: i64,
+}
+#[derive(Serialize, JsonSchema)]
+pub struct DeleteUserResponse {
+    deleted_posts: u64,
+}
+
 pub async fn get_post(
     State(state): State<std::sync::Arc<RepoFactory>>,
     Query(_query): Query<SetUserQuery>,
@@ -0,0 +1,295 @@
+//! # events
+//! Live changes of `events.rs`, as Server-Sent Events on `GET /events`
+//! or over a WebSocket on `GET /ws`.
+//! Both take the topics to follow in `topics`, e.g. `?topics=post:12,user:3`,
+//! and `X-Auth-Key` to also follow posts that are not published.
+//! Changes only say what changed, read the resource back for its content.
+//! ## WebSocket messages
+//! The client sends `{"subscribe": ["post:12"]}` or `{"unsubscribe": ["post:12"]}`,
+//! the server answers with the topics now followed, `{"type": "topics", "topics": [...]}`,
+//! then sends `{"type": "change", ...}` for every change.
+
+use std::{convert::Infallible, time::Duration};
+
+use aide::{
+    OperationOutput,
+    axum::{ApiRouter, routing::get},
+    generate::GenContext,
+    openapi::{MediaType, Operation, Response, SchemaObject, StatusCode},
+};
+use axum::{
+    extract::{
+        State,
+        ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade, close_code},
+    },
+    http::HeaderMap,
+    response::{
+        IntoResponse,
+        sse::{Event, KeepAlive, Sse},
+    },
+};
+use futures_util::{Stream, stream};
+use indexmap::IndexMap;
+use schemars::JsonSchema;
+
+use crate::{
+    auth,
+    config::Config,
+    error::AppError,
+    events::{Change, Hub, Received, Subscription, Topic},
+    prelude::*,
+    shutdown::Shutdown,
+    state::EventsState,
+    validation::FieldError,
+};
+
+/// Topics one client may follow at once.
+const MAX_TOPICS: usize = 32;
+/// Comment sent when nothing happens, so proxies keep the stream open.
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// # Events
+/// Mounted with `RouteRegistry::mount` in `routes/mod.rs`, outside of API versions
+/// as streams carry no `ApiResponse` envelope.
+pub struct Events;
+impl RouteModule for Events {
+    type State = EventsState;
+    const NAME: &'static str = "events";
+    const DESCRIPTION: &'static str = "Live changes to users and posts, over SSE or WebSocket";
+    const PREFIX: Option<&'static str> = None;
+
+    fn router() -> ApiRouter<EventsState> {
+        ApiRouter::new()
+            .api_route("/events", get(sse))
+            .api_route("/ws", get(ws))
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct SseQuery {
+    /// Comma separated `posts`, `post:{id}` or `user:{id}`, at most 32
+    topics: String,
+}
+
+/// # Streams changes as Server-Sent Events
+/// Each change is a `change` event whose `id` is the `Change` id.
+/// A `lagged` event, holding the number of changes missed, means the client was too slow
+/// and should read the resources it follows again.
+/// Send `X-Auth-Key` to also receive changes to posts that are not published.
+pub async fn sse(
+    State(hub): State<Hub>,
+    State(config): State<Arc<Config>>,
+    State(shutdown): State<Shutdown>,
+    headers: HeaderMap,
+    Query(query): Query<SseQuery>,
+) -> Result<ChangeStream<impl Stream<Item = Result<Event, Infallible>>>, AppError> {
+    let topics = parse_topics(query.topics.split(',')).map_err(invalid_topics)?;
+    let admin = auth::is_admin(&config, &headers);
+    let subscription = hub.subscribe(topics, admin, shutdown);
+    let events = stream::unfold(subscription, |mut subscription| async move {
+        let event = match subscription.next().await? {
+            Received::Change(change) => Event::default()
+                .event("change")
+                .id(change.id.to_string())
+                .json_data(&*change)
+                .unwrap_or_else(|_| Event::default().event("change")),
+            Received::Lagged(missed) => Event::default().event("lagged").data(missed.to_string()),
+        };
+        Some((Ok(event), subscription))
+    });
+    Ok(ChangeStream(
+        Sse::new(events).keep_alive(KeepAlive::new().interval(KEEP_ALIVE)),
+    ))
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct WsQuery {
+    /// Comma separated `posts`, `post:{id}` or `user:{id}` to follow from the start
+    topics: Option<String>,
+}
+
+/// # Streams changes over a WebSocket
+/// Topics are changed while connected with `subscribe` and `unsubscribe` messages,
+/// see the `events` module. The socket is closed when the server shuts down.
+/// Send `X-Auth-Key` to also receive changes to posts that are not published.
+pub async fn ws(
+    State(hub): State<Hub>,
+    State(config): State<Arc<Config>>,
+    State(shutdown): State<Shutdown>,
+    headers: HeaderMap,
+    Query(query): Query<WsQuery>,
+    upgrade: WebSocketUpgrade,
+) -> Result<axum::response::Response, AppError> {
+    let topics = match &query.topics {
+        Some(topics) => parse_topics(topics.split(',')).map_err(invalid_topics)?,
+        None => Vec::new(),
+    };
+    let admin = auth::is_admin(&config, &headers);
+    let subscription = hub.subscribe(topics, admin, shutdown);
+    Ok(upgrade.on_upgrade(|socket| serve_socket(socket, subscription)))
+}
+
+/// Sent by the client.
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ClientMessage {
+    Subscribe(Vec<String>),
+    Unsubscribe(Vec<String>),
+}
+
+/// Sent by the server.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ServerMessage {
+    Change(Arc<Change>),
+    /// The client was too slow and `missed` changes were dropped
+    Lagged {
+        missed: u64,
+    },
+    /// Topics followed once a `subscribe` or `unsubscribe` is applied
+    Topics {
+        topics: Vec<String>,
+    },
+    /// A message could not be applied, nothing changed
+    Error {
+        message: String,
+    },
+}
+
+async fn serve_socket(mut socket: WebSocket, mut subscription: Subscription) {
+    loop {
+        let message = tokio::select! {
+            received = subscription.next() => match received {
+                Some(Received::Change(change)) => ServerMessage::Change(change),
+                Some(Received::Lagged(missed)) => ServerMessage::Lagged { missed },
+                None => {
+                    let close = CloseFrame {
+                        code: close_code::AWAY,
+                        reason: "Server is shutting down".into(),
+                    };
+                    let _ = socket.send(Message::Close(Some(close))).await;
+                    return;
+                }
+            },
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => apply(&mut subscription, &text),
+                // Pings are answered by axum
+                Some(Ok(Message::Ping(..) | Message::Pong(..) | Message::Binary(..))) => continue,
+                Some(Ok(Message::Close(..)) | Err(..)) | None => return,
+            },
+        };
+        let Ok(text) = serde_json::to_string(&message) else {
+            continue;
+        };
+        if socket.send(Message::Text(text.into())).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Applies a `subscribe` or `unsubscribe` message, all of its topics or none.
+fn apply(subscription: &mut Subscription, text: &str) -> ServerMessage {
+    let request = match serde_json::from_str::<ClientMessage>(text) {
+        Ok(request) => request,
+        Err(err) => {
+            return ServerMessage::Error {
+                message: format!("Expected a subscribe or unsubscribe message : {}", err),
+            };
+        }
+    };
+    let (topics, subscribe) = match request {
+        ClientMessage::Subscribe(topics) => (topics, true),
+        ClientMessage::Unsubscribe(topics) => (topics, false),
+    };
+    let topics = match parse_topics(topics.iter().map(String::as_str)) {
+        Ok(topics) => topics,
+        Err(message) => return ServerMessage::Error { message },
+    };
+    let mut followed = subscription.topics.clone();
+    if subscribe {
+        followed.extend(
+            topics
+                .into_iter()
+                .filter(|t| !subscription.topics.contains(t)),
+        );
+    } else {
+        followed.retain(|t| !topics.contains(t));
+    }
+    if followed.len() > MAX_TOPICS {
+        return ServerMessage::Error {
+            message: format!("At most {} topics can be followed", MAX_TOPICS),
+        };
+    }
+    subscription.topics = followed;
+    ServerMessage::Topics {
+        topics: subscription.topics.iter().map(Topic::to_string).collect(),
+    }
+}
+
+/// Parsed `topics`, without duplicates.
+fn parse_topics<'a>(topics: impl Iterator<Item = &'a str>) -> Result<Vec<Topic>, String> {
+    let mut parsed = Vec::new();
+    for topic in topics {
+        let topic = topic.parse::<Topic>()?;
+        if !parsed.contains(&topic) {
+            parsed.push(topic);
+        }
+    }
+    if parsed.len() > MAX_TOPICS {
+        return Err(format!("At most {} topics can be followed", MAX_TOPICS));
+    }
+    Ok(parsed)
+}
+
+/// Answered 422 on the `topics` field.
+fn invalid_topics(message: String) -> AppError {
+    AppError::Invalid(vec![FieldError {
+        field: "topics".to_string(),
+        code: "topic".to_string(),
+        message,
+    }])
+}
+
+/// # ChangeStream
+/// `Sse` documented as a `text/event-stream` of [`Change`]s.
+pub struct ChangeStream<S>(Sse<S>);
+
+impl<S> IntoResponse for ChangeStream<S>
+where
+    Sse<S>: IntoResponse,
+{
+    fn into_response(self) -> axum::response::Response {
+        self.0.into_response()
+    }
+}
+
+impl<S> OperationOutput for ChangeStream<S> {
+    type Inner = Change;
+
+    fn operation_response(ctx: &mut GenContext, _operation: &mut Operation) -> Option<Response> {
+        Some(Response {
+            description: "`change` events holding a `Change`, and `lagged` events".to_string(),
+            content: IndexMap::from_iter([(
+                "text/event-stream".into(),
+                MediaType {
+                    schema: Some(SchemaObject {
+                        json_schema: ctx.schema.subschema_for::<Change>(),
+                        example: None,
+                        external_docs: None,
+                    }),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        })
+    }
+
+    fn inferred_responses(
+        ctx: &mut GenContext,
+        operation: &mut Operation,
+    ) -> Vec<(Option<StatusCode>, Response)> {
+        Self::operation_response(ctx, operation)
+            .map(|response| vec![(Some(StatusCode::Code(200)), response)])
+            .unwrap_or_default()
+    }
+}
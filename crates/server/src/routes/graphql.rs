@@ -0,0 +1,84 @@
+//! # graphql
+//! `POST /graphql`, running queries against the schema of `graphql.rs`.
+//! Try it on the GraphiQL page, `/docs/graphiql`.
+
+use aide::axum::{
+    ApiRouter,
+    routing::{get, post},
+};
+use axum::{extract::State, http::HeaderMap};
+use schemars::JsonSchema;
+
+use crate::{
+    auth,
+    config::Config,
+    graphql::{self, ApiSchema, Viewer},
+    prelude::*,
+    repository::RepoFactory,
+    state::GraphqlState,
+};
+
+/// # Graphql
+/// Mounted with `RouteRegistry::mount` in `routes/mod.rs`, outside of API versions
+/// as the schema evolves by deprecating fields.
+pub struct Graphql;
+impl RouteModule for Graphql {
+    type State = GraphqlState;
+    const NAME: &'static str = "graphql";
+    const DESCRIPTION: &'static str = "GraphQL over users and posts, try it at `/docs/graphiql`";
+    const PREFIX: Option<&'static str> = Some("/graphql");
+
+    fn router() -> ApiRouter<GraphqlState> {
+        ApiRouter::new()
+            .api_route("/", post(execute))
+            .api_route("/schema.graphql", get(sdl))
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GraphqlRequest {
+    query: String,
+    /// Operation to run when `query` holds several
+    operation_name: Option<String>,
+    /// Values of the `$variables` of `query`
+    variables: Option<serde_json::Value>,
+}
+
+/// `data` and `errors` as the GraphQL spec lays them out, not an `ApiResponse`.
+/// Errors hold the status REST would answer under `extensions.status`.
+#[derive(Serialize, JsonSchema)]
+#[serde(transparent)]
+pub struct GraphqlResponse(#[schemars(with = "serde_json::Value")] async_graphql::Response);
+
+/// # Runs a GraphQL query
+/// Always answered 200, failures are listed in `errors`.
+/// Send `X-Auth-Key` for the arguments admin routes would guard, e.g. `includeUnpublished`.
+pub async fn execute(
+    State(schema): State<ApiSchema>,
+    State(repos): State<Arc<RepoFactory>>,
+    State(config): State<Arc<Config>>,
+    headers: HeaderMap,
+    Json(body): Json<GraphqlRequest>,
+) -> Json<GraphqlResponse> {
+    let mut request = async_graphql::Request::new(body.query);
+    if let Some(name) = body.operation_name {
+        request = request.operation_name(name);
+    }
+    if let Some(variables) = body.variables {
+        request = request.variables(async_graphql::Variables::from_json(variables));
+    }
+    let viewer = Viewer {
+        admin: auth::is_admin(&config, &headers),
+    };
+    let response = schema
+        .execute(graphql::prepare(request, repos, viewer))
+        .await;
+    Json(GraphqlResponse(response))
+}
+
+/// # The schema in GraphQL SDL
+/// For client code generators.
+pub async fn sdl(State(schema): State<ApiSchema>) -> String {
+    schema.sdl()
+}
@@ -1,26 +1,27 @@
-use crate::prelude::*;
+use crate::{prelude::*, shutdown::Shutdown};
 use aide::axum::{ApiRouter, routing::get};
 use axum::{
-    Json,
+    extract::State,
+    http::StatusCode,
     response::{Html, Redirect},
 };
 use log::info;
 
-/// # get_router
-/// Adds route easily in `main.rs` file.
-pub fn get_router() -> (Option<Tag>, ApiRouter) {
-    (
-        Some(Tag {
-            name: "Index".to_string(),
-            description: Some("Default features".to_string()),
-            ..Default::default()
-        }),
+/// # Index
+/// Mounted with `RouteRegistry::mount` in `routes/mod.rs`.
+pub struct Index;
+impl RouteModule for Index {
+    type State = Shutdown;
+    const NAME: &'static str = "Index";
+    const DESCRIPTION: &'static str = "Default features";
+
+    fn router() -> ApiRouter<Shutdown> {
         ApiRouter::new()
             .api_route("/", get(index))
             .api_route("/test", get(test))
             .api_route("/is_alive", get(is_alive))
-            .with_tag("Index"),
-    )
+            .api_route("/is_ready", get(is_ready))
+    }
 }
 
 /// index
@@ -43,3 +44,28 @@ pub async fn is_alive() -> Json<ApiResponse<String>> {
         data: "Server is alive!".to_string(),
     })
 }
+
+/// is_ready
+/// Readiness probe. Answers 503 once a shutdown has begun,
+/// so load balancers stop routing here while requests drain.
+pub async fn is_ready(State(shutdown): State<Shutdown>) -> (StatusCode, Json<ApiResponse<String>>) {
+    if shutdown.is_ready() {
+        (
+            StatusCode::OK,
+            Json(ApiResponse {
+                code: 200,
+                resp: "ok".to_string(),
+                data: "Server is ready!".to_string(),
+            }),
+        )
+    } else {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse {
+                code: 503,
+                resp: "Service Unavailable".to_string(),
+                data: "Server is shutting down".to_string(),
+            }),
+        )
+    }
+}
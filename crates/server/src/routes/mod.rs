@@ -1,97 +1,189 @@
+#[cfg(feature = "db")]
+pub mod admin;
+#[cfg(feature = "calc")]
 pub mod calc;
+#[cfg(feature = "db")]
 pub mod database;
+#[cfg(feature = "events")]
+pub mod events;
+#[cfg(feature = "graphql")]
+pub mod graphql;
 pub mod index;
+#[cfg(feature = "db")]
+pub mod posts;
+#[cfg(feature = "users")]
 pub mod users;
-
+#[cfg(feature = "webhooks")]
+pub mod webhooks;
 
 pub mod apis {
     use aide::{axum::ApiRouter, openapi::OpenApi};
-    use std::sync::Arc;
+    use api_routes::ApiVersion;
 
-    pub fn route_settings(state: Arc<RepoFactory>) -> (ApiRouter, OpenApi) {
-        [
-            // Add routes here
-            index::get_router(),
-            calc::get_router(),
-            database::get_router(state),
-            users::get_router(),
-        ]
-        .into_iter()
-        .fold(
-            (ApiRouter::new(), OpenApi::default()),
-            |(app, mut api), route| {
-                match route.0 {
-                    Some(v) => api.tags.push(v),
-                    None => (),
-                };
-                (app.merge(route.1), api)
-            },
-        )
+    /// Deprecated since 2026-10-19, answered until its sunset.
+    /// Unversioned paths redirect here, as they behaved like it.
+    pub const V1: ApiVersion = ApiVersion::new("/v1")
+        .deprecated("@1792368000", "Mon, 19 Apr 2027 00:00:00 GMT")
+        .legacy();
+    /// Current version, without the `database` testing module.
+    pub const V2: ApiVersion = ApiVersion::new("/v2");
+    /// Every version, each with its own OpenAPI document under `/docs`.
+    pub const VERSIONS: [ApiVersion; 2] = [V1, V2];
+
+    /// Mounts every route module compiled in and not disabled by `state.config`.
+    /// The OpenAPI tag list only holds mounted modules.
+    pub fn route_settings(state: AppState) -> (ApiRouter, OpenApi) {
+        let routes = state.config.routes.clone();
+        // Unversioned routes
+        let registry = RouteRegistry::new(state)
+            .filter(move |name| routes.is_enabled(name))
+            .mount::<index::Index>();
+        #[cfg(feature = "graphql")]
+        let registry = registry.mount::<graphql::Graphql>();
+        #[cfg(feature = "events")]
+        let registry = registry.mount::<events::Events>();
+        let registry = modules(registry.version(V1));
+        #[cfg(feature = "db")]
+        let registry = registry.mount::<database::Database>();
+        let registry = modules(registry.version(V2));
+        #[cfg(feature = "webhooks")]
+        let registry = registry.mount::<webhooks::Webhooks>();
+        registry.finish()
     }
 
-    use aide::swagger::Swagger;
-    use aide::{
-        axum::{
-            IntoApiResponse,
-            routing::{get, get_with},
-        },
-        redoc::Redoc,
-        scalar::Scalar,
-    };
-    use axum::{Extension, Json, response::IntoResponse};
+    /// Modules of every version. Add routes here
+    fn modules(registry: RouteRegistry<AppState>) -> RouteRegistry<AppState> {
+        #[cfg(feature = "calc")]
+        let registry = registry.mount::<calc::Calc>();
+        #[cfg(feature = "db")]
+        let registry = registry.mount::<posts::Posts>().mount::<admin::Admin>();
+        #[cfg(feature = "users")]
+        let registry = registry.mount::<users::Users>();
+        registry
+    }
 
     use super::*;
-    use crate::repository::RepoFactory;
+    use crate::{prelude::RouteRegistry, state::AppState};
 
-    pub fn docs_routes(state: Arc<RepoFactory>) -> ApiRouter {
-        // We infer the return types for these routes
-        // as an example.
-        //
-        // As a result, the `serve_redoc` route will
-        // have the `text/html` content-type correctly set
-        // with a 200 status.
-        aide::generate::infer_responses(true);
-        const DOC_TITLE: &str = "api.movingju.com";
+    #[cfg(feature = "docs")]
+    pub use docs::docs_routes;
 
-        let router: ApiRouter = ApiRouter::new()
-            .route(
-                "/",
-                get_with(
-                    Scalar::new("/docs/openapi.json")
-                        .with_title(DOC_TITLE)
-                        .axum_handler(),
-                    |op| op.description("This documentation page."),
-                ),
-            )
-            .route(
-                "/redoc",
-                get_with(
-                    Redoc::new("/docs/openapi.json")
-                        .with_title(DOC_TITLE)
-                        .axum_handler(),
-                    |op| op.description("This documentation page."),
-                ),
-            )
-            .route(
-                "/swagger",
+    #[cfg(feature = "docs")]
+    mod docs {
+        use aide::swagger::Swagger;
+        use aide::{
+            axum::{
+                IntoApiResponse,
+                routing::{get, get_with},
+            },
+            redoc::Redoc,
+            scalar::Scalar,
+        };
+        #[cfg(feature = "graphql")]
+        use async_graphql::http::GraphiQLSource;
+        #[cfg(feature = "graphql")]
+        use axum::response::Html;
+        use axum::{Json, extract::State, http::StatusCode, response::IntoResponse};
+
+        use super::*;
+
+        use crate::{error::AppError, state::ApiDoc};
+
+        pub fn docs_routes(state: AppState) -> ApiRouter {
+            // We infer the return types for these routes
+            // as an example.
+            //
+            // As a result, the `serve_redoc` route will
+            // have the `text/html` content-type correctly set
+            // with a 200 status.
+            aide::generate::infer_responses(true);
+            const DOC_TITLE: &str = "api.movingju.com";
+
+            let mut router = ApiRouter::<AppState>::new();
+            // One document and page per version, e.g. `/docs/v1`
+            for version in VERSIONS {
+                let name = version.name();
+                router = router
+                    .route(
+                        &format!("/{}", name),
+                        get_with(
+                            Scalar::new(format!("/docs/{}/openapi.json", name))
+                                .with_title(&format!("{} {}", DOC_TITLE, name))
+                                .axum_handler(),
+                            |op| op.description("This documentation page."),
+                        ),
+                    )
+                    .route(
+                        &format!("/{}/openapi.json", name),
+                        get(move |State(api): State<ApiDoc>| async move {
+                            match api.version(name) {
+                                Some(doc) => Json(doc).into_response(),
+                                // Set once every route is registered
+                                None => AppError::Rejected {
+                                    status: StatusCode::SERVICE_UNAVAILABLE,
+                                    reason: "Service unavailable : the document is not ready yet",
+                                    fields: Vec::new(),
+                                }
+                                .into_response(),
+                            }
+                        }),
+                    );
+            }
+            let router = router
+                .route(
+                    "/",
+                    get_with(
+                        Scalar::new("/docs/openapi.json")
+                            .with_title(DOC_TITLE)
+                            .axum_handler(),
+                        |op| op.description("This documentation page."),
+                    ),
+                )
+                .route(
+                    "/redoc",
+                    get_with(
+                        Redoc::new("/docs/openapi.json")
+                            .with_title(DOC_TITLE)
+                            .axum_handler(),
+                        |op| op.description("This documentation page."),
+                    ),
+                )
+                .route(
+                    "/swagger",
+                    get_with(
+                        Swagger::new("/docs/openapi.json")
+                            .with_title(DOC_TITLE)
+                            .axum_handler(),
+                        |op| op.description("This documentation page."),
+                    ),
+                )
+                .route("/openapi.json", get(serve_docs));
+            #[cfg(feature = "graphql")]
+            let router = router.route(
+                "/graphiql",
                 get_with(
-                    Swagger::new("/docs/openapi.json")
-                        .with_title(DOC_TITLE)
-                        .axum_handler(),
-                    |op| op.description("This documentation page."),
+                    || async {
+                        Html(
+                            GraphiQLSource::build()
+                                .endpoint("/graphql")
+                                .title(&format!("{} GraphQL", DOC_TITLE))
+                                .finish(),
+                        )
+                    },
+                    |op| op.description("GraphiQL, to try `/graphql`."),
                 ),
-            )
-            .route("/openapi.json", get(serve_docs))
-            .with_state(state);
+            );
+            let router: ApiRouter = router.with_state(state);
 
-        // Afterwards we disable response inference because
-        // it might be incorrect for other routes.
-        aide::generate::infer_responses(false);
+            // Afterwards we disable response inference because
+            // it might be incorrect for other routes.
+            aide::generate::infer_responses(false);
 
-        router
-    }
+            router
+        }
 
-    async fn serve_docs(Extension(api): Extension<Arc<OpenApi>>) -> impl IntoApiResponse {
-        Json(api).into_response()
+        async fn serve_docs(State(api): State<ApiDoc>) -> impl IntoApiResponse {
+            Json(api.get()).into_response()
+        }
     }
 }
@@ -0,0 +1,612 @@
+//! # posts
+//! Public APIs over posts.
+
+use aide::axum::{
+    ApiRouter,
+    routing::{delete, get, post},
+};
+use axum::{extract::State, http::HeaderMap};
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use validator::Validate;
+
+use crate::{
+    auth,
+    config::Config,
+    error::AppError,
+    etag::{IfMatch, IfNoneMatch, Versioned},
+    markdown::Render,
+    prelude::*,
+    repository::{
+        ReadOptions, Repo, RepoFactory, UnitOfWork,
+        posts::{self, PostStatus, SearchHit, to_tsquery},
+        revisions::{Revision, RevisionDiff},
+        tags::{self, TagCount},
+    },
+    state::ReposState,
+    validation::{MAX_CONTENT_LEN, MAX_TITLE_LEN, Valid},
+};
+
+pub struct Posts;
+impl RouteModule for Posts {
+    type State = ReposState;
+    const NAME: &'static str = "posts";
+    const DESCRIPTION: &'static str = "APIs for posts and their tags";
+    const PREFIX: Option<&'static str> = Some("/posts");
+
+    fn router() -> ApiRouter<ReposState> {
+        ApiRouter::new()
+            .api_route("/", post(create_post))
+            .api_route("/search", get(search))
+            .api_route("/tags", get(get_tags))
+            .api_route("/tags/{tag}", get(get_tagged))
+            .api_route("/{id}", get(get_post).patch(edit_post).delete(delete_post))
+            .api_route("/{id}/revisions", get(get_revisions))
+            .api_route("/{id}/revisions/diff", get(diff_revisions))
+            .api_route("/{id}/revisions/{rev}/restore", post(restore_revision))
+            .api_route("/{id}/status", post(set_status))
+            .api_route("/{id}/tags", post(tag_post))
+            .api_route("/{id}/tags/{tag}", delete(untag_post))
+    }
+}
+
+/// # Writes a post, as a draft unless told otherwise
+pub async fn create_post(
+    State(state): State<Arc<RepoFactory>>,
+    Valid(Json(body)): Valid<Json<CreateBody>>,
+) -> Result<Json<ApiResponse<posts::Posts>>, AppError> {
+    let status = body.status.unwrap_or(PostStatus::Draft);
+    let publish_at = publish_at(status, body.publish_at, None)?;
+    let post = state
+        .transaction(|uow| async move {
+            if uow
+                .user
+                .find(body.user_id, ReadOptions::default())
+                .await?
+                .is_none()
+            {
+                return Err(AppError::NotFound("User"));
+            }
+            let row = posts::Posts {
+                title: body.title,
+                content: body.content,
+                user_id: body.user_id,
+                status,
+                publish_at,
+                ..Default::default()
+            };
+            Ok(uow.posts.insert(&row).await?)
+        })
+        .await?;
+    Ok(Json(ApiResponse {
+        code: 0,
+        resp: "ok".to_string(),
+        data: post,
+    }))
+}
+
+/// # Moves a post through its workflow
+/// `scheduled` needs a `publish_at` in the future, the post is published then.
+/// Requires `If-Match` with the current `ETag`.
+/// Posts that are not published are only found with `X-Auth-Key`.
+pub async fn set_status(
+    State(state): State<Arc<RepoFactory>>,
+    State(config): State<Arc<Config>>,
+    headers: HeaderMap,
+    Path(path): Path<IdPath>,
+    if_match: IfMatch,
+    Json(body): Json<StatusBody>,
+) -> Result<Versioned<Json<ApiResponse<posts::Posts>>>, AppError> {
+    let opts = visible_to(&config, &headers);
+    let post = state
+        .transaction(|uow| async move {
+            let current = find_visible(&uow, path.id, ReadOptions { lock: true, ..opts }).await?;
+            if_match.check(current.version)?;
+            let at = publish_at(body.status, body.publish_at, current.publish_at)?;
+            uow.posts.set_status(path.id, body.status, at).await?;
+            find_post(&uow, path.id).await
+        })
+        .await?;
+    Ok(Versioned::Body(
+        post.version,
+        Json(ApiResponse {
+            code: 0,
+            resp: "ok".to_string(),
+            data: post,
+        }),
+    ))
+}
+
+/// # Searches posts by title and content
+/// `"quoted words"` match a phrase and `word*` matches a prefix,
+/// every other word has to appear.
+pub async fn search(
+    State(state): State<Arc<RepoFactory>>,
+    Valid(Query(query)): Valid<Query<SearchQuery>>,
+) -> Result<Json<ApiResponse<Vec<SearchHit>>>, AppError> {
+    let Some(tsquery) = to_tsquery(&query.q) else {
+        return Err(AppError::BadRequest("q has no word to search".to_string()));
+    };
+    let limit = query.limit.unwrap_or(20);
+    Ok(Json(ApiResponse {
+        code: 0,
+        resp: "ok".to_string(),
+        data: state.posts.search(&tsquery, limit).await?,
+    }))
+}
+#[derive(Deserialize, JsonSchema, Validate)]
+pub struct SearchQuery {
+    #[validate(length(min = 1, max = 200))]
+    q: String,
+    /// 20 when left out
+    #[validate(range(min = 1, max = 100))]
+    limit: Option<i64>,
+}
+
+/// # Lists tags with the number of posts carrying them
+pub async fn get_tags(
+    State(state): State<Arc<RepoFactory>>,
+) -> Result<Json<ApiResponse<Vec<TagCount>>>, AppError> {
+    Ok(Json(ApiResponse {
+        code: 0,
+        resp: "ok".to_string(),
+        data: state.tags.counts().await?,
+    }))
+}
+
+/// # Lists posts carrying a tag, newest first
+/// `render=html` answers the content as sanitized HTML.
+pub async fn get_tagged(
+    State(state): State<Arc<RepoFactory>>,
+    Path(path): Path<TagPath>,
+    Query(query): Query<RenderQuery>,
+) -> Result<Json<ApiResponse<Vec<posts::Posts>>>, AppError> {
+    let tag = normalize(&path.tag)?;
+    let posts = state
+        .posts
+        .select_by_tag(&tag, ReadOptions::default())
+        .await?;
+    Ok(Json(ApiResponse {
+        code: 0,
+        resp: "ok".to_string(),
+        data: posts.into_iter().map(|p| p.render(query.render)).collect(),
+    }))
+}
+
+/// # Finds a post
+/// `render=html` answers the content as sanitized HTML.
+/// Answers 304 when `If-None-Match` holds the current `ETag`.
+pub async fn get_post(
+    State(state): State<Arc<RepoFactory>>,
+    Path(path): Path<IdPath>,
+    Query(query): Query<RenderQuery>,
+    if_none_match: IfNoneMatch,
+) -> Result<Versioned<Json<ApiResponse<posts::Posts>>>, AppError> {
+    let post = state
+        .posts
+        .find(path.id, ReadOptions::default())
+        .await?
+        .ok_or(AppError::NotFound("Post"))?;
+    Ok(Versioned::new(
+        post.version,
+        &if_none_match,
+        Json(ApiResponse {
+            code: 0,
+            resp: "ok".to_string(),
+            data: post.render(query.render),
+        }),
+    ))
+}
+
+/// # Deletes a post
+/// Requires `If-Match` with the current `ETag`.
+/// Posts that are not published are only found with `X-Auth-Key`.
+pub async fn delete_post(
+    State(state): State<Arc<RepoFactory>>,
+    State(config): State<Arc<Config>>,
+    headers: HeaderMap,
+    Path(path): Path<IdPath>,
+    if_match: IfMatch,
+) -> Result<Json<ApiResponse<Empty>>, AppError> {
+    let opts = visible_to(&config, &headers);
+    state
+        .transaction(|uow| async move {
+            let current = find_visible(&uow, path.id, ReadOptions { lock: true, ..opts }).await?;
+            if_match.check(current.version)?;
+            let criteria = posts::Posts {
+                id: path.id,
+                ..Default::default()
+            };
+            uow.posts.delete(&criteria).await?;
+            Ok(())
+        })
+        .await?;
+    Ok(Json(ApiResponse {
+        code: 0,
+        resp: "ok".to_string(),
+        data: Empty,
+    }))
+}
+
+/// # Adds tags to a post
+/// Tags are created on first use. Answers the post with its tags.
+/// Requires `If-Match` with the current `ETag`, tags are part of the version.
+/// Posts that are not published are only found with `X-Auth-Key`.
+pub async fn tag_post(
+    State(state): State<Arc<RepoFactory>>,
+    State(config): State<Arc<Config>>,
+    headers: HeaderMap,
+    Path(path): Path<IdPath>,
+    if_match: IfMatch,
+    Valid(Json(body)): Valid<Json<TagBody>>,
+) -> Result<Versioned<Json<ApiResponse<posts::Posts>>>, AppError> {
+    let names = body
+        .tags
+        .iter()
+        .map(|name| normalize(name))
+        .collect::<Result<Vec<_>, _>>()?;
+    let opts = visible_to(&config, &headers);
+    let post = state
+        .transaction(|uow| async move {
+            let current = find_visible(&uow, path.id, ReadOptions { lock: true, ..opts }).await?;
+            if_match.check(current.version)?;
+            uow.tags.tag(path.id, &names).await?;
+            find_visible(&uow, path.id, opts).await
+        })
+        .await?;
+    Ok(Versioned::Body(
+        post.version,
+        Json(ApiResponse {
+            code: 0,
+            resp: "ok".to_string(),
+            data: post,
+        }),
+    ))
+}
+
+/// # Removes a tag from a post
+/// Answers the post with its remaining tags.
+/// Requires `If-Match` with the current `ETag`, tags are part of the version.
+/// Posts that are not published are only found with `X-Auth-Key`.
+pub async fn untag_post(
+    State(state): State<Arc<RepoFactory>>,
+    State(config): State<Arc<Config>>,
+    headers: HeaderMap,
+    Path(path): Path<IdTagPath>,
+    if_match: IfMatch,
+) -> Result<Versioned<Json<ApiResponse<posts::Posts>>>, AppError> {
+    let tag = normalize(&path.tag)?;
+    let opts = visible_to(&config, &headers);
+    let post = state
+        .transaction(|uow| async move {
+            let current = find_visible(&uow, path.id, ReadOptions { lock: true, ..opts }).await?;
+            if_match.check(current.version)?;
+            if uow.tags.untag(path.id, &[tag]).await? == 0 {
+                return Err(AppError::NotFound("Tag"));
+            }
+            find_visible(&uow, path.id, opts).await
+        })
+        .await?;
+    Ok(Versioned::Body(
+        post.version,
+        Json(ApiResponse {
+            code: 0,
+            resp: "ok".to_string(),
+            data: post,
+        }),
+    ))
+}
+
+/// # Edits the title and/or content of a post
+/// Requires `If-Match` with the current `ETag`.
+/// The previous version stays available in the revisions of the post.
+/// Posts that are not published are only found with `X-Auth-Key`.
+pub async fn edit_post(
+    State(state): State<Arc<RepoFactory>>,
+    State(config): State<Arc<Config>>,
+    headers: HeaderMap,
+    Path(path): Path<IdPath>,
+    if_match: IfMatch,
+    Valid(Json(body)): Valid<Json<EditBody>>,
+) -> Result<Versioned<Json<ApiResponse<posts::Posts>>>, AppError> {
+    let opts = visible_to(&config, &headers);
+    let post = state
+        .transaction(|uow| async move {
+            let current = find_visible(&uow, path.id, ReadOptions { lock: true, ..opts }).await?;
+            if_match.check(current.version)?;
+            uow.posts.edit(path.id, body.title, body.content).await?;
+            find_post(&uow, path.id).await
+        })
+        .await?;
+    Ok(Versioned::Body(
+        post.version,
+        Json(ApiResponse {
+            code: 0,
+            resp: "ok".to_string(),
+            data: post,
+        }),
+    ))
+}
+
+/// # Lists the revisions of a post, latest first
+/// Posts that are not published are only found with `X-Auth-Key`.
+pub async fn get_revisions(
+    State(state): State<Arc<RepoFactory>>,
+    State(config): State<Arc<Config>>,
+    headers: HeaderMap,
+    Path(path): Path<IdPath>,
+) -> Result<Json<ApiResponse<Vec<Revision>>>, AppError> {
+    let opts = visible_to(&config, &headers);
+    let revisions = state
+        .transaction(|uow| async move {
+            find_visible(&uow, path.id, opts).await?;
+            Ok(uow.revisions.select(path.id).await?)
+        })
+        .await?;
+    Ok(Json(ApiResponse {
+        code: 0,
+        resp: "ok".to_string(),
+        data: revisions,
+    }))
+}
+
+/// # Compares two revisions of a post line by line
+/// Posts that are not published are only found with `X-Auth-Key`.
+pub async fn diff_revisions(
+    State(state): State<Arc<RepoFactory>>,
+    State(config): State<Arc<Config>>,
+    headers: HeaderMap,
+    Path(path): Path<IdPath>,
+    Valid(Query(query)): Valid<Query<DiffQuery>>,
+) -> Result<Json<ApiResponse<RevisionDiff>>, AppError> {
+    let opts = visible_to(&config, &headers);
+    let diff = state
+        .transaction(|uow| async move {
+            find_visible(&uow, path.id, opts).await?;
+            let from = find_revision(&uow, path.id, query.from).await?;
+            let to = find_revision(&uow, path.id, query.to).await?;
+            Ok(from.diff(&to))
+        })
+        .await?;
+    Ok(Json(ApiResponse {
+        code: 0,
+        resp: "ok".to_string(),
+        data: diff,
+    }))
+}
+
+/// # Brings a post back to one of its revisions
+/// Recorded as a new revision, so the history is kept.
+/// Requires `If-Match` with the current `ETag`.
+/// Posts that are not published are only found with `X-Auth-Key`.
+pub async fn restore_revision(
+    State(state): State<Arc<RepoFactory>>,
+    State(config): State<Arc<Config>>,
+    headers: HeaderMap,
+    Path(path): Path<IdRevPath>,
+    if_match: IfMatch,
+) -> Result<Versioned<Json<ApiResponse<posts::Posts>>>, AppError> {
+    let opts = visible_to(&config, &headers);
+    let post = state
+        .transaction(|uow| async move {
+            let current = find_visible(&uow, path.id, ReadOptions { lock: true, ..opts }).await?;
+            if_match.check(current.version)?;
+            let revision = find_revision(&uow, path.id, path.rev).await?;
+            uow.posts
+                .edit(path.id, Some(revision.title), Some(revision.content))
+                .await?;
+            find_post(&uow, path.id).await
+        })
+        .await?;
+    Ok(Versioned::Body(
+        post.version,
+        Json(ApiResponse {
+            code: 0,
+            resp: "ok".to_string(),
+            data: post,
+        }),
+    ))
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct IdPath {
+    id: i64,
+}
+#[derive(Deserialize, JsonSchema)]
+pub struct RenderQuery {
+    /// `raw` when left out
+    #[serde(default)]
+    render: Render,
+}
+#[derive(Deserialize, JsonSchema)]
+pub struct TagPath {
+    tag: String,
+}
+#[derive(Deserialize, JsonSchema)]
+pub struct IdTagPath {
+    id: i64,
+    tag: String,
+}
+#[derive(Deserialize, JsonSchema, Validate)]
+pub struct CreateBody {
+    user_id: i64,
+    #[validate(length(min = 1, max = MAX_TITLE_LEN))]
+    title: String,
+    /// Markdown
+    #[validate(length(max = MAX_CONTENT_LEN))]
+    content: String,
+    /// `draft` when left out
+    status: Option<PostStatus>,
+    /// Required for `scheduled`
+    publish_at: Option<DateTime<Utc>>,
+}
+#[derive(Deserialize, JsonSchema)]
+pub struct StatusBody {
+    status: PostStatus,
+    /// Required for `scheduled`, ignored otherwise
+    publish_at: Option<DateTime<Utc>>,
+}
+#[derive(Deserialize, JsonSchema)]
+pub struct IdRevPath {
+    id: i64,
+    rev: i32,
+}
+#[derive(Deserialize, JsonSchema, Validate)]
+pub struct DiffQuery {
+    #[validate(range(min = 1))]
+    from: i32,
+    #[validate(range(min = 1))]
+    to: i32,
+}
+#[derive(Deserialize, JsonSchema, Validate)]
+pub struct EditBody {
+    /// Left unchanged when left out
+    #[validate(length(min = 1, max = MAX_TITLE_LEN))]
+    title: Option<String>,
+    /// Markdown, left unchanged when left out
+    #[validate(length(max = MAX_CONTENT_LEN))]
+    content: Option<String>,
+}
+#[derive(Deserialize, JsonSchema, Validate)]
+pub struct TagBody {
+    /// Case-insensitive, without whitespace, at most 32 characters each
+    #[validate(length(min = 1, max = 20))]
+    tags: Vec<String>,
+}
+
+/// Live post of any status, to read a change back once [`find_visible`] let it through.
+async fn find_post(uow: &UnitOfWork, id: i64) -> Result<posts::Posts, AppError> {
+    let opts = ReadOptions {
+        include_unpublished: true,
+        ..Default::default()
+    };
+    uow.posts
+        .find(id, opts)
+        .await?
+        .ok_or(AppError::NotFound("Post"))
+}
+
+/// Live post as `opts` allow, see [`visible_to`].
+async fn find_visible(
+    uow: &UnitOfWork,
+    id: i64,
+    opts: ReadOptions,
+) -> Result<posts::Posts, AppError> {
+    uow.posts
+        .find(id, opts)
+        .await?
+        .ok_or(AppError::NotFound("Post"))
+}
+
+/// Published posts only, unless the request carries `X-Auth-Key`.
+pub fn visible_to(config: &Config, headers: &HeaderMap) -> ReadOptions {
+    ReadOptions {
+        include_unpublished: auth::is_admin(config, headers),
+        ..Default::default()
+    }
+}
+
+async fn find_revision(uow: &UnitOfWork, id: i64, rev: i32) -> Result<Revision, AppError> {
+    uow.revisions
+        .find(id, rev)
+        .await?
+        .ok_or(AppError::NotFound("Revision"))
+}
+
+/// `publish_at` to store with `status`, given the requested and currently stored ones.
+pub fn publish_at(
+    status: PostStatus,
+    requested: Option<DateTime<Utc>>,
+    current: Option<DateTime<Utc>>,
+) -> Result<Option<DateTime<Utc>>, AppError> {
+    let now = Utc::now();
+    match status {
+        PostStatus::Draft => Ok(None),
+        PostStatus::Scheduled => match requested {
+            Some(at) if at > now => Ok(Some(at)),
+            _ => Err(AppError::BadRequest(
+                "scheduled posts need a publish_at in the future".to_string(),
+            )),
+        },
+        // Published again after being archived keeps its first publication time
+        PostStatus::Published => Ok(current.filter(|at| *at <= now).or(Some(now))),
+        PostStatus::Archived => Ok(current),
+    }
+}
+
+fn normalize(name: &str) -> Result<String, AppError> {
+    tags::normalize(name).ok_or_else(|| AppError::BadRequest(format!("Invalid tag : {}", name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{extract::FromRequestParts, http::Request};
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::repository::users::Users;
+
+    async fn if_match_any() -> IfMatch {
+        let (mut parts, _) = Request::builder()
+            .header("If-Match", "*")
+            .body(())
+            .unwrap()
+            .into_parts();
+        IfMatch::from_request_parts(&mut parts, &()).await.unwrap()
+    }
+
+    /// `If-Match: *` passes every version check, the post has to stay hidden before that.
+    /// Needs `DATABASE_URL` with the migrations applied : `cargo test -- --ignored`
+    #[tokio::test]
+    #[ignore]
+    async fn anonymous_if_match_any_does_not_find_a_draft() {
+        dotenv::dotenv().ok();
+        let mut config = Config::from_env().unwrap();
+        config.admin_key = Some("secret".to_string());
+        let config = Arc::new(config);
+        let repos = Arc::new(RepoFactory::new(
+            PgPool::connect(&config.database_url).await.unwrap(),
+        ));
+        let user = Users {
+            name: format!("draft-{}", uuid::Uuid::new_v4()),
+            ..Default::default()
+        };
+        let user = repos.user.insert(&user).await.unwrap();
+        let draft = posts::Posts {
+            user_id: user.id,
+            title: "draft".to_string(),
+            status: PostStatus::Draft,
+            ..Default::default()
+        };
+        let draft = repos.posts.insert(&draft).await.unwrap();
+        let edit = |headers| {
+            let (repos, config) = (repos.clone(), config.clone());
+            async move {
+                let body = EditBody {
+                    title: None,
+                    content: None,
+                };
+                edit_post(
+                    State(repos),
+                    State(config),
+                    headers,
+                    Path(IdPath { id: draft.id }),
+                    if_match_any().await,
+                    Valid(Json(body)),
+                )
+                .await
+            }
+        };
+
+        let anonymous = edit(HeaderMap::new()).await;
+        let mut admin = HeaderMap::new();
+        admin.insert(auth::AUTH_HEADER, "secret".parse().unwrap());
+        let admin = edit(admin).await;
+
+        repos.posts.delete(&draft).await.unwrap();
+        repos.user.delete(&user).await.unwrap();
+        assert!(matches!(anonymous, Err(AppError::NotFound("Post"))));
+        assert!(admin.is_ok());
+    }
+}
@@ -1,25 +1,32 @@
 use aide::axum::{ApiRouter, routing::get};
-use axum::{Json, extract::Query};
+use axum::extract::State;
 use log::info;
 use schemars::JsonSchema;
+use validator::Validate;
 
-use crate::prelude::*;
+use crate::{
+    error::AppError,
+    etag::{IfMatch, IfNoneMatch, Versioned},
+    prelude::*,
+    repository::{ReadOptions, RepoFactory, UnitOfWork, users},
+    validation::{MAX_USER_NAME_LEN, USER_NAME, Valid},
+};
 
-/// # get_router
-/// Adds route easily in `main.rs` file.
-pub fn get_router() -> (Option<Tag>, ApiRouter) {
-    (
-        Some(Tag {
-            name: "test".to_string(),
-            description: Some("testing routes".to_string()),
-            ..Default::default()
-        }),
+/// # Users
+/// Mounted with `RouteRegistry::mount` in `routes/mod.rs`.
+pub struct Users;
+impl RouteModule for Users {
+    type State = Arc<RepoFactory>;
+    const NAME: &'static str = "test";
+    const DESCRIPTION: &'static str = "User APIs and testing routes";
+    const PREFIX: Option<&'static str> = Some("/users");
+
+    fn router() -> ApiRouter<Arc<RepoFactory>> {
         ApiRouter::new()
             .api_route("/get_users", get(get_users))
             .api_route("/set_users", get(set_users))
-            .with_prefix("/users")
-            .with_tag("test"),
-    )
+            .api_route("/{id}", get(get_user).patch(rename_user))
+    }
 }
 
 #[derive(Serialize, JsonSchema)]
@@ -29,7 +36,9 @@ pub struct UserResp {
     pub email: Option<String>,
 }
 
-pub async fn get_users(Query(query): Query<GetUserQuery>) -> Json<ApiResponse<UserResp>> {
+pub async fn get_users(
+    Valid(Query(query)): Valid<Query<GetUserQuery>>,
+) -> Json<ApiResponse<UserResp>> {
     info!("Request to get users table.");
     Json(ApiResponse {
         code: 200,
@@ -41,8 +50,9 @@ pub async fn get_users(Query(query): Query<GetUserQuery>) -> Json<ApiResponse<Us
         },
     })
 }
-#[derive(Serialize, Deserialize, JsonSchema)]
+#[derive(Serialize, Deserialize, JsonSchema, Validate)]
 pub struct GetUserQuery {
+    #[validate(length(min = 1))]
     name: String,
 }
 
@@ -54,3 +64,77 @@ pub async fn set_users() -> Json<ApiResponse<Empty>> {
         data: Empty,
     })
 }
+
+/// # Finds a user
+/// Answers 304 when `If-None-Match` holds the current `ETag`.
+pub async fn get_user(
+    State(state): State<Arc<RepoFactory>>,
+    Path(path): Path<IdPath>,
+    if_none_match: IfNoneMatch,
+) -> Result<Versioned<Json<ApiResponse<users::Users>>>, AppError> {
+    let user = state
+        .user
+        .find(path.id, ReadOptions::default())
+        .await?
+        .ok_or(AppError::NotFound("User"))?;
+    Ok(Versioned::new(
+        user.version,
+        &if_none_match,
+        Json(ApiResponse {
+            code: 0,
+            resp: "ok".to_string(),
+            data: user,
+        }),
+    ))
+}
+
+/// # Renames a user
+/// Requires `If-Match` with the current `ETag`. Answers 409 when the name is taken.
+pub async fn rename_user(
+    State(state): State<Arc<RepoFactory>>,
+    Path(path): Path<IdPath>,
+    if_match: IfMatch,
+    Valid(Json(body)): Valid<Json<RenameBody>>,
+) -> Result<Versioned<Json<ApiResponse<users::Users>>>, AppError> {
+    let user = state
+        .transaction(|uow| async move {
+            let current = lock_user(&uow, path.id).await?;
+            if_match.check(current.version)?;
+            uow.user.rename(path.id, body.name).await?;
+            uow.user
+                .find(path.id, ReadOptions::default())
+                .await?
+                .ok_or(AppError::NotFound("User"))
+        })
+        .await?;
+    Ok(Versioned::Body(
+        user.version,
+        Json(ApiResponse {
+            code: 0,
+            resp: "ok".to_string(),
+            data: user,
+        }),
+    ))
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct IdPath {
+    id: i64,
+}
+#[derive(Deserialize, JsonSchema, Validate)]
+pub struct RenameBody {
+    #[validate(length(min = 1, max = MAX_USER_NAME_LEN), regex(path = *USER_NAME))]
+    name: String,
+}
+
+/// Live user, locked until the transaction ends so its version cannot change under us.
+async fn lock_user(uow: &UnitOfWork, id: i64) -> Result<users::Users, AppError> {
+    let opts = ReadOptions {
+        lock: true,
+        ..Default::default()
+    };
+    uow.user
+        .find(id, opts)
+        .await?
+        .ok_or(AppError::NotFound("User"))
+}
@@ -0,0 +1,299 @@
+//! # webhooks
+//! Webhooks of integrators and the log of their deliveries, guarded by `X-Auth-Key`.
+//! Events are queued from the outbox by `tasks/outbox.rs` and sent by `tasks/webhooks.rs`,
+//! whose documentation explains how to check signatures.
+
+use aide::axum::{
+    ApiRouter,
+    routing::{get, post},
+};
+use axum::extract::State;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::{Validate, ValidationError};
+
+use crate::{
+    auth::AdminKey,
+    error::AppError,
+    prelude::*,
+    repository::{
+        RepoFactory,
+        webhooks::{Attempt, Delivery, DeliveryStatus, EVENTS, Webhook},
+    },
+    state::ReposState,
+    validation::Valid,
+};
+
+/// # Webhooks
+/// Mounted with `RouteRegistry::mount` in `routes/mod.rs`, from `v2` on.
+pub struct Webhooks;
+impl RouteModule for Webhooks {
+    type State = ReposState;
+    const NAME: &'static str = "webhooks";
+    const DESCRIPTION: &'static str =
+        "Signed notifications of user and post events, require `X-Auth-Key`";
+    const PREFIX: Option<&'static str> = Some("/webhooks");
+
+    fn router() -> ApiRouter<ReposState> {
+        ApiRouter::new()
+            .api_route("/", get(get_webhooks).post(create_webhook))
+            .api_route(
+                "/{id}",
+                get(get_webhook).patch(edit_webhook).delete(delete_webhook),
+            )
+            .api_route("/{id}/ping", post(ping_webhook))
+            .api_route("/{id}/deliveries", get(get_deliveries))
+            .api_route("/{id}/deliveries/{delivery_id}", get(get_delivery))
+            .api_route("/{id}/deliveries/{delivery_id}/retry", post(retry_delivery))
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct IdPath {
+    id: i64,
+}
+#[derive(Deserialize, JsonSchema)]
+pub struct DeliveryPath {
+    id: i64,
+    delivery_id: i64,
+}
+
+/// # Lists webhooks
+pub async fn get_webhooks(
+    _: AdminKey,
+    State(state): State<Arc<RepoFactory>>,
+) -> Result<Json<ApiResponse<Vec<Webhook>>>, AppError> {
+    Ok(Json(ApiResponse {
+        code: 0,
+        resp: "ok".to_string(),
+        data: state.webhooks.select().await?,
+    }))
+}
+
+/// # Registers a webhook
+/// Answers the secret signing its deliveries, which is never shown again.
+pub async fn create_webhook(
+    _: AdminKey,
+    State(state): State<Arc<RepoFactory>>,
+    Valid(Json(body)): Valid<Json<CreateBody>>,
+) -> Result<Json<ApiResponse<CreatedWebhook>>, AppError> {
+    let secret = format!(
+        "whsec_{}{}",
+        Uuid::new_v4().simple(),
+        Uuid::new_v4().simple()
+    );
+    let webhook = state
+        .webhooks
+        .insert(body.url, secret.clone(), dedup(body.events))
+        .await?;
+    Ok(Json(ApiResponse {
+        code: 0,
+        resp: "ok".to_string(),
+        data: CreatedWebhook { webhook, secret },
+    }))
+}
+#[derive(Deserialize, JsonSchema, Validate)]
+pub struct CreateBody {
+    /// `http` or `https` URL receiving a `POST` per event
+    #[validate(length(max = 2048), custom(function = "http_url"))]
+    url: String,
+    /// `user.created`, `user.updated`, `user.deleted`, `user.restored`, `post.created`,
+    /// `post.updated`, `post.published`, `post.deleted` or `post.restored`
+    #[validate(length(min = 1, max = 9), custom(function = "known_events"))]
+    events: Vec<String>,
+}
+#[derive(Serialize, JsonSchema)]
+pub struct CreatedWebhook {
+    #[serde(flatten)]
+    webhook: Webhook,
+    /// Key of the `Webhook-Signature` HMAC, keep it secret
+    secret: String,
+}
+
+/// # Finds a webhook
+pub async fn get_webhook(
+    _: AdminKey,
+    State(state): State<Arc<RepoFactory>>,
+    Path(path): Path<IdPath>,
+) -> Result<Json<ApiResponse<Webhook>>, AppError> {
+    Ok(Json(ApiResponse {
+        code: 0,
+        resp: "ok".to_string(),
+        data: find(&state, path.id).await?,
+    }))
+}
+
+/// # Edits a webhook
+/// Deactivating it holds its deliveries until it is active again.
+pub async fn edit_webhook(
+    _: AdminKey,
+    State(state): State<Arc<RepoFactory>>,
+    Path(path): Path<IdPath>,
+    Valid(Json(body)): Valid<Json<EditBody>>,
+) -> Result<Json<ApiResponse<Webhook>>, AppError> {
+    let webhook = state
+        .webhooks
+        .update(path.id, body.url, body.events.map(dedup), body.active)
+        .await
+        .map_err(|err| match err {
+            sqlx::Error::RowNotFound => AppError::NotFound("Webhook"),
+            err => err.into(),
+        })?;
+    Ok(Json(ApiResponse {
+        code: 0,
+        resp: "ok".to_string(),
+        data: webhook,
+    }))
+}
+#[derive(Deserialize, JsonSchema, Validate)]
+pub struct EditBody {
+    /// Left unchanged when left out
+    #[validate(length(max = 2048), custom(function = "http_url"))]
+    url: Option<String>,
+    /// Left unchanged when left out
+    #[validate(length(min = 1, max = 9), custom(function = "known_events"))]
+    events: Option<Vec<String>>,
+    /// Left unchanged when left out
+    active: Option<bool>,
+}
+
+/// # Deletes a webhook
+/// Its deliveries and their log go with it.
+pub async fn delete_webhook(
+    _: AdminKey,
+    State(state): State<Arc<RepoFactory>>,
+    Path(path): Path<IdPath>,
+) -> Result<Json<ApiResponse<Empty>>, AppError> {
+    if state.webhooks.delete(path.id).await? == 0 {
+        return Err(AppError::NotFound("Webhook"));
+    }
+    Ok(Json(ApiResponse {
+        code: 0,
+        resp: "ok".to_string(),
+        data: Empty,
+    }))
+}
+
+/// # Sends a test event
+/// Queues a `webhook.ping` delivery, whatever the webhook subscribed to.
+pub async fn ping_webhook(
+    _: AdminKey,
+    State(state): State<Arc<RepoFactory>>,
+    Path(path): Path<IdPath>,
+) -> Result<Json<ApiResponse<Delivery>>, AppError> {
+    find(&state, path.id).await?;
+    Ok(Json(ApiResponse {
+        code: 0,
+        resp: "ok".to_string(),
+        data: state.webhooks.ping(path.id).await?,
+    }))
+}
+
+/// # Lists deliveries of a webhook, newest first
+pub async fn get_deliveries(
+    _: AdminKey,
+    State(state): State<Arc<RepoFactory>>,
+    Path(path): Path<IdPath>,
+    Valid(Query(query)): Valid<Query<DeliveriesQuery>>,
+) -> Result<Json<ApiResponse<Vec<Delivery>>>, AppError> {
+    find(&state, path.id).await?;
+    let deliveries = state
+        .webhooks
+        .deliveries(path.id, query.status, query.limit.unwrap_or(50))
+        .await?;
+    Ok(Json(ApiResponse {
+        code: 0,
+        resp: "ok".to_string(),
+        data: deliveries,
+    }))
+}
+#[derive(Deserialize, JsonSchema, Validate)]
+pub struct DeliveriesQuery {
+    /// Every status when left out, `dead` lists the dead letters
+    status: Option<DeliveryStatus>,
+    /// 50 when left out
+    #[validate(range(min = 1, max = 500))]
+    limit: Option<i64>,
+}
+
+/// # Finds a delivery, with the log of its attempts
+pub async fn get_delivery(
+    _: AdminKey,
+    State(state): State<Arc<RepoFactory>>,
+    Path(path): Path<DeliveryPath>,
+) -> Result<Json<ApiResponse<DeliveryLog>>, AppError> {
+    let delivery = state
+        .webhooks
+        .find_delivery(path.id, path.delivery_id)
+        .await?
+        .ok_or(AppError::NotFound("Delivery"))?;
+    let attempts = state.webhooks.attempts(delivery.id).await?;
+    Ok(Json(ApiResponse {
+        code: 0,
+        resp: "ok".to_string(),
+        data: DeliveryLog {
+            delivery,
+            log: attempts,
+        },
+    }))
+}
+#[derive(Serialize, JsonSchema)]
+pub struct DeliveryLog {
+    #[serde(flatten)]
+    delivery: Delivery,
+    /// Every attempt, oldest first
+    log: Vec<Attempt>,
+}
+
+/// # Sends a delivery again
+/// Queues a `dead` or `pending` delivery for now, with a fresh set of attempts.
+/// Answers 404 for delivered ones.
+pub async fn retry_delivery(
+    _: AdminKey,
+    State(state): State<Arc<RepoFactory>>,
+    Path(path): Path<DeliveryPath>,
+) -> Result<Json<ApiResponse<Delivery>>, AppError> {
+    let delivery = state
+        .webhooks
+        .retry(path.id, path.delivery_id)
+        .await?
+        .ok_or(AppError::NotFound("Delivery"))?;
+    Ok(Json(ApiResponse {
+        code: 0,
+        resp: "ok".to_string(),
+        data: delivery,
+    }))
+}
+
+async fn find(state: &RepoFactory, id: i64) -> Result<Webhook, AppError> {
+    state
+        .webhooks
+        .find(id)
+        .await?
+        .ok_or(AppError::NotFound("Webhook"))
+}
+
+fn dedup(mut events: Vec<String>) -> Vec<String> {
+    events.sort();
+    events.dedup();
+    events
+}
+
+fn http_url(url: &str) -> Result<(), ValidationError> {
+    match reqwest::Url::parse(url) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") && url.has_host() => Ok(()),
+        _ => Err(ValidationError::new("url").with_message("expected an http or https URL".into())),
+    }
+}
+
+fn known_events(events: &[String]) -> Result<(), ValidationError> {
+    match events
+        .iter()
+        .find(|event| !EVENTS.contains(&event.as_str()))
+    {
+        None => Ok(()),
+        Some(event) => Err(ValidationError::new("event")
+            .with_message(format!("{} is not a known event", event).into())),
+    }
+}
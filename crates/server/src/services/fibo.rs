@@ -1,27 +1,23 @@
-use lazy_static::lazy_static;
 use num::BigUint;
 use std::collections::HashMap;
-use std::sync::RwLock;
+use std::sync::{Arc, RwLock};
 
-lazy_static! {
-    static ref FIBO_CACHE: RwLock<HashMap<usize, BigUint>> = RwLock::new(HashMap::new());
-}
-pub fn calc_fibo_rec(n: usize) -> BigUint {
+/// Results shared between requests, kept in `AppState`.
+#[derive(Clone, Default)]
+pub struct FiboCache(Arc<RwLock<HashMap<usize, BigUint>>>);
+
+pub fn calc_fibo_rec(n: usize, cache: &FiboCache) -> BigUint {
     {
-        if let Some(res) = FIBO_CACHE
-            .read()
-            .ok()
-            .and_then(|cache| cache.get(&n).cloned())
-        {
+        if let Some(res) = cache.0.read().ok().and_then(|cache| cache.get(&n).cloned()) {
             return res;
         }
     }
     if n == 0 || n == 1 {
         BigUint::from(n)
     } else {
-        let res = calc_fibo_rec(n - 1) + calc_fibo_rec(n - 2);
+        let res = calc_fibo_rec(n - 1, cache) + calc_fibo_rec(n - 2, cache);
         {
-            if let Ok(mut cache) = FIBO_CACHE.write() {
+            if let Ok(mut cache) = cache.0.write() {
                 cache.insert(n, res.clone());
             }
         }
@@ -1,29 +1,80 @@
 use num::{BigUint, pow::pow};
-use tokio::task;
-pub async fn calc_hanoi_num(num_cell: usize) -> Result<BigUint, task::JoinError> {
+#[cfg(feature = "grpc")]
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use super::CalcError;
+use crate::shutdown::Shutdown;
+
+pub async fn calc_hanoi_num(num_cell: usize, shutdown: &Shutdown) -> Result<BigUint, CalcError> {
     if num_cell < 1_000_000 {
         Ok(pow(BigUint::from(2usize), num_cell) - BigUint::from(1usize))
     } else {
-        task::spawn_blocking(move || {
-            Ok(pow(BigUint::from(2usize), num_cell) - BigUint::from(1usize))
+        let job = shutdown
+            .spawn_blocking(move || pow(BigUint::from(2usize), num_cell) - BigUint::from(1usize));
+        let token = shutdown.cancel_token();
+        // `pow` can't be interrupted, so stop waiting for it instead.
+        tokio::select! {
+            res = job => Ok(res?),
+            _ = token.cancelled() => Err(CalcError::Cancelled),
+        }
+    }
+}
+pub async fn calc_hanoi_rec(
+    num_cell: usize,
+    shutdown: &Shutdown,
+) -> Result<Vec<(u8, u8)>, CalcError> {
+    let token = shutdown.cancel_token();
+    shutdown
+        .spawn_blocking(move || {
+            let mut orders: Vec<(u8, u8)> = Vec::new();
+            calc_hanoi_inner_(num_cell, 1, 3, 2, &mut |order| orders.push(order), &token)?;
+            Ok(orders)
         })
         .await?
-    }
 }
-pub async fn calc_hanoi_rec(num_cell: usize) -> Result<Vec<(u8, u8)>, task::JoinError> {
-    task::spawn_blocking(move || {
-        let mut orders: Vec<(u8, u8)> = Vec::new();
-        calc_hanoi_inner_(num_cell, 1, 3, 2, &mut orders);
-        Ok(orders)
-    })
-    .await?
+/// Same orders as [`calc_hanoi_rec`], sent one by one as they are found,
+/// so they are never all held in memory.
+/// Stops early once the receiver is dropped.
+#[cfg(feature = "grpc")]
+pub fn stream_hanoi_rec(
+    num_cell: usize,
+    shutdown: &Shutdown,
+) -> mpsc::Receiver<Result<(u8, u8), CalcError>> {
+    // Enough to keep the sender busy while the receiver writes to the network
+    let (tx, rx) = mpsc::channel(1024);
+    // Stops the recursion once nobody listens anymore
+    let token = shutdown.cancel_token().child_token();
+    let closed = token.clone();
+    shutdown.spawn_blocking(move || {
+        let mut send = |order| {
+            if tx.blocking_send(Ok(order)).is_err() {
+                closed.cancel();
+            }
+        };
+        if let Err(err) = calc_hanoi_inner_(num_cell, 1, 3, 2, &mut send, &token) {
+            let _ = tx.blocking_send(Err(err));
+        }
+    });
+    rx
 }
-fn calc_hanoi_inner_(num_cell: usize, from: u8, to: u8, via: u8, res_vec: &mut Vec<(u8, u8)>) {
+fn calc_hanoi_inner_(
+    num_cell: usize,
+    from: u8,
+    to: u8,
+    via: u8,
+    emit: &mut impl FnMut((u8, u8)),
+    token: &CancellationToken,
+) -> Result<(), CalcError> {
+    if token.is_cancelled() {
+        return Err(CalcError::Cancelled);
+    }
     if num_cell == 1 {
-        res_vec.push((from, to));
+        emit((from, to));
     } else {
-        calc_hanoi_inner_(num_cell - 1, from, via, to, res_vec);
-        res_vec.push((from, to));
-        calc_hanoi_inner_(num_cell - 1, via, to, from, res_vec);
+        calc_hanoi_inner_(num_cell - 1, from, via, to, emit, token)?;
+        emit((from, to));
+        calc_hanoi_inner_(num_cell - 1, via, to, from, emit, token)?;
     }
+    Ok(())
 }
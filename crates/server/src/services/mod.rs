@@ -1,2 +1,12 @@
 pub mod fibo;
 pub mod hanoi;
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum CalcError {
+    #[error("Thread join error : {0}")]
+    Join(#[from] tokio::task::JoinError),
+    #[error("Calculation cancelled by server shutdown")]
+    Cancelled,
+}
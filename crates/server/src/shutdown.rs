@@ -0,0 +1,139 @@
+//! # shutdown
+//! Coordinates graceful shutdown.
+//! ## Sequence
+//! 1. A signal arrives and readiness starts answering 503.
+//! 2. After the drain period the listener stops accepting connections.
+//! 3. In-flight requests and tracked jobs get until the timeout to finish.
+//! 4. Past the timeout, jobs are cancelled and the process exits anyway.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use log::{error, info, warn};
+use tokio::task::JoinHandle;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+
+use crate::{config::ShutdownConfig, prelude::*};
+
+#[derive(Clone)]
+pub struct Shutdown {
+    config: ShutdownConfig,
+    ready: Arc<AtomicBool>,
+    /// Fires when the listener should stop accepting connections.
+    stop: CancellationToken,
+    /// Fires when long-running jobs must give up.
+    cancel: CancellationToken,
+    jobs: TaskTracker,
+}
+impl Shutdown {
+    pub fn new(config: ShutdownConfig) -> Self {
+        Self {
+            config,
+            ready: Arc::new(AtomicBool::new(true)),
+            stop: CancellationToken::new(),
+            cancel: CancellationToken::new(),
+            jobs: TaskTracker::new(),
+        }
+    }
+
+    /// `false` once a shutdown has begun.
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Relaxed)
+    }
+
+    /// Token handed to long-running services so they can stop early.
+    #[cfg_attr(not(feature = "calc"), allow(dead_code))]
+    pub fn cancel_token(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+
+    /// `spawn_blocking` that is waited for during shutdown.
+    #[cfg_attr(not(feature = "calc"), allow(dead_code))]
+    pub fn spawn_blocking<F, T>(&self, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.jobs.spawn_blocking(f)
+    }
+
+    /// Completes when the listener should stop accepting connections.
+    pub async fn stopped(&self) {
+        self.stop.cancelled().await
+    }
+
+    /// Waits for a termination signal, then flips readiness and drains.
+    pub async fn watch_signals(self) {
+        wait_for_signal().await;
+        self.ready.store(false, Ordering::Relaxed);
+        info!(
+            "Readiness set to unavailable, draining for {:?}",
+            self.config.drain
+        );
+        tokio::time::sleep(self.config.drain).await;
+        info!("Stop accepting new connections");
+        self.stop.cancel();
+    }
+
+    /// Waits for `server` and every tracked job within the shutdown timeout.
+    /// Returns `false` when the timeout elapsed and jobs were cancelled.
+    pub async fn finish<F: Future>(&self, server: F) -> bool {
+        self.jobs.close();
+        let finished = tokio::time::timeout(self.config.timeout, async {
+            server.await;
+            self.jobs.wait().await;
+        })
+        .await;
+        match finished {
+            Ok(..) => true,
+            Err(..) => {
+                warn!(
+                    "Shutdown timed out after {:?}, cancelling {} job(s)",
+                    self.config.timeout,
+                    self.jobs.len()
+                );
+                self.cancel.cancel();
+                false
+            }
+        }
+    }
+}
+
+/// Signal handler for graceful shutdown
+/// Essential for container environments
+async fn wait_for_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        match (
+            signal(SignalKind::terminate()),
+            signal(SignalKind::interrupt()),
+        ) {
+            (Ok(mut sigterm), Ok(mut sigint)) => tokio::select! {
+                _ = sigterm.recv() => info!("Received SIGTERM"),
+                _ = sigint.recv() => {println!(); info!("Received SIGINT")},
+            },
+            (Err(err), _) | (_, Err(err)) => {
+                error!("Fail to install signal handler : {}", err);
+                wait_for_ctrl_c().await
+            }
+        }
+    }
+
+    #[cfg(windows)]
+    wait_for_ctrl_c().await
+}
+
+async fn wait_for_ctrl_c() {
+    match tokio::signal::ctrl_c().await {
+        Ok(..) => {
+            println!();
+            info!("Received Ctrl+C");
+        }
+        Err(err) => {
+            // Without any signal source the server would never stop gracefully,
+            // so keep it running until it is killed.
+            error!("Fail to listen for Ctrl+C : {}", err);
+            std::future::pending::<()>().await
+        }
+    }
+}
@@ -0,0 +1,198 @@
+//! # state
+//! Shared application state.
+//! Route modules and handlers extract only the parts they use through `FromRef`,
+//! e.g. `State<Arc<RepoFactory>>` or `State<Shutdown>`.
+
+use std::{collections::HashMap, sync::OnceLock};
+
+use aide::openapi::{OpenApi, ReferenceOr};
+use api_routes::ApiVersion;
+use axum::extract::FromRef;
+
+use crate::{config::Config, prelude::*, repository::RepoFactory, shutdown::Shutdown};
+
+#[derive(Clone)]
+pub struct AppState {
+    pub repos: Arc<RepoFactory>,
+    pub config: Arc<Config>,
+    pub shutdown: Shutdown,
+    pub api_doc: ApiDoc,
+    #[cfg(feature = "calc")]
+    pub fibo_cache: crate::services::fibo::FiboCache,
+    #[cfg(feature = "graphql")]
+    pub graphql: crate::graphql::ApiSchema,
+    #[cfg(feature = "events")]
+    pub events: crate::events::Hub,
+}
+impl AppState {
+    pub fn new(config: Config, repos: RepoFactory, shutdown: Shutdown) -> Self {
+        Self {
+            repos: Arc::new(repos),
+            config: Arc::new(config),
+            shutdown,
+            api_doc: ApiDoc::default(),
+            #[cfg(feature = "calc")]
+            fibo_cache: Default::default(),
+            #[cfg(feature = "graphql")]
+            graphql: crate::graphql::schema(),
+            #[cfg(feature = "events")]
+            events: Default::default(),
+        }
+    }
+}
+
+/// # ApiDoc
+/// The generated OpenAPI document, whole and split per API version.
+/// Filled once every route is registered, see `run_server` in `main.rs`.
+#[derive(Clone, Default)]
+pub struct ApiDoc(Arc<OnceLock<Docs>>);
+struct Docs {
+    full: Arc<OpenApi>,
+    versions: HashMap<&'static str, Arc<OpenApi>>,
+}
+impl ApiDoc {
+    pub fn set(&self, api: OpenApi, versions: &[ApiVersion]) {
+        let versions = versions
+            .iter()
+            .map(|version| (version.name(), Arc::new(only_version(&api, version))))
+            .collect();
+        let _ = self.0.set(Docs {
+            full: Arc::new(api),
+            versions,
+        });
+    }
+    #[cfg_attr(not(feature = "docs"), allow(dead_code))]
+    pub fn get(&self) -> Arc<OpenApi> {
+        self.0
+            .get()
+            .map(|docs| docs.full.clone())
+            .unwrap_or_default()
+    }
+    /// Document of the version named `name`, e.g. `v1`.
+    #[cfg_attr(not(feature = "docs"), allow(dead_code))]
+    pub fn version(&self, name: &str) -> Option<Arc<OpenApi>> {
+        self.0.get()?.versions.get(name).cloned()
+    }
+}
+
+/// `api` with the paths under `version` only, and the tags they use.
+fn only_version(api: &OpenApi, version: &ApiVersion) -> OpenApi {
+    let mut api = api.clone();
+    api.info.version = version.name().to_string();
+    let nested = format!("{}/", version.prefix);
+    let Some(paths) = api.paths.as_mut() else {
+        return api;
+    };
+    paths
+        .paths
+        .retain(|path, _| path == version.prefix || path.starts_with(&nested));
+    let used: Vec<&String> = paths
+        .paths
+        .values()
+        .filter_map(|item| match item {
+            ReferenceOr::Item(item) => Some(item),
+            ReferenceOr::Reference { .. } => None,
+        })
+        .flat_map(|item| item.iter().flat_map(|(_, op)| &op.tags))
+        .collect();
+    let tags = api
+        .tags
+        .iter()
+        .filter(|tag| used.contains(&&tag.name))
+        .cloned()
+        .collect();
+    api.tags = tags;
+    api
+}
+
+macro_rules! sub_state {
+    ($($field:ident : $ty:ty),* $(,)?) => {
+        $(
+            impl FromRef<AppState> for $ty {
+                fn from_ref(state: &AppState) -> Self {
+                    state.$field.clone()
+                }
+            }
+        )*
+    };
+}
+sub_state! {
+    repos: Arc<RepoFactory>,
+    config: Arc<Config>,
+    shutdown: Shutdown,
+    api_doc: ApiDoc,
+}
+#[cfg(feature = "calc")]
+sub_state! {
+    fibo_cache: crate::services::fibo::FiboCache,
+}
+#[cfg(feature = "graphql")]
+sub_state! {
+    graphql: crate::graphql::ApiSchema,
+}
+#[cfg(feature = "events")]
+sub_state! {
+    events: crate::events::Hub,
+}
+
+/// # module_state
+/// State of a route module, holding only the fields of [`AppState`] it uses.
+/// Handlers still extract each field on its own, e.g. `State<Arc<Config>>`.
+macro_rules! module_state {
+    ($(#[$meta:meta])* $name:ident { $($field:ident : $ty:ty),* $(,)? }) => {
+        $(#[$meta])*
+        #[derive(Clone)]
+        pub struct $name {
+            $($field: $ty,)*
+        }
+        impl FromRef<AppState> for $name {
+            fn from_ref(state: &AppState) -> Self {
+                Self {
+                    $($field: state.$field.clone(),)*
+                }
+            }
+        }
+        $(
+            impl FromRef<$name> for $ty {
+                fn from_ref(state: &$name) -> Self {
+                    state.$field.clone()
+                }
+            }
+        )*
+    };
+}
+module_state! {
+    /// Repositories, and the config `X-Auth-Key` is checked against.
+    ReposState {
+        repos: Arc<RepoFactory>,
+        config: Arc<Config>,
+    }
+}
+#[cfg(feature = "calc")]
+module_state! {
+    CalcState {
+        fibo_cache: crate::services::fibo::FiboCache,
+        shutdown: Shutdown,
+    }
+}
+#[cfg(feature = "graphql")]
+module_state! {
+    GraphqlState {
+        graphql: crate::graphql::ApiSchema,
+        repos: Arc<RepoFactory>,
+        config: Arc<Config>,
+    }
+}
+#[cfg(feature = "events")]
+module_state! {
+    EventsState {
+        events: crate::events::Hub,
+        config: Arc<Config>,
+        shutdown: Shutdown,
+    }
+}
+
+/// For route modules without state.
+impl FromRef<AppState> for () {
+    fn from_ref(_: &AppState) {}
+}
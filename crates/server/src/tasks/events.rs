@@ -0,0 +1,46 @@
+//! # events
+//! Feeds the notifications of `row_changes` to the [`Hub`] of live events.
+//! Reconnects on its own, changes committed while disconnected are lost.
+
+use log::{error, info, warn};
+use sqlx::{PgPool, postgres::PgListener};
+
+use crate::{events::Hub, repository::outbox::CHANGES_CHANNEL, shutdown::Shutdown};
+
+pub async fn run(pool: PgPool, hub: Hub, shutdown: Shutdown) {
+    let mut listener = match PgListener::connect_with(&pool).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!(
+                "Fail to listen for row changes, live events are off : {}",
+                err
+            );
+            return;
+        }
+    };
+    if let Err(err) = listener.listen(CHANGES_CHANNEL).await {
+        error!(
+            "Fail to listen for row changes, live events are off : {}",
+            err
+        );
+        return;
+    }
+    info!("Listening for row changes on {}", CHANGES_CHANNEL);
+    loop {
+        let notification = tokio::select! {
+            notification = listener.try_recv() => notification,
+            _ = shutdown.stopped() => return,
+        };
+        match notification {
+            Ok(Some(notification)) => match serde_json::from_str(notification.payload()) {
+                Ok(change) => hub.publish(change),
+                Err(err) => error!("Unreadable row change {} : {}", notification.payload(), err),
+            },
+            Ok(None) => warn!("Connection for row changes lost, reconnecting"),
+            Err(err) => {
+                error!("Fail to receive row changes : {}", err);
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
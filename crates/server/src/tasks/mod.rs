@@ -0,0 +1,11 @@
+//! # tasks
+//! Background jobs running next to the server.
+//! Each task stops once `Shutdown::stopped` fires.
+
+#[cfg(feature = "events")]
+pub mod events;
+pub mod outbox;
+pub mod publish;
+pub mod purge;
+#[cfg(feature = "webhooks")]
+pub mod webhooks;
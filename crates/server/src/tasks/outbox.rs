@@ -0,0 +1,124 @@
+//! # outbox
+//! Relays the events of `repository/outbox.rs` to the sinks of `OUTBOX_SINKS`.
+//! Each batch is handed to every sink and marked published in one transaction,
+//! so a failure hands the whole batch again later : sinks see events at least once.
+//! Woken by the `outbox` notification of every write, and checks every `OUTBOX_INTERVAL_SECS`
+//! in case a notification was missed.
+
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use log::{error, info, warn};
+use sqlx::{PgPool, postgres::PgListener};
+
+use crate::{
+    config::{OutboxConfig, OutboxSink},
+    error::AppError,
+    prelude::*,
+    repository::{RepoFactory, UnitOfWork, outbox::CHANNEL},
+    shutdown::Shutdown,
+};
+
+/// Events relayed per transaction.
+const BATCH: i64 = 100;
+/// Time between two removals of old published events.
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+pub async fn run(pool: PgPool, repos: Arc<RepoFactory>, config: OutboxConfig, shutdown: Shutdown) {
+    let mut listener = match listen(&pool).await {
+        Ok(listener) => Some(listener),
+        Err(err) => {
+            warn!("Fail to listen for outbox events, polling only : {}", err);
+            None
+        }
+    };
+    let mut interval = tokio::time::interval(config.interval);
+    let mut purged = Instant::now();
+    loop {
+        tokio::select! {
+            _ = interval.tick() => (),
+            _ = notified(&mut listener, config.interval) => (),
+            _ = shutdown.stopped() => return,
+        }
+        relay(&repos, &config.sinks).await;
+        if purged.elapsed() >= PURGE_INTERVAL {
+            purged = Instant::now();
+            purge(&repos, config.retention).await;
+        }
+    }
+}
+
+async fn listen(pool: &PgPool) -> Result<PgListener, sqlx::Error> {
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener.listen(CHANNEL).await?;
+    Ok(listener)
+}
+
+/// Completes on the next `outbox` notification, never without a listener.
+async fn notified(listener: &mut Option<PgListener>, backoff: Duration) {
+    match listener {
+        Some(listener) => {
+            // `recv` reconnects on its own, errors only need a pause before the next try
+            if let Err(err) = listener.recv().await {
+                error!("Fail to receive outbox notifications : {}", err);
+                tokio::time::sleep(backoff).await;
+            }
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Relays batches until no event is pending, or one fails.
+async fn relay(repos: &RepoFactory, sinks: &[OutboxSink]) {
+    loop {
+        match repos
+            .transaction(|uow| async move { relay_batch(&uow, sinks).await })
+            .await
+        {
+            Ok(n) if n as i64 == BATCH => (),
+            Ok(..) => return,
+            Err(err) => {
+                error!("Fail to relay outbox events, retrying later : {}", err);
+                return;
+            }
+        }
+    }
+}
+
+async fn relay_batch(uow: &UnitOfWork, sinks: &[OutboxSink]) -> Result<usize, AppError> {
+    let entries = uow.outbox.claim(BATCH).await?;
+    if entries.is_empty() {
+        return Ok(0);
+    }
+    let ids: Vec<i64> = entries.iter().map(|entry| entry.id).collect();
+    for sink in sinks {
+        match sink {
+            OutboxSink::Webhooks => {
+                uow.webhooks.enqueue(&ids).await?;
+            }
+            OutboxSink::Events => uow.outbox.notify_changes(&ids).await?,
+            OutboxSink::Log => {
+                for entry in &entries {
+                    info!("Event {} {} : {}", entry.id, entry.event, entry.payload);
+                }
+            }
+        }
+    }
+    uow.outbox.mark_published(&ids).await?;
+    Ok(entries.len())
+}
+
+async fn purge(repos: &RepoFactory, retention: Duration) {
+    let Ok(retention) = chrono::Duration::from_std(retention) else {
+        return;
+    };
+    match repos
+        .outbox
+        .purge_published_before(Utc::now() - retention)
+        .await
+    {
+        Ok(0) => (),
+        Ok(n) => info!("Removed {} relayed outbox event(s)", n),
+        Err(err) => error!("Fail to remove relayed outbox events : {}", err),
+    }
+}
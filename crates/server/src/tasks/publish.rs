@@ -0,0 +1,22 @@
+//! # publish
+//! Publishes scheduled posts once their `publish_at` has passed.
+//! Schedules live in Postgres, so posts due while the server was down go out on start.
+
+use log::{error, info};
+
+use crate::{config::PublishConfig, prelude::*, repository::RepoFactory, shutdown::Shutdown};
+
+pub async fn run(repos: Arc<RepoFactory>, config: PublishConfig, shutdown: Shutdown) {
+    let mut interval = tokio::time::interval(config.interval);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => (),
+            _ = shutdown.stopped() => return,
+        }
+        match repos.posts.publish_due().await {
+            Ok(0) => (),
+            Ok(n) => info!("Published {} scheduled post(s)", n),
+            Err(err) => error!("Fail to publish scheduled posts : {}", err),
+        }
+    }
+}
@@ -0,0 +1,42 @@
+//! # purge
+//! Removes rows soft-deleted longer than `SOFT_DELETE_RETENTION_DAYS`,
+//! and expired idempotency keys.
+
+use log::{error, info};
+
+use crate::{
+    config::SoftDeleteConfig,
+    prelude::*,
+    repository::{Repo, RepoFactory},
+    shutdown::Shutdown,
+};
+
+pub async fn run(repos: Arc<RepoFactory>, config: SoftDeleteConfig, shutdown: Shutdown) {
+    let mut interval = tokio::time::interval(config.purge_interval);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => (),
+            _ = shutdown.stopped() => return,
+        }
+        let before = chrono::Utc::now() - config.retention;
+        match repos.posts.purge_deleted_before(before).await {
+            Ok(0) => (),
+            Ok(n) => info!("Purged {} deleted post(s)", n),
+            Err(err) => error!("Fail to purge deleted posts : {}", err),
+        }
+        // Users go with the posts they still have, in one transaction
+        match repos
+            .transaction(|uow| async move { Ok(uow.user.purge_deleted_before(before).await?) })
+            .await
+        {
+            Ok(0) => (),
+            Ok(n) => info!("Purged {} deleted user(s)", n),
+            Err(err) => error!("Fail to purge deleted users : {}", err),
+        }
+        match repos.idempotency.purge_expired().await {
+            Ok(0) => (),
+            Ok(n) => info!("Removed {} expired idempotency key(s)", n),
+            Err(err) => error!("Fail to remove expired idempotency keys : {}", err),
+        }
+    }
+}
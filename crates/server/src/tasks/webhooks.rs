@@ -0,0 +1,336 @@
+//! # webhooks
+//! Sends the deliveries queued for webhooks, retrying failures with exponential backoff
+//! until `WEBHOOK_MAX_ATTEMPTS`, after which they are `dead` until retried by hand.
+//! Any 2xx answer is a success, redirects are not followed and count as failures.
+//! Deliveries are at least once, `Webhook-Id` tells duplicates apart.
+//! ## Headers
+//! - `Webhook-Id` : id of the delivery, the same on every attempt
+//! - `Webhook-Event` : e.g. `post.published`
+//! - `Webhook-Timestamp` : Unix time of the attempt, in seconds
+//! - `Webhook-Signature` : `sha256=` then the hex HMAC-SHA256 of `{timestamp}.{body}`,
+//!   keyed with the secret answered when the webhook was created
+//!
+//! Receivers compute the signature over the raw body, compare it in constant time,
+//! and reject timestamps a few minutes old.
+
+use std::{
+    error::Error,
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use log::{error, info, warn};
+use reqwest::{Client, header::CONTENT_TYPE, redirect::Policy};
+use sha2::Sha256;
+use tokio::task::JoinSet;
+
+use crate::{
+    config::WebhookConfig,
+    prelude::*,
+    repository::{
+        RepoFactory,
+        webhooks::{AttemptResult, Due},
+    },
+    shutdown::Shutdown,
+};
+
+/// Deliveries sent at once.
+const BATCH: i64 = 32;
+/// Longest wait between two attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(6 * 60 * 60);
+/// Time past the request timeout before another sender may take a delivery again.
+const LEASE_MARGIN: Duration = Duration::from_secs(30);
+/// Part of the response kept in the attempt log.
+const MAX_RESPONSE_BODY: usize = 1024;
+
+pub async fn run(repos: Arc<RepoFactory>, config: WebhookConfig, shutdown: Shutdown) {
+    let client = match client(&config) {
+        Ok(client) => client,
+        Err(err) => {
+            error!(
+                "Fail to build the webhook client, webhooks are off : {}",
+                err
+            );
+            return;
+        }
+    };
+    let mut interval = tokio::time::interval(config.interval);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => (),
+            _ = shutdown.stopped() => return,
+        }
+        // Full batches mean more may be due
+        loop {
+            let due = match repos
+                .webhooks
+                .claim_due(BATCH, config.timeout + LEASE_MARGIN)
+                .await
+            {
+                Ok(due) => due,
+                Err(err) => {
+                    error!("Fail to read due webhook deliveries : {}", err);
+                    break;
+                }
+            };
+            let full = due.len() as i64 == BATCH;
+            let mut sends = JoinSet::new();
+            for due in due {
+                let (client, repos, config) = (client.clone(), repos.clone(), config.clone());
+                sends.spawn(async move { deliver(&client, &repos, &config, due).await });
+            }
+            sends.join_all().await;
+            if !full || !shutdown.is_ready() {
+                break;
+            }
+        }
+    }
+}
+
+/// Client of every delivery. Redirects are not followed, they could lead anywhere the server reaches.
+fn client(config: &WebhookConfig) -> reqwest::Result<Client> {
+    Client::builder()
+        .timeout(config.timeout)
+        .redirect(Policy::none())
+        .user_agent(concat!(
+            "api.movingju.com-webhooks/",
+            env!("CARGO_PKG_VERSION")
+        ))
+        .build()
+}
+
+async fn deliver(client: &Client, repos: &RepoFactory, config: &WebhookConfig, due: Due) {
+    let result = send(client, &due).await;
+    let attempts = due.attempts as u32 + 1;
+    let retry_in = (!result.is_success() && attempts < config.max_attempts)
+        .then(|| backoff(config.retry_base, attempts));
+    if let Err(err) = repos.webhooks.record(&due, &result, retry_in).await {
+        error!("Fail to record webhook delivery {} : {}", due.id, err);
+        return;
+    }
+    match (result.is_success(), retry_in) {
+        (true, _) => info!("Delivered webhook delivery {} ({})", due.id, due.event),
+        (false, Some(..)) => (),
+        (false, None) => warn!(
+            "Webhook delivery {} is dead after {} attempts",
+            due.id, attempts
+        ),
+    }
+}
+
+/// One attempt at `due`, with what it got back.
+async fn send(client: &Client, due: &Due) -> AttemptResult {
+    let body = due.payload.to_string();
+    let timestamp = Utc::now().timestamp();
+    let started = Instant::now();
+    let sent = client
+        .post(&due.url)
+        .header(CONTENT_TYPE, "application/json")
+        .header("Webhook-Id", due.id)
+        .header("Webhook-Event", &due.event)
+        .header("Webhook-Timestamp", timestamp)
+        .header("Webhook-Signature", sign(&due.secret, timestamp, &body))
+        .body(body)
+        .send()
+        .await;
+    let (response_status, response_body, error) = match sent {
+        Ok(mut response) => {
+            let status = response.status().as_u16() as i32;
+            let mut body = Vec::new();
+            while body.len() < MAX_RESPONSE_BODY {
+                match response.chunk().await {
+                    Ok(Some(chunk)) => body.extend_from_slice(&chunk),
+                    Ok(None) | Err(..) => break,
+                }
+            }
+            body.truncate(MAX_RESPONSE_BODY);
+            let body = String::from_utf8_lossy(&body).into_owned();
+            (Some(status), Some(body), None)
+        }
+        Err(err) => (None, None, Some(describe(&err))),
+    };
+    AttemptResult {
+        response_status,
+        response_body,
+        error,
+        duration_ms: started.elapsed().as_millis().try_into().unwrap_or(i32::MAX),
+    }
+}
+
+/// Wait before the next attempt, once `attempts` failed : `base`, then doubling.
+fn backoff(base: Duration, attempts: u32) -> Duration {
+    base.saturating_mul(1 << (attempts - 1).min(20))
+        .min(MAX_BACKOFF)
+}
+
+/// `err` with its causes, e.g. the refused connection behind a failed request.
+fn describe(err: &dyn Error) -> String {
+    let mut message = err.to_string();
+    let mut source = err.source();
+    while let Some(cause) = source {
+        message = format!("{} : {}", message, cause);
+        source = cause.source();
+    }
+    message
+}
+
+/// `Webhook-Signature` of `body` sent at `timestamp`.
+pub fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    fn config() -> WebhookConfig {
+        WebhookConfig {
+            interval: Duration::from_secs(1),
+            timeout: Duration::from_secs(5),
+            max_attempts: 5,
+            retry_base: Duration::from_secs(30),
+        }
+    }
+
+    /// Listener answering one request with `response`, handing back the request it read.
+    async fn receiver(response: &'static str) -> (String, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let handle = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 4096];
+            // Headers, then as much body as `Content-Length` announces
+            loop {
+                let n = stream.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&request).to_string();
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length = head
+                        .lines()
+                        .find_map(|line| {
+                            line.to_ascii_lowercase()
+                                .strip_prefix("content-length: ")
+                                .map(str::to_string)
+                        })
+                        .and_then(|length| length.trim().parse::<usize>().ok())
+                        .unwrap_or(0);
+                    if body.len() >= length || n == 0 {
+                        break;
+                    }
+                }
+            }
+            stream.write_all(response.as_bytes()).await.unwrap();
+            stream.shutdown().await.unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        (url, handle)
+    }
+
+    fn due(url: String) -> Due {
+        Due {
+            id: 7,
+            event: "webhook.ping".to_string(),
+            payload: json!({"event": "webhook.ping"}),
+            attempts: 0,
+            url,
+            secret: "whsec_test".to_string(),
+        }
+    }
+
+    #[test]
+    fn sign_matches_a_known_vector() {
+        assert_eq!(
+            sign("whsec_test", 1_700_000_000, r#"{"event":"webhook.ping"}"#),
+            "sha256=f1b0032e7f2eb55946822f997ea28bcc9b2130583c2322179bc8afecdc1c5726"
+        );
+        assert_eq!(
+            sign("key", 0, ""),
+            "sha256=85841b4efc3cd7776c3c8f9b7cca9e281c550e5d19889d78e9e669c6337f000d"
+        );
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let base = Duration::from_secs(30);
+        assert_eq!(backoff(base, 1), base);
+        assert_eq!(backoff(base, 2), Duration::from_secs(60));
+        assert_eq!(backoff(base, 4), Duration::from_secs(240));
+        assert_eq!(backoff(base, 20), MAX_BACKOFF);
+        assert_eq!(backoff(base, 100), MAX_BACKOFF);
+        assert_eq!(backoff(Duration::MAX, 3), MAX_BACKOFF);
+    }
+
+    #[tokio::test]
+    async fn send_posts_a_signed_delivery() {
+        let (url, request) = receiver("HTTP/1.1 204 No Content\r\nconnection: close\r\n\r\n").await;
+        let result = send(&client(&config()).unwrap(), &due(url)).await;
+        assert!(result.is_success(), "{:?}", result.error);
+        assert_eq!(result.response_status, Some(204));
+
+        let request = request.await.unwrap();
+        let (head, body) = request.split_once("\r\n\r\n").unwrap();
+        let head = head.to_ascii_lowercase();
+        assert!(head.starts_with("post /hook http/1.1"), "{}", head);
+        assert!(head.contains("webhook-id: 7"), "{}", head);
+        assert!(head.contains("webhook-event: webhook.ping"), "{}", head);
+        assert_eq!(body, r#"{"event":"webhook.ping"}"#);
+        let timestamp: i64 = head
+            .lines()
+            .find_map(|line| line.strip_prefix("webhook-timestamp: "))
+            .unwrap()
+            .parse()
+            .unwrap();
+        let signature = format!("webhook-signature: {}", sign("whsec_test", timestamp, body));
+        assert!(head.contains(&signature), "{}", head);
+    }
+
+    #[tokio::test]
+    async fn send_does_not_follow_redirects() {
+        let (url, request) = receiver(
+            "HTTP/1.1 302 Found\r\nlocation: http://169.254.169.254/\r\n\
+             content-length: 0\r\nconnection: close\r\n\r\n",
+        )
+        .await;
+        let result = send(&client(&config()).unwrap(), &due(url)).await;
+        request.await.unwrap();
+        assert_eq!(result.response_status, Some(302));
+        assert!(!result.is_success());
+    }
+
+    #[tokio::test]
+    async fn send_keeps_the_start_of_the_response() {
+        let (url, request) = receiver(
+            "HTTP/1.1 500 Internal Server Error\r\ncontent-length: 5\r\n\
+             connection: close\r\n\r\noops!",
+        )
+        .await;
+        let result = send(&client(&config()).unwrap(), &due(url)).await;
+        request.await.unwrap();
+        assert_eq!(result.response_status, Some(500));
+        assert_eq!(result.response_body.as_deref(), Some("oops!"));
+        assert!(!result.is_success());
+    }
+
+    #[tokio::test]
+    async fn send_records_connection_errors() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        drop(listener);
+        let result = send(&client(&config()).unwrap(), &due(url)).await;
+        assert_eq!(result.response_status, None);
+        assert!(result.error.is_some());
+    }
+}
@@ -0,0 +1,241 @@
+//! # validation
+//! Declarative checks on request types, with `validator`.
+//! ## How to use
+//! ```
+//! #[derive(Deserialize, JsonSchema, Validate)]
+//! pub struct FiboQuery {
+//!     #[validate(range(max = 5_000))]
+//!     n: usize,
+//! }
+//! pub async fn fibo(Valid(Query(query)): Valid<Query<FiboQuery>>) { ... }
+//! ```
+//! Failing requests are answered 422 with one [`FieldError`] per broken constraint.
+//! `schemars` reads the same `#[validate(...)]` attributes, so the constraints show up in the OpenAPI schema.
+
+#![cfg_attr(
+    not(any(feature = "calc", feature = "db", feature = "users")),
+    allow(dead_code)
+)]
+
+use aide::{
+    OperationInput,
+    generate::GenContext,
+    openapi::{Operation, Response},
+};
+use axum::{
+    extract::{FromRequest, FromRequestParts, Request},
+    http::request::Parts,
+};
+use schemars::JsonSchema;
+use serde_json::Value;
+use validator::{Validate, ValidationError, ValidationErrors, ValidationErrorsKind};
+
+use crate::{error::AppError, prelude::*};
+
+#[cfg(any(feature = "db", feature = "users"))]
+pub const MAX_USER_NAME_LEN: u64 = 64;
+#[cfg(feature = "db")]
+pub const MAX_TITLE_LEN: u64 = 200;
+/// Markdown source of a post, in characters
+#[cfg(feature = "db")]
+pub const MAX_CONTENT_LEN: u64 = 100_000;
+
+/// User names : no leading or trailing whitespace, no control characters.
+#[cfg(any(feature = "db", feature = "users"))]
+pub static USER_NAME: std::sync::LazyLock<regex::Regex> = std::sync::LazyLock::new(|| {
+    regex::Regex::new(r"^[^\s\p{Cc}](?:[^\p{Cc}]*[^\s\p{Cc}])?$").unwrap()
+});
+
+/// # Valid
+/// Wraps our `Query<T>` or `Json<T>`, running `T::validate` once extracted.
+pub struct Valid<E>(pub E);
+
+impl<T, S> FromRequestParts<S> for Valid<Query<T>>
+where
+    T: Validate,
+    Query<T>: FromRequestParts<S, Rejection = AppError>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let query = Query::<T>::from_request_parts(parts, state).await?;
+        query.0.validate().map_err(invalid)?;
+        Ok(Valid(query))
+    }
+}
+impl<T, S> FromRequest<S> for Valid<Json<T>>
+where
+    T: Validate,
+    Json<T>: FromRequest<S, Rejection = AppError>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let json = Json::<T>::from_request(req, state).await?;
+        json.0.validate().map_err(invalid)?;
+        Ok(Valid(json))
+    }
+}
+impl<E: OperationInput> OperationInput for Valid<E> {
+    fn operation_input(ctx: &mut GenContext, operation: &mut Operation) {
+        E::operation_input(ctx, operation);
+    }
+
+    fn inferred_early_responses(
+        ctx: &mut GenContext,
+        operation: &mut Operation,
+    ) -> Vec<(Option<aide::openapi::StatusCode>, Response)> {
+        E::inferred_early_responses(ctx, operation)
+    }
+}
+
+fn invalid(errors: ValidationErrors) -> AppError {
+    AppError::Invalid(field_errors(&errors))
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct FieldError {
+    /// Path of the field, e.g. `tags[2]`
+    pub field: String,
+    /// Broken constraint, e.g. `length`
+    pub code: String,
+    pub message: String,
+}
+
+/// Flattens `errors`, nested structs and lists included, sorted by field.
+pub fn field_errors(errors: &ValidationErrors) -> Vec<FieldError> {
+    let mut out = Vec::new();
+    flatten("", errors, &mut out);
+    out.sort_by(|a, b| a.field.cmp(&b.field));
+    out
+}
+
+fn flatten(prefix: &str, errors: &ValidationErrors, out: &mut Vec<FieldError>) {
+    for (field, kind) in errors.errors() {
+        let path = match prefix {
+            "" => field.to_string(),
+            _ => format!("{}.{}", prefix, field),
+        };
+        match kind {
+            ValidationErrorsKind::Field(errors) => {
+                out.extend(errors.iter().map(|err| FieldError {
+                    field: path.clone(),
+                    code: err.code.to_string(),
+                    message: message(err),
+                }));
+            }
+            ValidationErrorsKind::Struct(errors) => flatten(&path, errors, out),
+            ValidationErrorsKind::List(items) => {
+                for (i, errors) in items {
+                    flatten(&format!("{}[{}]", path, i), errors, out);
+                }
+            }
+        }
+    }
+}
+
+/// The message set on the constraint, or one made from its code and bounds.
+fn message(err: &ValidationError) -> String {
+    if let Some(message) = &err.message {
+        return message.to_string();
+    }
+    let param = |name: &str| {
+        err.params
+            .get(name)
+            .filter(|v| !v.is_null())
+            .map(Value::to_string)
+    };
+    let bounds = match (param("min"), param("max")) {
+        (Some(min), Some(max)) => format!("within {}..={}", min, max),
+        (Some(min), None) => format!("at least {}", min),
+        (None, Some(max)) => format!("at most {}", max),
+        (None, None) => String::new(),
+    };
+    match err.code.as_ref() {
+        "length" => format!("length must be {}", bounds),
+        "range" => format!("must be {}", bounds),
+        "email" => "must be an email address".to_string(),
+        "url" => "must be a URL".to_string(),
+        "regex" => "has an invalid format".to_string(),
+        "required" => "is required".to_string(),
+        code => format!("fails `{}`", code),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Validate)]
+    struct Item {
+        #[validate(length(min = 1, max = 3))]
+        name: String,
+    }
+
+    #[derive(Validate)]
+    struct Body {
+        #[validate(range(min = 1))]
+        n: i64,
+        #[validate(email)]
+        email: String,
+        #[validate(length(min = 2, message = "needs two letters"))]
+        code: String,
+        #[validate(nested)]
+        items: Vec<Item>,
+        #[validate(nested)]
+        owner: Item,
+    }
+
+    fn errors(body: Body) -> Vec<(String, String, String)> {
+        field_errors(&body.validate().unwrap_err())
+            .into_iter()
+            .map(|err| (err.field, err.code, err.message))
+            .collect()
+    }
+
+    fn item(name: &str) -> Item {
+        Item {
+            name: name.to_string(),
+        }
+    }
+
+    fn error(field: &str, code: &str, message: &str) -> (String, String, String) {
+        (field.to_string(), code.to_string(), message.to_string())
+    }
+
+    #[test]
+    fn field_errors_name_each_broken_constraint_sorted_by_field() {
+        let body = Body {
+            n: 0,
+            email: "nope".to_string(),
+            code: "a".to_string(),
+            items: vec![item("ok"), item("")],
+            owner: item("toolong"),
+        };
+        assert_eq!(
+            errors(body),
+            vec![
+                error("code", "length", "needs two letters"),
+                error("email", "email", "must be an email address"),
+                error("items[1].name", "length", "length must be within 1..=3"),
+                error("n", "range", "must be at least 1"),
+                error("owner.name", "length", "length must be within 1..=3"),
+            ]
+        );
+    }
+
+    #[test]
+    fn field_errors_is_empty_for_a_valid_body() {
+        let body = Body {
+            n: 1,
+            email: "a@b.io".to_string(),
+            code: "ab".to_string(),
+            items: vec![item("a")],
+            owner: item("abc"),
+        };
+        assert!(body.validate().is_ok());
+        assert!(field_errors(&ValidationErrors::new()).is_empty());
+    }
+}
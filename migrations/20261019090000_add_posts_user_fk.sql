@@ -0,0 +1,27 @@
+-- Add migration script here
+-- Posts of users that no longer exist would block the foreign key.
+-- They are moved to `orphaned_posts`, to give back to their authors or drop by hand.
+CREATE TABLE orphaned_posts AS
+SELECT * FROM posts
+WHERE NOT EXISTS (SELECT 1 FROM users WHERE users.id = posts.user_id);
+
+DO $$
+DECLARE
+    orphans BIGINT;
+BEGIN
+    DELETE FROM posts WHERE id IN (SELECT id FROM orphaned_posts);
+    GET DIAGNOSTICS orphans = ROW_COUNT;
+    IF orphans > 0 THEN
+        RAISE NOTICE 'Moved % post(s) of missing users to orphaned_posts', orphans;
+    END IF;
+END $$;
+
+-- sqlx runs this file in one transaction, so writes to posts wait until it commits
+ALTER TABLE posts
+ADD CONSTRAINT posts_user_id_fkey
+FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE;
+
+CREATE INDEX posts_user_id_idx ON posts (user_id);
+
+-- Left behind by the former BIGSERIAL type of `user_id`
+DROP SEQUENCE IF EXISTS posts_user_id_seq;
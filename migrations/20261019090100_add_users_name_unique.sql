@@ -0,0 +1,12 @@
+-- Add migration script here
+-- The oldest user keeps a shared name, the others get their id appended, e.g. `ann#12`.
+UPDATE users
+SET name = users.name || '#' || users.id
+FROM (
+    SELECT id, row_number() OVER (PARTITION BY name ORDER BY id) AS rank
+    FROM users
+) AS ranked
+WHERE ranked.id = users.id AND ranked.rank > 1;
+
+ALTER TABLE users
+ADD CONSTRAINT users_name_key UNIQUE (name);
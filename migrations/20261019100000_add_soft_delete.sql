@@ -0,0 +1,11 @@
+-- Add migration script here
+ALTER TABLE users ADD COLUMN deleted_at TIMESTAMPTZ;
+ALTER TABLE posts ADD COLUMN deleted_at TIMESTAMPTZ;
+
+-- Background purge looks rows up by deletion time
+CREATE INDEX users_deleted_at_idx ON users (deleted_at) WHERE deleted_at IS NOT NULL;
+CREATE INDEX posts_deleted_at_idx ON posts (deleted_at) WHERE deleted_at IS NOT NULL;
+
+-- Names of deleted users are free again, restoring one fails while a live user holds its name
+ALTER TABLE users DROP CONSTRAINT users_name_key;
+CREATE UNIQUE INDEX users_name_key ON users (name) WHERE deleted_at IS NULL;
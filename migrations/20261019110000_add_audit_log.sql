@@ -0,0 +1,29 @@
+-- Add migration script here
+CREATE TABLE audit_log (
+    id BIGSERIAL PRIMARY KEY,
+    actor TEXT NOT NULL,
+    table_name TEXT NOT NULL,
+    row_id BIGINT NOT NULL,
+    action TEXT NOT NULL,
+    -- Only the columns that changed
+    before JSONB,
+    after JSONB,
+    request_id TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX audit_log_created_at_idx ON audit_log (created_at);
+CREATE INDEX audit_log_actor_idx ON audit_log (actor, created_at);
+CREATE INDEX audit_log_table_name_idx ON audit_log (table_name, created_at);
+
+-- Keys of `a` whose value differs in `b`, with their value in `a`
+CREATE FUNCTION jsonb_changed(a JSONB, b JSONB) RETURNS JSONB AS $$
+    SELECT CASE
+        WHEN a IS NULL THEN NULL
+        WHEN b IS NULL THEN a
+        ELSE (
+            SELECT COALESCE(jsonb_object_agg(key, value), '{}'::jsonb)
+            FROM jsonb_each(a)
+            WHERE b -> key IS DISTINCT FROM value
+        )
+    END
+$$ LANGUAGE sql IMMUTABLE;
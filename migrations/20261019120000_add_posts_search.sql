@@ -0,0 +1,6 @@
+-- Add migration script here
+-- `simple` keeps words as written, so posts in any language are searchable.
+ALTER TABLE posts ADD COLUMN search TSVECTOR GENERATED ALWAYS AS (
+    setweight(to_tsvector('simple', title), 'A') || setweight(to_tsvector('simple', content), 'B')
+) STORED;
+CREATE INDEX posts_search_idx ON posts USING GIN (search);
@@ -0,0 +1,4 @@
+-- Add migration script here
+-- Rendered and sanitized `content`, written by the application with the post.
+-- NULL for posts written before, they are rendered when read.
+ALTER TABLE posts ADD COLUMN content_html TEXT;
@@ -0,0 +1,32 @@
+-- Add migration script here
+CREATE TABLE post_revisions (
+    id BIGSERIAL PRIMARY KEY,
+    post_id BIGINT NOT NULL REFERENCES posts (id) ON DELETE CASCADE,
+    -- 1 for the first version of the post, incremented on every edit
+    rev INT NOT NULL,
+    title TEXT NOT NULL,
+    content TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (post_id, rev)
+);
+
+-- Current version of existing posts
+INSERT INTO post_revisions (post_id, rev, title, content, created_at)
+SELECT id, 1, title, content, updated_at FROM posts;
+
+-- The latest revision of a post always matches the post itself
+CREATE OR REPLACE FUNCTION record_post_revision() RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'UPDATE' AND OLD.title = NEW.title AND OLD.content = NEW.content THEN
+        RETURN NULL;
+    END IF;
+    INSERT INTO post_revisions (post_id, rev, title, content)
+    SELECT NEW.id, COALESCE(max(rev), 0) + 1, NEW.title, NEW.content
+    FROM post_revisions WHERE post_id = NEW.id;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER posts_record_revision
+AFTER INSERT OR UPDATE OF title, content ON posts
+FOR EACH ROW EXECUTE FUNCTION record_post_revision();
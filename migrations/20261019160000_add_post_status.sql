@@ -0,0 +1,15 @@
+-- Add migration script here
+-- Existing posts were all public, so they start out published.
+ALTER TABLE posts
+ADD COLUMN status TEXT NOT NULL DEFAULT 'published'
+    CHECK (status IN ('draft', 'scheduled', 'published', 'archived')),
+ADD COLUMN publish_at TIMESTAMPTZ,
+ADD CONSTRAINT posts_scheduled_publish_at CHECK (status <> 'scheduled' OR publish_at IS NOT NULL);
+
+-- Backfilled without touching `updated_at`, the posts did not change
+ALTER TABLE posts DISABLE TRIGGER posts_set_updated_at;
+UPDATE posts SET publish_at = created_at;
+ALTER TABLE posts ENABLE TRIGGER posts_set_updated_at;
+
+-- Due posts, looked up by the publish scheduler
+CREATE INDEX posts_scheduled_idx ON posts (publish_at) WHERE status = 'scheduled';
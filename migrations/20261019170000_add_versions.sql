@@ -0,0 +1,32 @@
+-- Add migration script here
+-- Incremented on every update, answered as the `ETag` of the row
+CREATE OR REPLACE FUNCTION bump_version() RETURNS TRIGGER AS $$
+BEGIN
+    NEW.version = OLD.version + 1;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+ALTER TABLE users ADD COLUMN version BIGINT NOT NULL DEFAULT 1;
+ALTER TABLE posts ADD COLUMN version BIGINT NOT NULL DEFAULT 1;
+
+CREATE TRIGGER users_bump_version
+BEFORE UPDATE ON users
+FOR EACH ROW EXECUTE FUNCTION bump_version();
+
+CREATE TRIGGER posts_bump_version
+BEFORE UPDATE ON posts
+FOR EACH ROW EXECUTE FUNCTION bump_version();
+
+-- Tags are part of a post, so tagging changes its version as well
+CREATE OR REPLACE FUNCTION touch_tagged_post() RETURNS TRIGGER AS $$
+BEGIN
+    UPDATE posts SET updated_at = now()
+    WHERE id = CASE TG_OP WHEN 'DELETE' THEN OLD.post_id ELSE NEW.post_id END;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER post_tags_touch_post
+AFTER INSERT OR DELETE ON post_tags
+FOR EACH ROW EXECUTE FUNCTION touch_tagged_post();
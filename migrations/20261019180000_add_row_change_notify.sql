@@ -0,0 +1,35 @@
+-- Add migration script here
+-- Announces every audited write to users and posts on the `row_changes` channel.
+-- Notifications are delivered on commit only, to every listening instance.
+-- Payloads stay small (NOTIFY caps them at 8000 bytes), listeners read rows back when they need them.
+CREATE FUNCTION notify_row_change() RETURNS TRIGGER AS $$
+DECLARE
+    is_post BOOLEAN := NEW.table_name IN ('posts', 'post_tags');
+BEGIN
+    IF NEW.table_name NOT IN ('users', 'posts', 'post_tags') THEN
+        RETURN NULL;
+    END IF;
+    PERFORM pg_notify('row_changes', json_build_object(
+        'id', NEW.id,
+        'table', NEW.table_name,
+        'row_id', NEW.row_id,
+        'action', NEW.action,
+        'changed', (SELECT COALESCE(jsonb_agg(key), '[]'::jsonb)
+                    FROM jsonb_object_keys(COALESCE(NEW.after, NEW.before)) AS key),
+        -- `post_tags` rows are logged under the id of their post
+        'user_id', CASE WHEN is_post
+            THEN COALESCE((SELECT user_id FROM posts WHERE id = NEW.row_id),
+                          (NEW.before->>'user_id')::BIGINT)
+            ELSE NEW.row_id END,
+        'status', CASE WHEN is_post
+            THEN COALESCE((SELECT status FROM posts WHERE id = NEW.row_id),
+                          NEW.before->>'status') END,
+        'at', NEW.created_at
+    )::text);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_log_notify
+AFTER INSERT ON audit_log
+FOR EACH ROW EXECUTE FUNCTION notify_row_change();
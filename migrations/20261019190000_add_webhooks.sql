@@ -0,0 +1,108 @@
+-- Add migration script here
+-- Webhooks registered by integrators, and their deliveries.
+-- Deliveries are queued by a trigger on `audit_log`, so in the transaction of the write they announce.
+CREATE TABLE webhooks (
+    id BIGSERIAL PRIMARY KEY,
+    url TEXT NOT NULL,
+    -- Key of the HMAC-SHA256 signature of every delivery
+    secret TEXT NOT NULL,
+    -- Names of the events sent, e.g. `post.published`
+    events TEXT[] NOT NULL,
+    active BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TRIGGER webhooks_set_updated_at
+BEFORE UPDATE ON webhooks
+FOR EACH ROW EXECUTE FUNCTION set_updated_at();
+
+CREATE TABLE webhook_deliveries (
+    id BIGSERIAL PRIMARY KEY,
+    webhook_id BIGINT NOT NULL REFERENCES webhooks (id) ON DELETE CASCADE,
+    event TEXT NOT NULL,
+    payload JSONB NOT NULL,
+    -- `dead` once every attempt failed, until retried by hand
+    status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'delivered', 'dead')),
+    attempts INT NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    delivered_at TIMESTAMPTZ
+);
+-- Due deliveries, looked up by the sender
+CREATE INDEX webhook_deliveries_due_idx ON webhook_deliveries (next_attempt_at) WHERE status = 'pending';
+CREATE INDEX webhook_deliveries_webhook_id_idx ON webhook_deliveries (webhook_id, id);
+
+CREATE TABLE webhook_attempts (
+    id BIGSERIAL PRIMARY KEY,
+    delivery_id BIGINT NOT NULL REFERENCES webhook_deliveries (id) ON DELETE CASCADE,
+    attempt INT NOT NULL,
+    -- `NULL` when no response came back, see `error`
+    response_status INT,
+    -- First KiB of the response
+    response_body TEXT,
+    error TEXT,
+    duration_ms INT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX webhook_attempts_delivery_id_idx ON webhook_attempts (delivery_id, attempt);
+
+-- Events announced by an `audit_log` entry, hard deletes announce none
+CREATE FUNCTION audit_events(table_name TEXT, action TEXT, before JSONB, after JSONB)
+RETURNS TEXT[] AS $$
+    SELECT CASE
+        WHEN table_name = 'users' THEN CASE
+            WHEN action = 'insert' THEN ARRAY['user.created']
+            -- Purged, deleted already unless it was live
+            WHEN action = 'delete' AND before->>'deleted_at' IS NULL THEN ARRAY['user.deleted']
+            WHEN action = 'delete' THEN ARRAY[]::TEXT[]
+            WHEN after ? 'deleted_at' AND after->>'deleted_at' IS NOT NULL THEN ARRAY['user.deleted']
+            WHEN after ? 'deleted_at' THEN ARRAY['user.restored']
+            ELSE ARRAY['user.updated']
+        END
+        WHEN table_name = 'posts' THEN CASE
+            WHEN action = 'insert' AND after->>'status' = 'published' THEN ARRAY['post.created', 'post.published']
+            WHEN action = 'insert' THEN ARRAY['post.created']
+            WHEN action = 'delete' AND before->>'deleted_at' IS NULL THEN ARRAY['post.deleted']
+            WHEN action = 'delete' THEN ARRAY[]::TEXT[]
+            WHEN after ? 'deleted_at' AND after->>'deleted_at' IS NOT NULL THEN ARRAY['post.deleted']
+            WHEN after ? 'deleted_at' THEN ARRAY['post.restored']
+            WHEN after->>'status' = 'published' THEN ARRAY['post.published']
+            ELSE ARRAY['post.updated']
+        END
+        -- Tags of the post `row_id`
+        WHEN table_name = 'post_tags' THEN ARRAY['post.updated']
+        ELSE ARRAY[]::TEXT[]
+    END
+$$ LANGUAGE sql IMMUTABLE;
+
+CREATE FUNCTION enqueue_webhook_deliveries() RETURNS TRIGGER AS $$
+DECLARE
+    events TEXT[] := audit_events(NEW.table_name, NEW.action, NEW.before, NEW.after);
+    data JSONB;
+BEGIN
+    IF cardinality(events) = 0 THEN
+        RETURN NULL;
+    END IF;
+    data := CASE WHEN NEW.table_name = 'users'
+        THEN jsonb_build_object('user_id', NEW.row_id)
+        ELSE jsonb_build_object(
+            'post_id', NEW.row_id,
+            'user_id', COALESCE((SELECT user_id FROM posts WHERE id = NEW.row_id),
+                                (NEW.before->>'user_id')::BIGINT))
+    END || jsonb_build_object('changes', COALESCE(NEW.after, '{}'::jsonb));
+    INSERT INTO webhook_deliveries (webhook_id, event, payload)
+    SELECT webhooks.id, event, jsonb_build_object(
+        'event', event,
+        'occurred_at', NEW.created_at,
+        'data', data
+    )
+    FROM unnest(events) AS event
+    JOIN webhooks ON webhooks.active AND event = ANY (webhooks.events);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_log_webhooks
+AFTER INSERT ON audit_log
+FOR EACH ROW EXECUTE FUNCTION enqueue_webhook_deliveries();
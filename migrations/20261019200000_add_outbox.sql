@@ -0,0 +1,56 @@
+-- Add migration script here
+-- Events of audited writes are written to `outbox` in the transaction of the write,
+-- then relayed to webhooks and live events by `tasks/outbox.rs`.
+-- Replaces the triggers that queued deliveries and notified `row_changes` directly.
+DROP TRIGGER audit_log_webhooks ON audit_log;
+DROP FUNCTION enqueue_webhook_deliveries();
+DROP TRIGGER audit_log_notify ON audit_log;
+DROP FUNCTION notify_row_change();
+
+CREATE TABLE outbox (
+    id BIGSERIAL PRIMARY KEY,
+    event TEXT NOT NULL,
+    -- Body of the webhook deliveries
+    payload JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    -- Set once every sink has the event
+    published_at TIMESTAMPTZ
+);
+-- Pending events, read in order by the relay
+CREATE INDEX outbox_pending_idx ON outbox (id) WHERE published_at IS NULL;
+CREATE INDEX outbox_published_at_idx ON outbox (published_at);
+
+CREATE FUNCTION write_outbox() RETURNS TRIGGER AS $$
+DECLARE
+    events TEXT[] := audit_events(NEW.table_name, NEW.action, NEW.before, NEW.after);
+    data JSONB;
+BEGIN
+    IF cardinality(events) = 0 THEN
+        RETURN NULL;
+    END IF;
+    IF NEW.table_name = 'users' THEN
+        data := jsonb_build_object('user_id', NEW.row_id);
+    ELSE
+        -- `post_tags` rows are logged under the id of their post
+        SELECT jsonb_build_object(
+            'post_id', NEW.row_id,
+            'user_id', COALESCE(posts.user_id, (NEW.before->>'user_id')::BIGINT),
+            'status', COALESCE(posts.status, NEW.before->>'status')
+        )
+        INTO data
+        FROM (SELECT NEW.row_id AS id) AS row
+        LEFT JOIN posts ON posts.id = row.id;
+    END IF;
+    data := data || jsonb_build_object('changes', COALESCE(NEW.after, '{}'::jsonb));
+    INSERT INTO outbox (event, payload)
+    SELECT event, jsonb_build_object('event', event, 'occurred_at', NEW.created_at, 'data', data)
+    FROM unnest(events) AS event;
+    -- Wakes the relay once the transaction commits, repeats are folded into one
+    PERFORM pg_notify('outbox', '');
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_log_outbox
+AFTER INSERT ON audit_log
+FOR EACH ROW EXECUTE FUNCTION write_outbox();
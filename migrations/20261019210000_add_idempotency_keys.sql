@@ -0,0 +1,19 @@
+-- Add migration script here
+-- Responses of `POST` requests sent with `Idempotency-Key`, replayed to retries (see `idempotency.rs`).
+CREATE TABLE idempotency_keys (
+    -- `admin` or `anonymous`, so keys are scoped by the request as well
+    actor TEXT NOT NULL,
+    key TEXT NOT NULL,
+    -- sha256 of the method, path and body of the request
+    fingerprint BYTEA NOT NULL,
+    -- NULL while the first request is running
+    status SMALLINT,
+    -- `[name, value]` pairs
+    headers JSONB,
+    body BYTEA,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    -- End of the lease of a running request, renewed while it runs, or of the replays once answered
+    expires_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (actor, key, fingerprint)
+);
+CREATE INDEX idempotency_keys_expires_at_idx ON idempotency_keys (expires_at);
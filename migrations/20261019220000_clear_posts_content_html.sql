@@ -0,0 +1,8 @@
+-- Add migration script here
+-- Stored HTML predates the `user-content-` prefix of ids, posts are rendered again when read.
+-- Neither `updated_at` nor the version move, the posts did not change.
+ALTER TABLE posts DISABLE TRIGGER posts_set_updated_at;
+ALTER TABLE posts DISABLE TRIGGER posts_bump_version;
+UPDATE posts SET content_html = NULL WHERE content_html IS NOT NULL;
+ALTER TABLE posts ENABLE TRIGGER posts_bump_version;
+ALTER TABLE posts ENABLE TRIGGER posts_set_updated_at;